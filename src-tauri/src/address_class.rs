@@ -0,0 +1,299 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cidr::Cidr;
+
+/// What kind of address an IP is, per the IANA special-purpose address
+/// registries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressClass {
+    /// Globally reachable unicast
+    Public,
+    /// RFC 1918 private-use IPv4
+    Private,
+    /// Shared address space behind carrier-grade NAT, 100.64.0.0/10
+    Cgnat,
+    /// IPv6 unique-local, fc00::/7
+    UniqueLocal,
+    LinkLocal,
+    Loopback,
+    Multicast,
+    Broadcast,
+    Unspecified,
+    /// Example ranges such as 192.0.2.0/24 and 2001:db8::/32
+    Documentation,
+    /// Benchmarking ranges such as 198.18.0.0/15
+    Benchmarking,
+    /// 6to4 and Teredo, whose addresses stand in for a tunnel endpoint
+    Tunnel,
+    /// Anything else set aside and not globally reachable
+    Reserved,
+}
+
+use AddressClass::*;
+
+impl AddressClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Public => "public",
+            Private => "private",
+            Cgnat => "cgnat",
+            UniqueLocal => "unique_local",
+            LinkLocal => "link_local",
+            Loopback => "loopback",
+            Multicast => "multicast",
+            Broadcast => "broadcast",
+            Unspecified => "unspecified",
+            Documentation => "documentation",
+            Benchmarking => "benchmarking",
+            Tunnel => "tunnel",
+            Reserved => "reserved",
+        }
+    }
+
+    /// Worth a geolocation lookup
+    pub fn is_public(&self) -> bool {
+        *self == Public
+    }
+
+    /// A peer on a local or carrier network, recorded only when asked for
+    pub fn is_lan(&self) -> bool {
+        matches!(self, Private | Cgnat | UniqueLocal | LinkLocal)
+    }
+}
+
+const fn v4(a: u8, b: u8, c: u8, d: u8, prefix: u8) -> Cidr {
+    Cidr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), prefix)
+}
+
+const fn v6(segments: [u16; 8], prefix: u8) -> Cidr {
+    let [a, b, c, d, e, f, g, h] = segments;
+    Cidr::new(IpAddr::V6(Ipv6Addr::new(a, b, c, d, e, f, g, h)), prefix)
+}
+
+/// IANA IPv4 Special-Purpose Address Registry plus multicast and the
+/// reserved class E block. The longest matching prefix wins, so globally
+/// reachable entries inside a reserved block are listed as `Public`.
+const V4_RANGES: &[(Cidr, AddressClass)] = &[
+    (v4(0, 0, 0, 0, 8), Reserved),
+    (v4(0, 0, 0, 0, 32), Unspecified),
+    (v4(10, 0, 0, 0, 8), Private),
+    (v4(100, 64, 0, 0, 10), Cgnat),
+    (v4(127, 0, 0, 0, 8), Loopback),
+    (v4(169, 254, 0, 0, 16), LinkLocal),
+    (v4(172, 16, 0, 0, 12), Private),
+    (v4(192, 0, 0, 0, 24), Reserved),
+    (v4(192, 0, 0, 9, 32), Public),  // PCP anycast
+    (v4(192, 0, 0, 10, 32), Public), // TURN anycast
+    (v4(192, 0, 2, 0, 24), Documentation),
+    (v4(192, 88, 99, 0, 24), Tunnel), // deprecated 6to4 relay anycast
+    (v4(192, 168, 0, 0, 16), Private),
+    (v4(198, 18, 0, 0, 15), Benchmarking),
+    (v4(198, 51, 100, 0, 24), Documentation),
+    (v4(203, 0, 113, 0, 24), Documentation),
+    (v4(224, 0, 0, 0, 4), Multicast),
+    (v4(240, 0, 0, 0, 4), Reserved),
+    (v4(255, 255, 255, 255, 32), Broadcast),
+];
+
+/// IANA IPv6 Special-Purpose Address Registry on top of the address space
+/// registry, where only 2000::/3 is allocated global unicast. IPv4-mapped
+/// addresses are classified by the IPv4 address they carry.
+const V6_RANGES: &[(Cidr, AddressClass)] = &[
+    (v6([0, 0, 0, 0, 0, 0, 0, 0], 0), Reserved),
+    (v6([0, 0, 0, 0, 0, 0, 0, 0], 128), Unspecified),
+    (v6([0, 0, 0, 0, 0, 0, 0, 1], 128), Loopback),
+    (v6([0x64, 0xff9b, 0, 0, 0, 0, 0, 0], 96), Public), // NAT64 well-known prefix
+    (v6([0x64, 0xff9b, 1, 0, 0, 0, 0, 0], 48), Reserved),
+    (v6([0x100, 0, 0, 0, 0, 0, 0, 0], 64), Reserved), // discard-only
+    (v6([0x2000, 0, 0, 0, 0, 0, 0, 0], 3), Public),
+    (v6([0x2001, 0, 0, 0, 0, 0, 0, 0], 23), Reserved),
+    (v6([0x2001, 0, 0, 0, 0, 0, 0, 0], 32), Tunnel), // Teredo
+    (v6([0x2001, 1, 0, 0, 0, 0, 0, 1], 128), Public), // PCP anycast
+    (v6([0x2001, 1, 0, 0, 0, 0, 0, 2], 128), Public), // TURN anycast
+    (v6([0x2001, 1, 0, 0, 0, 0, 0, 3], 128), Public), // DNS-SD SRP anycast
+    (v6([0x2001, 2, 0, 0, 0, 0, 0, 0], 48), Benchmarking),
+    (v6([0x2001, 3, 0, 0, 0, 0, 0, 0], 32), Public), // AMT
+    (v6([0x2001, 4, 0x112, 0, 0, 0, 0, 0], 48), Public), // AS112-v6
+    (v6([0x2001, 0x20, 0, 0, 0, 0, 0, 0], 28), Public), // ORCHIDv2
+    (v6([0x2001, 0x30, 0, 0, 0, 0, 0, 0], 28), Public), // drone remote ID
+    (v6([0x2001, 0xdb8, 0, 0, 0, 0, 0, 0], 32), Documentation),
+    (v6([0x2002, 0, 0, 0, 0, 0, 0, 0], 16), Tunnel), // 6to4
+    (v6([0x3fff, 0, 0, 0, 0, 0, 0, 0], 20), Documentation),
+    (v6([0x5f00, 0, 0, 0, 0, 0, 0, 0], 16), Reserved), // SRv6 SIDs
+    (v6([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7), UniqueLocal),
+    (v6([0xfe80, 0, 0, 0, 0, 0, 0, 0], 10), LinkLocal),
+    (v6([0xff00, 0, 0, 0, 0, 0, 0, 0], 8), Multicast),
+];
+
+pub fn classify(ip: &IpAddr) -> AddressClass {
+    let (ip, table) = match ip {
+        IpAddr::V4(_) => (*ip, V4_RANGES),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => (IpAddr::V4(v4), V4_RANGES),
+            None => (*ip, V6_RANGES),
+        },
+    };
+    table
+        .iter()
+        .filter(|(range, _)| range.contains(&ip))
+        .max_by_key(|(range, _)| range.prefix())
+        .map_or(Public, |(_, class)| *class)
+}
+
+/// Same as `classify`, for addresses stored as text
+pub fn classify_str(ip: &str) -> Option<AddressClass> {
+    ip.parse::<IpAddr>().ok().map(|ip| classify(&ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(cases: &[(&str, AddressClass)]) {
+        for (ip, expected) in cases {
+            assert_eq!(classify_str(ip), Some(*expected), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn ipv4_special_purpose_ranges() {
+        check(&[
+            ("0.0.0.0", Unspecified),
+            ("0.1.2.3", Reserved),
+            ("10.0.0.1", Private),
+            ("10.255.255.255", Private),
+            ("100.63.255.255", Public),
+            ("100.64.0.1", Cgnat),
+            ("100.127.255.255", Cgnat),
+            ("100.128.0.0", Public),
+            ("127.0.0.1", Loopback),
+            ("127.255.0.1", Loopback),
+            ("169.254.1.1", LinkLocal),
+            ("172.15.255.255", Public),
+            ("172.16.0.1", Private),
+            ("172.31.255.255", Private),
+            ("172.32.0.0", Public),
+            ("192.0.0.1", Reserved),
+            ("192.0.0.8", Reserved),
+            ("192.0.0.9", Public),
+            ("192.0.0.10", Public),
+            ("192.0.0.170", Reserved),
+            ("192.0.2.55", Documentation),
+            ("192.88.99.1", Tunnel),
+            ("192.168.1.1", Private),
+            ("198.17.255.255", Public),
+            ("198.18.0.1", Benchmarking),
+            ("198.19.255.255", Benchmarking),
+            ("198.20.0.0", Public),
+            ("198.51.100.7", Documentation),
+            ("203.0.113.200", Documentation),
+            ("224.0.0.251", Multicast),
+            ("239.255.255.250", Multicast),
+            ("240.0.0.1", Reserved),
+            ("255.255.255.254", Reserved),
+            ("255.255.255.255", Broadcast),
+        ]);
+    }
+
+    #[test]
+    fn ipv4_public_addresses() {
+        check(&[
+            ("1.1.1.1", Public),
+            ("8.8.8.8", Public),
+            ("93.184.216.34", Public),
+            ("192.31.196.1", Public),
+            ("223.255.255.255", Public),
+        ]);
+    }
+
+    #[test]
+    fn ipv6_special_purpose_ranges() {
+        check(&[
+            ("::", Unspecified),
+            ("::1", Loopback),
+            ("::2", Reserved),
+            ("64:ff9b::808:808", Public),
+            ("64:ff9b:1::1", Reserved),
+            ("100::1", Reserved),
+            ("2001::1", Tunnel),
+            ("2001:0:4136:e378::1", Tunnel),
+            ("2001:1::1", Public),
+            ("2001:1::3", Public),
+            ("2001:1::4", Reserved),
+            ("2001:2::1", Benchmarking),
+            ("2001:3::1", Public),
+            ("2001:4:112::1", Public),
+            ("2001:10::1", Reserved),
+            ("2001:20::1", Public),
+            ("2001:db8::1", Documentation),
+            ("2002:c000:204::1", Tunnel),
+            ("3fff::1", Documentation),
+            ("3fff:fff::1", Documentation),
+            ("3fff:1000::1", Public),
+            ("5f00::1", Reserved),
+            ("fc00::1", UniqueLocal),
+            ("fd12:3456:789a::1", UniqueLocal),
+            ("fe80::1", LinkLocal),
+            ("febf::1", LinkLocal),
+            ("fec0::1", Reserved),
+            ("ff02::fb", Multicast),
+        ]);
+    }
+
+    #[test]
+    fn ipv6_public_addresses() {
+        check(&[
+            ("2001:4860:4860::8888", Public),
+            ("2606:4700:4700::1111", Public),
+            ("2a00:1450:4001::200e", Public),
+            ("3ffe::1", Public),
+        ]);
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_use_the_ipv4_class() {
+        check(&[
+            ("::ffff:8.8.8.8", Public),
+            ("::ffff:192.168.0.1", Private),
+            ("::ffff:127.0.0.1", Loopback),
+            ("::ffff:203.0.113.1", Documentation),
+        ]);
+    }
+
+    #[test]
+    fn lan_and_public_groupings() {
+        let cases = [
+            (Public, true, false),
+            (Private, false, true),
+            (Cgnat, false, true),
+            (UniqueLocal, false, true),
+            (LinkLocal, false, true),
+            (Loopback, false, false),
+            (Multicast, false, false),
+            (Documentation, false, false),
+            (Tunnel, false, false),
+            (Reserved, false, false),
+        ];
+        for (class, public, lan) in cases {
+            assert_eq!(class.is_public(), public, "{:?}", class);
+            assert_eq!(class.is_lan(), lan, "{:?}", class);
+        }
+    }
+
+    #[test]
+    fn names_match_serde() {
+        for class in [Public, Cgnat, UniqueLocal, LinkLocal, Documentation, Tunnel] {
+            let json = serde_json::to_string(&class).unwrap();
+            assert_eq!(json, format!("\"{}\"", class.as_str()));
+        }
+    }
+
+    #[test]
+    fn unparseable_text_has_no_class() {
+        assert_eq!(classify_str("not an ip"), None);
+        assert_eq!(classify_str(""), None);
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network such as `10.0.0.0/8` or `2606:4700::/32`.
+/// A bare address parses as a single-host network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// A network from parts known to be valid, e.g. for built-in tables
+    pub const fn new(network: IpAddr, prefix: u8) -> Self {
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        assert!(prefix <= max, "prefix longer than the address");
+        Self { network, prefix }
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = mask_u32(self.prefix);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = mask_u128(self.prefix);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            // IPv4-mapped IPv6 addresses belong to their IPv4 network
+            (IpAddr::V4(_), IpAddr::V6(ip)) => {
+                ip.to_ipv4_mapped().is_some_and(|v4| self.contains(&IpAddr::V4(v4)))
+            }
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+
+    /// Same as `contains`, for addresses stored as text
+    pub fn contains_str(&self, ip: &str) -> bool {
+        ip.parse::<IpAddr>().is_ok_and(|ip| self.contains(&ip))
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("Invalid address in {}", s))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("Invalid prefix length in {}", s))?,
+            None => max,
+        };
+
+        Ok(Self { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn mask_u32(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn mask_u128(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(network: &str, cases: &[(&str, bool)]) {
+        let cidr: Cidr = network.parse().unwrap();
+        for (ip, expected) in cases {
+            assert_eq!(cidr.contains_str(ip), *expected, "{} in {}", ip, network);
+        }
+    }
+
+    #[test]
+    fn ipv4_networks() {
+        check("10.0.0.0/8", &[
+            ("10.0.0.0", true),
+            ("10.255.255.255", true),
+            ("9.255.255.255", false),
+            ("11.0.0.0", false),
+        ]);
+        check("192.168.1.0/24", &[("192.168.1.77", true), ("192.168.2.1", false)]);
+        check("203.0.113.7/32", &[("203.0.113.7", true), ("203.0.113.6", false), ("203.0.113.8", false)]);
+        check("203.0.113.7", &[("203.0.113.7", true), ("203.0.113.6", false)]);
+        check("0.0.0.0/0", &[("0.0.0.0", true), ("255.255.255.255", true), ("2001:db8::1", false)]);
+        // Host bits are ignored
+        check("10.1.2.3/8", &[("10.9.9.9", true), ("11.1.2.3", false)]);
+        check("172.16.0.0/12", &[("172.31.255.255", true), ("172.32.0.0", false), ("172.15.255.255", false)]);
+    }
+
+    #[test]
+    fn ipv6_networks() {
+        check("2606:4700::/32", &[
+            ("2606:4700::1111", true),
+            ("2606:4700:ffff:ffff:ffff:ffff:ffff:ffff", true),
+            ("2606:4701::", false),
+            ("1.1.1.1", false),
+        ]);
+        check("2001:db8::1/128", &[("2001:db8::1", true), ("2001:db8::2", false)]);
+        check("2001:db8::1", &[("2001:db8::1", true), ("2001:db8::", false)]);
+        check("::/0", &[("::", true), ("ffff::1", true), ("::ffff:1.2.3.4", true), ("1.2.3.4", false)]);
+        check("2001:db8:ffff::/33", &[("2001:db8:8000::", true), ("2001:db8:7fff::", false)]);
+    }
+
+    #[test]
+    fn ipv4_mapped_ipv6_addresses_match_ipv4_networks() {
+        check("1.1.1.0/24", &[
+            ("::ffff:1.1.1.1", true),
+            ("::ffff:1.1.2.1", false),
+            // IPv4-compatible and NAT64 addresses are not the IPv4 address
+            ("::1.1.1.1", false),
+            ("64:ff9b::1.1.1.1", false),
+        ]);
+        check("0.0.0.0/0", &[("::ffff:8.8.8.8", true), ("::1", false)]);
+        check("::ffff:0:0/96", &[("::ffff:1.1.1.1", true), ("1.1.1.1", false)]);
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        let cases = [
+            ("10.0.0.0/8", "10.0.0.0/8", 8),
+            (" 1.1.1.1 ", "1.1.1.1/32", 32),
+            ("2606:4700::/32", "2606:4700::/32", 32),
+            ("2001:db8::1", "2001:db8::1/128", 128),
+            ("0.0.0.0/0", "0.0.0.0/0", 0),
+            ("::/0", "::/0", 0),
+        ];
+        for (input, display, prefix) in cases {
+            let cidr: Cidr = input.parse().unwrap();
+            assert_eq!((cidr.to_string().as_str(), cidr.prefix()), (display, prefix), "{}", input);
+            assert_eq!(cidr.to_string().parse::<Cidr>(), Ok(cidr), "{} round trip", input);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_networks() {
+        for input in [
+            "", "/8", "10.0.0.0/", "10.0.0.0/33", "2001:db8::/129", "10.0.0.0/-1", "10.0.0.0/8/8",
+            "10.0.0.0/ 8", "10.0.0/8", "10.0.0.256", "example.com", "2001:db8::g/32", "10.0.0.0/0x8",
+        ] {
+            assert!(input.parse::<Cidr>().is_err(), "{:?} parsed", input);
+        }
+        assert_eq!("10.0.0.0/33".parse::<Cidr>(), Err("Invalid prefix length in 10.0.0.0/33".to_string()));
+        assert_eq!("nope/8".parse::<Cidr>(), Err("Invalid address in nope/8".to_string()));
+    }
+
+    #[test]
+    fn masks_cover_every_prefix_length() {
+        assert_eq!(mask_u32(0), 0);
+        assert_eq!(mask_u32(1), 0x8000_0000);
+        assert_eq!(mask_u32(24), 0xFFFF_FF00);
+        assert_eq!(mask_u32(32), u32::MAX);
+        assert_eq!(mask_u128(0), 0);
+        assert_eq!(mask_u128(64), u128::MAX << 64);
+        assert_eq!(mask_u128(128), u128::MAX);
+    }
+}
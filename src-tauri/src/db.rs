@@ -1,8 +1,233 @@
-use rusqlite::{Connection, params};
+use chrono::{DateTime, Utc};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use rusqlite::{Connection, params, params_from_iter};
 use serde::{Serialize, Deserialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
+use crate::address_class;
+use crate::cidr::Cidr;
+use crate::geolocator::{GeoCacheEntry, IpInfoResponse};
+use crate::migrations;
+use crate::process::FlowProcess;
+use crate::rdns::RdnsCacheEntry;
+use crate::sniffer::{DnsAnswer, FlowTraffic, IpTraffic, ServerNameSeen, FLOW_IDLE_TIMEOUT};
+
+/// How many port/protocol pairs to attach to each `IpConnection`
+const TOP_PORTS_PER_IP: usize = 5;
+/// How many recently resolved domain names to attach to each `IpConnection`
+const DOMAINS_PER_IP: usize = 3;
+/// How many recently requested server names to attach to each `IpConnection`
+const SERVER_NAMES_PER_IP: usize = 3;
+/// How many capture interfaces to attach to each `IpConnection`
+const INTERFACES_PER_IP: usize = 8;
+/// How many remote IPs to list under each `ProcessUsage`
+const IPS_PER_PROCESS: usize = 20;
+
+/// Resolution of freshly recorded time-series buckets
+pub const MINUTE_BUCKET_SECS: i64 = 60;
+/// Resolution minute buckets are rolled up into once they age out
+pub const HOUR_BUCKET_SECS: i64 = 3600;
+
+/// Page size for `query_connections` when none is given
+const DEFAULT_PAGE_SIZE: usize = 500;
+
+/// Most series `get_timeseries` returns when no keys are given
+const DEFAULT_TIMESERIES_LIMIT: usize = 10;
+/// Roughly how many points per series an automatic step aims for
+const TIMESERIES_TARGET_POINTS: i64 = 300;
+
+/// Start (unix seconds) of the `width`-second bucket holding `ts`
+pub fn bucket_start(ts: DateTime<Utc>, width: i64) -> i64 {
+    let secs = ts.timestamp();
+    secs - secs.rem_euclid(width)
+}
+
+/// File name used inside a data directory
+pub const DB_FILE_NAME: &str = "snifff.db";
+
+/// Where the database path came from, so the frontend knows whether its own
+/// saved location should take over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbPathSource {
+    Default,
+    Env,
+    Cli,
+    Runtime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseInfo {
+    pub path: String,
+    pub source: DbPathSource,
+}
+
+/// Resolve the database file: `--db <file>` or `--data-dir <dir>` on the
+/// command line, then `SNIFFF_DB_PATH` or `SNIFFF_DATA_DIR`, then
+/// `snifff.db` inside the app data directory.
+pub fn resolve_db_path(app_data_dir: &Path) -> (PathBuf, DbPathSource) {
+    let args: Vec<String> = std::env::args().collect();
+    let flag = |name: &str| {
+        args.iter().enumerate().find_map(|(i, arg)| {
+            arg.strip_prefix(&format!("{}=", name))
+                .map(str::to_string)
+                .or_else(|| (arg == name).then(|| args.get(i + 1).cloned()).flatten())
+        })
+    };
+
+    if let Some(path) = flag("--db") {
+        return (PathBuf::from(path), DbPathSource::Cli);
+    }
+    if let Some(dir) = flag("--data-dir") {
+        return (PathBuf::from(dir).join(DB_FILE_NAME), DbPathSource::Cli);
+    }
+    if let Some(path) = std::env::var_os("SNIFFF_DB_PATH").filter(|p| !p.is_empty()) {
+        return (PathBuf::from(path), DbPathSource::Env);
+    }
+    if let Some(dir) = std::env::var_os("SNIFFF_DATA_DIR").filter(|p| !p.is_empty()) {
+        return (PathBuf::from(dir).join(DB_FILE_NAME), DbPathSource::Env);
+    }
+    (app_data_dir.join(DB_FILE_NAME), DbPathSource::Default)
+}
+
+/// Column to order connections by
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionSort {
+    #[default]
+    LastSeen,
+    FirstSeen,
+    HitCount,
+    PacketCount,
+    ByteCount,
+    Ip,
+    Asn,
+    Country,
+}
+
+impl ConnectionSort {
+    /// SQL expression to sort by; never NULL, so keyset cursors compare cleanly
+    fn sql(&self) -> &'static str {
+        match self {
+            ConnectionSort::LastSeen => "last_seen",
+            ConnectionSort::FirstSeen => "first_seen",
+            ConnectionSort::HitCount => "hit_count",
+            ConnectionSort::PacketCount => "packet_count",
+            ConnectionSort::ByteCount => "byte_count",
+            ConnectionSort::Ip => "ip",
+            ConnectionSort::Asn => "COALESCE(asn, '')",
+            ConnectionSort::Country => "COALESCE(country_code, '')",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Filters, ordering and paging for `query_connections`. Every filter is optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ConnectionQuery {
+    /// Case-insensitive substring of IP, ASN, AS name, country or country code
+    pub search: Option<String>,
+    /// Single address or CIDR block, e.g. `1.1.1.0/24`
+    pub ip: Option<String>,
+    /// ASN or AS name, matched exactly
+    pub asn: Option<String>,
+    pub country_code: Option<String>,
+    pub continent_code: Option<String>,
+    /// Only connections seen on this capture interface
+    pub interface: Option<String>,
+    /// RFC 3339 bounds; a connection matches if it was active at any point between them
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// Minimum `hit_count`. That counts the capture sessions an IP was seen
+    /// in, not its packets; sort by `packet_count` to rank by traffic.
+    pub min_hits: Option<u64>,
+    pub sort: ConnectionSort,
+    pub order: SortOrder,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// `next_cursor` from a previous page; takes precedence over `offset`
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionPage {
+    pub connections: Vec<IpConnection>,
+    /// Connections matching the filters across all pages
+    pub total: u64,
+    /// Pass back as `cursor` for the next page; absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// What `purge` deletes. Connections matching the IP/ASN/country filters and
+/// active only within the time range are removed with all their flows and
+/// time-series history; with a time range, flows and history inside it are
+/// removed for every matching IP as well.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PurgeFilter {
+    /// RFC 3339 bounds of the range to delete
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// Single address or CIDR block
+    pub ip: Option<String>,
+    /// ASN or AS name
+    pub asn: Option<String>,
+    pub country_code: Option<String>,
+}
+
+impl PurgeFilter {
+    fn is_empty(&self) -> bool {
+        [&self.since, &self.until, &self.ip, &self.asn, &self.country_code]
+            .iter()
+            .all(|f| f.as_deref().is_none_or(|v| v.trim().is_empty()))
+    }
+}
+
+/// Rows brought in by `merge_from`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MergeCounts {
+    /// Connections in the source
+    pub connections: u64,
+    /// Of those, IPs this database had never seen
+    pub new_connections: u64,
+    pub flows: u64,
+    pub buckets: u64,
+}
+
+/// Rows removed by a purge or retention run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PurgeResult {
+    pub connections: u64,
+    pub flows: u64,
+    pub buckets: u64,
+    /// How much smaller the file got after vacuuming
+    pub bytes_reclaimed: u64,
+}
+
+impl PurgeResult {
+    pub fn is_empty(&self) -> bool {
+        self.connections == 0 && self.flows == 0 && self.buckets == 0
+    }
+
+    pub fn add(&mut self, other: PurgeResult) {
+        self.connections += other.connections;
+        self.flows += other.flows;
+        self.buckets += other.buckets;
+        self.bytes_reclaimed += other.bytes_reclaimed;
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IpConnection {
     pub ip: String,
@@ -14,8 +239,111 @@ pub struct IpConnection {
     pub continent_code: Option<String>,
     pub continent: Option<String>,
     pub hit_count: u64,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// "inbound" or "outbound" — direction of the first packet recorded for this IP
+    pub direction: Option<String>,
     pub first_seen: String,
     pub last_seen: String,
+    /// Comma-separated labels of the hosts this row was merged in from;
+    /// `None` if it was only ever captured here
+    #[serde(default)]
+    pub source_host: Option<String>,
+    /// Reverse DNS name, if the lookup found one
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// `AddressClass` of the IP, e.g. "public" or "private"
+    #[serde(default)]
+    pub ip_class: Option<String>,
+    /// Names recently looked up that resolved to this IP, newest first
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Names clients recently asked this IP for via TLS SNI, QUIC or the
+    /// HTTP `Host` header, newest first
+    #[serde(default)]
+    pub server_names: Vec<String>,
+    /// Capture interfaces this IP was seen on, most recent first
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    /// Busiest service ports for this IP, by packet count
+    #[serde(default)]
+    pub top_ports: Vec<PortUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortUsage {
+    pub protocol: String,
+    pub port: u16,
+    pub flow_count: u64,
+    pub packet_count: u64,
+    pub byte_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowRecord {
+    pub src_ip: String,
+    pub src_port: u16,
+    pub dst_ip: String,
+    pub dst_port: u16,
+    pub protocol: String,
+    pub remote_ip: String,
+    pub direction: String,
+    pub tcp_flags: u8,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub start_time: String,
+    pub end_time: String,
+    /// Server name the client asked for on this flow, if one was seen
+    #[serde(default)]
+    pub server_name: Option<String>,
+    /// Local process that owned the socket, where it could be found
+    #[serde(default)]
+    pub pid: Option<u32>,
+    #[serde(default)]
+    pub process_name: Option<String>,
+    #[serde(default)]
+    pub exe_path: Option<String>,
+    /// Capture interface the flow was seen on; `None` if imported from a file
+    #[serde(default)]
+    pub interface: Option<String>,
+    /// Kind of tunnel the flow was decapsulated from, e.g. "gre" or "vxlan"
+    #[serde(default)]
+    pub tunnel: Option<String>,
+    /// Remote end of that tunnel
+    #[serde(default)]
+    pub tunnel_endpoint: Option<String>,
+}
+
+/// Traffic one program exchanged with a set of connections
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessUsage {
+    pub process_name: String,
+    pub exe_path: Option<String>,
+    /// Every pid the program's flows were seen under
+    pub pids: Vec<u32>,
+    pub flow_count: u64,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub last_seen: String,
+    /// Busiest remote IPs first
+    pub connections: Vec<ProcessConnection>,
+}
+
+/// One remote IP a program talked to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessConnection {
+    pub ip: String,
+    pub country_code: Option<String>,
+    pub country: Option<String>,
+    pub as_name: Option<String>,
+    pub flow_count: u64,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub last_seen: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,74 +351,564 @@ pub struct CountryStats {
     pub country_code: String,
     pub country: String,
     pub hit_count: u64,
+    pub packet_count: u64,
+    pub byte_count: u64,
     pub unique_ips: u64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotalStats {
+    pub total_ips: u64,
+    pub total_hits: u64,
+    pub total_packets: u64,
+    pub total_bytes: u64,
+    pub total_countries: u64,
+}
+
+/// What to group a time series by
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSeriesGroup {
+    Ip,
+    Asn,
+    Country,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimeSeriesQuery {
+    /// RFC 3339 bounds, start inclusive and end exclusive
+    pub start: String,
+    pub end: String,
+    pub group_by: TimeSeriesGroup,
+    /// Only these IPs/ASNs/country codes; otherwise the busiest `limit`
+    #[serde(default)]
+    pub keys: Option<Vec<String>>,
+    /// Point spacing in seconds; picked from the range when omitted
+    #[serde(default)]
+    pub step_secs: Option<i64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimeSeriesPoint {
+    pub time: String,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimeSeries {
+    pub key: String,
+    /// AS name or country name, when grouped by those
+    pub label: Option<String>,
+    pub total_packets: u64,
+    pub total_bytes: u64,
+    pub points: Vec<TimeSeriesPoint>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimeSeriesResult {
+    pub step_secs: i64,
+    pub series: Vec<TimeSeries>,
+}
+
 pub struct Database {
     conn: Mutex<Connection>,
+    info: Mutex<DatabaseInfo>,
 }
 
 impl Database {
-    pub fn new() -> Result<Self, String> {
-        let db_path = Self::get_db_path()?;
+    pub fn open(path: &Path, source: DbPathSource) -> Result<Self, String> {
+        let conn = Self::connect(path)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            info: Mutex::new(DatabaseInfo {
+                path: path.display().to_string(),
+                source,
+            }),
+        })
+    }
+
+    /// Switch to another database file, creating it if needed. Everything
+    /// holding this `Database` sees the new file from the next call on.
+    pub fn switch_to(&self, path: &Path) -> Result<DatabaseInfo, String> {
+        // Open first so a bad path leaves the current database in place
+        let new_conn = Self::connect(path)?;
+
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let mut info = self.info.lock().map_err(|e| e.to_string())?;
+        *conn = new_conn;
+        *info = DatabaseInfo {
+            path: path.display().to_string(),
+            source: DbPathSource::Runtime,
+        };
+        Ok(info.clone())
+    }
+
+    pub fn info(&self) -> Result<DatabaseInfo, String> {
+        Ok(self.info.lock().map_err(|e| e.to_string())?.clone())
+    }
 
+    fn connect(db_path: &Path) -> Result<Connection, String> {
         // Ensure parent directory exists
         if let Some(parent) = db_path.parent() {
             std::fs::create_dir_all(parent)
                 .map_err(|e| format!("Failed to create db directory: {}", e))?;
         }
 
-        let conn = Connection::open(&db_path)
-            .map_err(|e| format!("Failed to open database: {}", e))?;
+        let mut conn = Connection::open(db_path)
+            .map_err(|e| format!("Failed to open database {}: {}", db_path.display(), e))?;
 
-        // Create tables
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS connections (
-                ip TEXT PRIMARY KEY,
-                asn TEXT,
-                as_name TEXT,
-                as_domain TEXT,
-                country_code TEXT,
-                country TEXT,
-                continent_code TEXT,
-                continent TEXT,
-                hit_count INTEGER DEFAULT 1,
-                first_seen TEXT NOT NULL,
-                last_seen TEXT NOT NULL
-            );
-            CREATE INDEX IF NOT EXISTS idx_country_code ON connections(country_code);
-            CREATE INDEX IF NOT EXISTS idx_last_seen ON connections(last_seen);
-            "
-        ).map_err(|e| format!("Failed to create tables: {}", e))?;
+        migrations::migrate(&mut conn, db_path)?;
 
-        Ok(Self { conn: Mutex::new(conn) })
-    }
+        // ip_in_cidr(ip, cidr) for CIDR filters; false on anything unparseable
+        conn.create_scalar_function(
+            "ip_in_cidr",
+            2,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let ip = ctx.get::<String>(0)?;
+                let cidr = ctx.get::<String>(1)?;
+                Ok(cidr.parse::<Cidr>().is_ok_and(|c| c.contains_str(&ip)))
+            },
+        ).map_err(|e| format!("Failed to register ip_in_cidr: {}", e))?;
+
+        // ip_class(ip) for storing the address class; NULL if unparseable
+        conn.create_scalar_function(
+            "ip_class",
+            1,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let ip = ctx.get::<String>(0)?;
+                Ok(address_class::classify_str(&ip).map(|c| c.as_str()))
+            },
+        ).map_err(|e| format!("Failed to register ip_class: {}", e))?;
 
-    fn get_db_path() -> Result<PathBuf, String> {
-        let home = std::env::var("HOME")
-            .map_err(|_| "HOME not set".to_string())?;
-        Ok(PathBuf::from(home)
-            .join("Library/Application Support/com.snifff.app")
-            .join("snifff.db"))
+        // Rows recorded before the column existed
+        conn.execute("UPDATE connections SET ip_class = ip_class(ip) WHERE ip_class IS NULL", [])
+            .map_err(|e| format!("Failed to classify addresses: {}", e))?;
+
+        Ok(conn)
     }
 
-    /// Upsert a connection — insert or increment hit_count
-    pub fn upsert_connection(&self, ip: &str) -> Result<(), String> {
+    /// Upsert a connection — insert or increment hit_count.
+    /// `seen_at` is the packet time, which for imported captures may be in the past.
+    pub fn upsert_connection(&self, ip: &str, seen_at: DateTime<Utc>) -> Result<(), String> {
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
-        let now = chrono::Utc::now().to_rfc3339();
+        let seen_at = seen_at.to_rfc3339();
 
         conn.execute(
-            "INSERT INTO connections (ip, hit_count, first_seen, last_seen)
-             VALUES (?1, 1, ?2, ?2)
+            "INSERT INTO connections (ip, ip_class, hit_count, first_seen, last_seen)
+             VALUES (?1, ip_class(?1), 1, ?2, ?2)
              ON CONFLICT(ip) DO UPDATE SET
                 hit_count = hit_count + 1,
-                last_seen = ?2",
-            params![ip, now],
+                first_seen = MIN(first_seen, ?2),
+                last_seen = MAX(last_seen, ?2)",
+            params![ip, seen_at],
         ).map_err(|e| format!("Failed to upsert connection: {}", e))?;
 
         Ok(())
     }
 
+    /// Add a batch of per-IP packet/byte totals from the sniffer, captured on
+    /// `interface` if it came from a live capture
+    pub fn record_traffic(&self, batch: &[IpTraffic], interface: Option<&str>) -> Result<(), String> {
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO connections (ip, ip_class, hit_count, packet_count, byte_count,
+                                          packets_in, packets_out, bytes_in, bytes_out,
+                                          direction, first_seen, last_seen)
+                 VALUES (?1, ip_class(?1), 1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT(ip) DO UPDATE SET
+                    packet_count = packet_count + excluded.packet_count,
+                    byte_count = byte_count + excluded.byte_count,
+                    packets_in = packets_in + excluded.packets_in,
+                    packets_out = packets_out + excluded.packets_out,
+                    bytes_in = bytes_in + excluded.bytes_in,
+                    bytes_out = bytes_out + excluded.bytes_out,
+                    direction = COALESCE(direction, excluded.direction),
+                    first_seen = MIN(first_seen, excluded.first_seen),
+                    last_seen = MAX(last_seen, excluded.last_seen)"
+            ).map_err(|e| format!("Failed to prepare traffic upsert: {}", e))?;
+
+            let mut bucket_stmt = tx.prepare(
+                "INSERT INTO traffic_buckets (ip, resolution, bucket_start,
+                                              packets_in, packets_out, bytes_in, bytes_out)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT(ip, resolution, bucket_start) DO UPDATE SET
+                    packets_in = packets_in + excluded.packets_in,
+                    packets_out = packets_out + excluded.packets_out,
+                    bytes_in = bytes_in + excluded.bytes_in,
+                    bytes_out = bytes_out + excluded.bytes_out"
+            ).map_err(|e| format!("Failed to prepare bucket upsert: {}", e))?;
+
+            let mut interface_stmt = tx.prepare(
+                "INSERT INTO connection_interfaces (ip, interface, packet_count, byte_count,
+                                                   first_seen, last_seen)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT(ip, interface) DO UPDATE SET
+                    packet_count = packet_count + excluded.packet_count,
+                    byte_count = byte_count + excluded.byte_count,
+                    first_seen = MIN(first_seen, excluded.first_seen),
+                    last_seen = MAX(last_seen, excluded.last_seen)"
+            ).map_err(|e| format!("Failed to prepare interface upsert: {}", e))?;
+
+            for t in batch {
+                bucket_stmt.execute(params![
+                    t.ip,
+                    MINUTE_BUCKET_SECS,
+                    bucket_start(t.first_seen, MINUTE_BUCKET_SECS),
+                    t.packets_in as i64,
+                    t.packets_out as i64,
+                    t.bytes_in as i64,
+                    t.bytes_out as i64,
+                ]).map_err(|e| format!("Failed to record time series for {}: {}", t.ip, e))?;
+
+                stmt.execute(params![
+                    t.ip,
+                    t.packets() as i64,
+                    t.bytes() as i64,
+                    t.packets_in as i64,
+                    t.packets_out as i64,
+                    t.bytes_in as i64,
+                    t.bytes_out as i64,
+                    t.direction.as_str(),
+                    t.first_seen.to_rfc3339(),
+                    t.last_seen.to_rfc3339(),
+                ]).map_err(|e| format!("Failed to record traffic for {}: {}", t.ip, e))?;
+
+                if let Some(interface) = interface {
+                    interface_stmt.execute(params![
+                        t.ip,
+                        interface,
+                        t.packets() as i64,
+                        t.bytes() as i64,
+                        t.first_seen.to_rfc3339(),
+                        t.last_seen.to_rfc3339(),
+                    ]).map_err(|e| format!("Failed to record interface for {}: {}", t.ip, e))?;
+                }
+            }
+        }
+
+        tx.commit().map_err(|e| format!("Failed to commit traffic: {}", e))?;
+        Ok(())
+    }
+
+    /// Fold minute buckets older than `older_than` into hourly ones. The
+    /// cutoff is rounded down to the hour so no hour is split across both.
+    pub fn rollup_timeseries(&self, older_than: DateTime<Utc>) -> Result<usize, String> {
+        let cutoff = bucket_start(older_than, HOUR_BUCKET_SECS);
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        tx.execute(
+            "INSERT INTO traffic_buckets (ip, resolution, bucket_start,
+                                          packets_in, packets_out, bytes_in, bytes_out)
+             SELECT ip, ?1, bucket_start - (bucket_start % ?1),
+                    SUM(packets_in), SUM(packets_out), SUM(bytes_in), SUM(bytes_out)
+             FROM traffic_buckets
+             WHERE resolution = ?2 AND bucket_start < ?3
+             GROUP BY ip, bucket_start - (bucket_start % ?1)
+             ON CONFLICT(ip, resolution, bucket_start) DO UPDATE SET
+                packets_in = packets_in + excluded.packets_in,
+                packets_out = packets_out + excluded.packets_out,
+                bytes_in = bytes_in + excluded.bytes_in,
+                bytes_out = bytes_out + excluded.bytes_out",
+            params![HOUR_BUCKET_SECS, MINUTE_BUCKET_SECS, cutoff],
+        ).map_err(|e| format!("Failed to roll up time series: {}", e))?;
+
+        let removed = tx.execute(
+            "DELETE FROM traffic_buckets WHERE resolution = ?1 AND bucket_start < ?2",
+            params![MINUTE_BUCKET_SECS, cutoff],
+        ).map_err(|e| format!("Failed to prune minute buckets: {}", e))?;
+
+        tx.commit().map_err(|e| format!("Failed to commit rollup: {}", e))?;
+        Ok(removed)
+    }
+
+    /// Traffic over time for the busiest (or the given) IPs, ASNs or countries.
+    /// Minute and hourly buckets are both read, so a step finer than an hour
+    /// shows rolled-up history as one point per hour.
+    pub fn get_timeseries(&self, query: &TimeSeriesQuery) -> Result<TimeSeriesResult, String> {
+        let start = DateTime::parse_from_rfc3339(&query.start)
+            .map_err(|e| format!("Invalid start time: {}", e))?
+            .timestamp();
+        let end = DateTime::parse_from_rfc3339(&query.end)
+            .map_err(|e| format!("Invalid end time: {}", e))?
+            .timestamp();
+        if end <= start {
+            return Err("End time must be after start time".to_string());
+        }
+
+        let step = query
+            .step_secs
+            .filter(|s| *s > 0)
+            .unwrap_or_else(|| auto_step(end - start));
+
+        let (key_expr, label_expr) = match query.group_by {
+            TimeSeriesGroup::Ip => ("b.ip", "NULL"),
+            TimeSeriesGroup::Asn => ("COALESCE(c.asn, 'unknown')", "MAX(c.as_name)"),
+            TimeSeriesGroup::Country => ("COALESCE(c.country_code, 'unknown')", "MAX(c.country)"),
+        };
+
+        let keys = query.keys.clone().unwrap_or_default();
+        let key_filter = if keys.is_empty() {
+            String::new()
+        } else {
+            format!(
+                " AND {} IN ({})",
+                key_expr,
+                (0..keys.len()).map(|i| format!("?{}", i + 4)).collect::<Vec<_>>().join(", ")
+            )
+        };
+
+        let sql = format!(
+            "SELECT {key} AS series_key, {label},
+                    b.bucket_start - (b.bucket_start % ?3) AS t,
+                    SUM(b.packets_in), SUM(b.packets_out), SUM(b.bytes_in), SUM(b.bytes_out)
+             FROM traffic_buckets b
+             LEFT JOIN connections c ON c.ip = b.ip
+             WHERE b.bucket_start >= ?1 AND b.bucket_start < ?2{filter}
+             GROUP BY series_key, t
+             ORDER BY t",
+            key = key_expr,
+            label = label_expr,
+            filter = key_filter,
+        );
+
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare(&sql)
+            .map_err(|e| format!("Failed to prepare time series query: {}", e))?;
+
+        let mut args: Vec<rusqlite::types::Value> = vec![start.into(), end.into(), step.into()];
+        args.extend(keys.iter().cloned().map(rusqlite::types::Value::from));
+
+        let rows = stmt.query_map(rusqlite::params_from_iter(args), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, i64>(5)?,
+                row.get::<_, i64>(6)?,
+            ))
+        }).map_err(|e| format!("Failed to query time series: {}", e))?;
+
+        let mut by_key: HashMap<String, TimeSeries> = HashMap::new();
+        for row in rows {
+            let (key, label, t, packets_in, packets_out, bytes_in, bytes_out) =
+                row.map_err(|e| format!("Failed to read time series row: {}", e))?;
+            let series = by_key.entry(key.clone()).or_insert_with(|| TimeSeries {
+                key,
+                label: None,
+                total_packets: 0,
+                total_bytes: 0,
+                points: Vec::new(),
+            });
+            if label.is_some() {
+                series.label = label;
+            }
+            let time = DateTime::from_timestamp(t, 0).unwrap_or_default().to_rfc3339();
+            series.total_packets += (packets_in + packets_out) as u64;
+            series.total_bytes += (bytes_in + bytes_out) as u64;
+            series.points.push(TimeSeriesPoint {
+                time,
+                packets_in: packets_in as u64,
+                packets_out: packets_out as u64,
+                bytes_in: bytes_in as u64,
+                bytes_out: bytes_out as u64,
+            });
+        }
+
+        let mut series: Vec<TimeSeries> = by_key.into_values().collect();
+        series.sort_by(|a, b| b.total_bytes.cmp(&a.total_bytes).then_with(|| a.key.cmp(&b.key)));
+        if keys.is_empty() {
+            series.truncate(query.limit.unwrap_or(DEFAULT_TIMESERIES_LIMIT));
+        }
+
+        Ok(TimeSeriesResult { step_secs: step, series })
+    }
+
+    /// Add a batch of per-flow totals from the sniffer. A flow continues the
+    /// latest row for its 5-tuple, interface and tunnel unless that row has
+    /// been idle for longer than `FLOW_IDLE_TIMEOUT`, in which case it is a
+    /// new connection reusing the tuple and gets a row of its own.
+    pub fn record_flows(&self, batch: &[FlowTraffic], interface: Option<&str>) -> Result<(), String> {
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        {
+            let mut update = tx.prepare(
+                "UPDATE flows SET
+                    tcp_flags = tcp_flags | ?8,
+                    packet_count = packet_count + ?9,
+                    byte_count = byte_count + ?10,
+                    start_time = MIN(start_time, ?11),
+                    end_time = MAX(end_time, ?12)
+                 WHERE id = (SELECT id FROM flows
+                             WHERE src_ip = ?1 AND src_port = ?2 AND dst_ip = ?3
+                               AND dst_port = ?4 AND protocol = ?5 AND interface IS ?13
+                               AND tunnel IS ?14 AND tunnel_endpoint IS ?15
+                               AND end_time >= ?16
+                             ORDER BY end_time DESC LIMIT 1)"
+            ).map_err(|e| format!("Failed to prepare flow update: {}", e))?;
+            let mut insert = tx.prepare(
+                "INSERT INTO flows (src_ip, src_port, dst_ip, dst_port, protocol, remote_ip,
+                                    direction, tcp_flags, packet_count, byte_count,
+                                    start_time, end_time, interface, tunnel, tunnel_endpoint)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)"
+            ).map_err(|e| format!("Failed to prepare flow insert: {}", e))?;
+
+            for f in batch {
+                let values = params![
+                    f.src_ip,
+                    f.src_port,
+                    f.dst_ip,
+                    f.dst_port,
+                    f.protocol,
+                    f.remote_ip,
+                    f.direction.as_str(),
+                    f.tcp_flags,
+                    f.packets as i64,
+                    f.bytes as i64,
+                    f.start_time.to_rfc3339(),
+                    f.end_time.to_rfc3339(),
+                    interface,
+                    f.tunnel.map(|t| t.as_str()),
+                    f.tunnel_endpoint,
+                    (f.start_time - FLOW_IDLE_TIMEOUT).to_rfc3339(),
+                ];
+                let updated = update.execute(values)
+                    .map_err(|e| format!("Failed to record flow for {}: {}", f.remote_ip, e))?;
+                if updated == 0 {
+                    insert.execute(&values[..15])
+                        .map_err(|e| format!("Failed to record flow for {}: {}", f.remote_ip, e))?;
+                }
+            }
+        }
+
+        tx.commit().map_err(|e| format!("Failed to commit flows: {}", e))?;
+        Ok(())
+    }
+
+    /// Tag already recorded flows with the process that owned them: the
+    /// latest row for the 5-tuple on each interface and tunnel it was seen on
+    pub fn record_flow_processes(&self, attributed: &[FlowProcess]) -> Result<(), String> {
+        if attributed.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        {
+            let mut stmt = tx.prepare(
+                "UPDATE flows SET pid = ?6, process_name = ?7, exe_path = ?8
+                 WHERE id IN (SELECT id FROM (
+                                  SELECT id, ROW_NUMBER() OVER (
+                                      PARTITION BY interface, tunnel, tunnel_endpoint
+                                      ORDER BY end_time DESC
+                                  ) AS latest
+                                  FROM flows
+                                  WHERE src_ip = ?1 AND src_port = ?2 AND dst_ip = ?3
+                                    AND dst_port = ?4 AND protocol = ?5)
+                              WHERE latest = 1)"
+            ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+
+            for f in attributed {
+                stmt.execute(params![
+                    f.src_ip,
+                    f.src_port,
+                    f.dst_ip,
+                    f.dst_port,
+                    f.protocol,
+                    f.process.pid,
+                    f.process.name,
+                    f.process.exe_path,
+                ]).map_err(|e| format!("Failed to record process for {}:{}: {}", f.src_ip, f.src_port, e))?;
+            }
+        }
+
+        tx.commit().map_err(|e| format!("Failed to commit flow processes: {}", e))?;
+        Ok(())
+    }
+
+    /// Record domain → address mappings seen in DNS responses
+    pub fn record_dns(&self, answers: &[DnsAnswer]) -> Result<(), String> {
+        if answers.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO dns_answers (ip, domain, ttl, first_seen, last_seen)
+                 VALUES (?1, ?2, ?3, ?4, ?4)
+                 ON CONFLICT(ip, domain) DO UPDATE SET
+                    ttl = excluded.ttl,
+                    first_seen = MIN(first_seen, excluded.first_seen),
+                    last_seen = MAX(last_seen, excluded.last_seen)"
+            ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+
+            for answer in answers {
+                stmt.execute(params![
+                    answer.ip,
+                    answer.domain,
+                    answer.ttl,
+                    answer.seen_at.to_rfc3339(),
+                ]).map_err(|e| format!("Failed to record DNS answer for {}: {}", answer.domain, e))?;
+            }
+        }
+
+        tx.commit().map_err(|e| format!("Failed to commit DNS answers: {}", e))?;
+        Ok(())
+    }
+
+    /// Record server names clients asked for, one per flow
+    pub fn record_server_names(&self, names: &[ServerNameSeen]) -> Result<(), String> {
+        if names.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO server_names (src_ip, src_port, dst_ip, dst_port, protocol, ip,
+                                           name, source, first_seen, last_seen)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?3, ?6, ?7, ?8, ?8)
+                 ON CONFLICT(src_ip, src_port, dst_ip, dst_port, protocol) DO UPDATE SET
+                    name = excluded.name,
+                    source = excluded.source,
+                    first_seen = MIN(first_seen, excluded.first_seen),
+                    last_seen = MAX(last_seen, excluded.last_seen)"
+            ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+
+            for n in names {
+                stmt.execute(params![
+                    n.src_ip,
+                    n.src_port,
+                    n.dst_ip,
+                    n.dst_port,
+                    n.protocol,
+                    n.name,
+                    n.source.as_str(),
+                    n.seen_at.to_rfc3339(),
+                ]).map_err(|e| format!("Failed to record server name for {}: {}", n.dst_ip, e))?;
+            }
+        }
+
+        tx.commit().map_err(|e| format!("Failed to commit server names: {}", e))?;
+        Ok(())
+    }
+
     /// Update geo info for an IP
     pub fn update_geo_info(
         &self,
@@ -123,58 +941,696 @@ impl Database {
 
     /// Get all connections ordered by last_seen
     pub fn get_connections(&self, limit: usize) -> Result<Vec<IpConnection>, String> {
+        let query = ConnectionQuery {
+            limit: Some(limit),
+            ..Default::default()
+        };
+        Ok(self.query_connections(&query)?.connections)
+    }
+
+    /// One page of connections matching `query`, plus the total match count
+    pub fn query_connections(&self, query: &ConnectionQuery) -> Result<ConnectionPage, String> {
+        self.query_page(query, true)
+    }
+
+    /// Like `query_connections`, but skips counting, which costs a full scan
+    /// per page when walking a large table
+    pub fn query_page_uncounted(&self, query: &ConnectionQuery) -> Result<ConnectionPage, String> {
+        self.query_page(query, false)
+    }
+
+    fn query_page(&self, query: &ConnectionQuery, with_total: bool) -> Result<ConnectionPage, String> {
+        let (conditions, mut args) = connection_filter(query)?;
+
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
 
-        let mut stmt = conn.prepare(
+        let total: i64 = if with_total {
+            conn.query_row(
+                &format!("SELECT COUNT(*) FROM connections {}", filter),
+                params_from_iter(args.iter()),
+                |row| row.get(0),
+            ).map_err(|e| format!("Failed to count connections: {}", e))?
+        } else {
+            0
+        };
+
+        // Keyset pagination: continue strictly after the last row of the previous page
+        let sort = query.sort.sql();
+        let (dir, cmp) = match query.order {
+            SortOrder::Asc => ("ASC", ">"),
+            SortOrder::Desc => ("DESC", "<"),
+        };
+        let mut page_conditions = conditions.clone();
+        let mut offset = query.offset.unwrap_or(0);
+        if let Some(cursor) = query.cursor.as_deref() {
+            let (value, ip) = decode_cursor(cursor)?;
+            args.push(value);
+            args.push(ip.into());
+            page_conditions.push(format!(
+                "({sort}, ip) {cmp} (?{}, ?{})",
+                args.len() - 1,
+                args.len(),
+                sort = sort,
+                cmp = cmp
+            ));
+            offset = 0;
+        }
+        let page_filter = if page_conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", page_conditions.join(" AND "))
+        };
+
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        args.push((limit as i64).into());
+        args.push((offset as i64).into());
+
+        let mut stmt = conn.prepare(&format!(
             "SELECT ip, asn, as_name, as_domain, country_code, country,
-                    continent_code, continent, hit_count, first_seen, last_seen
+                    continent_code, continent, hit_count, packet_count, byte_count,
+                    packets_in, packets_out, bytes_in, bytes_out, direction,
+                    first_seen, last_seen, source_host, hostname, ip_class, {sort}
              FROM connections
-             ORDER BY last_seen DESC
-             LIMIT ?1"
-        ).map_err(|e| format!("Failed to prepare query: {}", e))?;
+             {filter}
+             ORDER BY {sort} {dir}, ip {dir}
+             LIMIT ?{} OFFSET ?{}",
+            args.len() - 1,
+            args.len(),
+            sort = sort,
+            filter = page_filter,
+            dir = dir,
+        )).map_err(|e| format!("Failed to prepare query: {}", e))?;
 
-        let rows = stmt.query_map(params![limit as i64], |row| {
-            Ok(IpConnection {
-                ip: row.get(0)?,
-                asn: row.get(1)?,
-                as_name: row.get(2)?,
-                as_domain: row.get(3)?,
-                country_code: row.get(4)?,
-                country: row.get(5)?,
-                continent_code: row.get(6)?,
-                continent: row.get(7)?,
-                hit_count: row.get::<_, i64>(8)? as u64,
-                first_seen: row.get(9)?,
-                last_seen: row.get(10)?,
-            })
+        let rows = stmt.query_map(params_from_iter(args.iter()), |row| {
+            Ok((
+                IpConnection {
+                    ip: row.get(0)?,
+                    asn: row.get(1)?,
+                    as_name: row.get(2)?,
+                    as_domain: row.get(3)?,
+                    country_code: row.get(4)?,
+                    country: row.get(5)?,
+                    continent_code: row.get(6)?,
+                    continent: row.get(7)?,
+                    hit_count: row.get::<_, i64>(8)? as u64,
+                    packet_count: row.get::<_, i64>(9)? as u64,
+                    byte_count: row.get::<_, i64>(10)? as u64,
+                    packets_in: row.get::<_, i64>(11)? as u64,
+                    packets_out: row.get::<_, i64>(12)? as u64,
+                    bytes_in: row.get::<_, i64>(13)? as u64,
+                    bytes_out: row.get::<_, i64>(14)? as u64,
+                    direction: row.get(15)?,
+                    first_seen: row.get(16)?,
+                    last_seen: row.get(17)?,
+                    source_host: row.get(18)?,
+                    hostname: row.get(19)?,
+                    ip_class: row.get(20)?,
+                    domains: Vec::new(),
+                    server_names: Vec::new(),
+                    interfaces: Vec::new(),
+                    top_ports: Vec::new(),
+                },
+                row.get::<_, Value>(21)?,
+            ))
         }).map_err(|e| format!("Failed to query connections: {}", e))?;
 
         let mut connections = Vec::new();
+        let mut last_sort_value = None;
         for row in rows {
-            connections.push(row.map_err(|e| format!("Row error: {}", e))?);
+            let (connection, sort_value) = row.map_err(|e| format!("Row error: {}", e))?;
+            connections.push(connection);
+            last_sort_value = Some(sort_value);
+        }
+
+        let next_cursor = match (connections.last(), last_sort_value) {
+            (Some(last), Some(value)) if connections.len() == limit => Some(encode_cursor(value, &last.ip)),
+            _ => None,
+        };
+
+        Self::attach_top_ports(&conn, &mut connections)?;
+        Self::attach_domains(&conn, &mut connections)?;
+        Self::attach_server_names(&conn, &mut connections)?;
+        Self::attach_interfaces(&conn, &mut connections)?;
+
+        Ok(ConnectionPage {
+            connections,
+            total: total as u64,
+            next_cursor,
+        })
+    }
+
+    /// Merge another SNIFFF database, already migrated to the current schema,
+    /// into this one. Counters are summed, first/last seen widened, and geo
+    /// data taken from whichever side looked it up more recently. Merged
+    /// connections get `source_host` appended to their labels, or the labels
+    /// they already carry if the source was itself merged from other hosts.
+    /// A source host can only be merged once, since its counters would be
+    /// summed twice.
+    pub fn merge_from(&self, source: &Path, source_host: &str) -> Result<MergeCounts, String> {
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        conn.execute("ATTACH DATABASE ?1 AS src", params![source.to_string_lossy()])
+            .map_err(|e| format!("Failed to attach {}: {}", source.display(), e))?;
+        let result = Self::merge_attached(&mut conn, source_host);
+        if let Err(e) = conn.execute_batch("DETACH DATABASE src") {
+            log::error!("[SNIFFF] Failed to detach merged database: {}", e);
+        }
+        result
+    }
+
+    fn merge_attached(conn: &mut Connection, source_host: &str) -> Result<MergeCounts, String> {
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+        let count = |sql: &str| -> Result<u64, String> {
+            tx.query_row(sql, [], |row| row.get::<_, i64>(0))
+                .map(|n| n as u64)
+                .map_err(|e| format!("Failed to count rows: {}", e))
+        };
+
+        // The source may itself hold merges of hosts this database already has
+        let already_merged = tx.query_row(
+            "SELECT source_host FROM main.merged_sources
+             WHERE source_host = ?1 OR source_host IN (SELECT source_host FROM src.merged_sources)
+             ORDER BY source_host != ?1 LIMIT 1",
+            params![source_host],
+            |row| row.get::<_, String>(0),
+        );
+        match already_merged {
+            Ok(host) => {
+                return Err(format!(
+                    "\"{}\" has already been merged into this database; merging it again would count its traffic twice",
+                    host
+                ))
+            }
+            Err(rusqlite::Error::QueryReturnedNoRows) => {}
+            Err(e) => return Err(format!("Failed to check merged sources: {}", e)),
+        }
+        tx.execute(
+            "INSERT INTO main.merged_sources (source_host, merged_at)
+             SELECT ?1, ?2
+             UNION SELECT source_host, merged_at FROM src.merged_sources",
+            params![source_host, Utc::now().to_rfc3339()],
+        ).map_err(|e| format!("Failed to record merged source: {}", e))?;
+
+        let mut counts = MergeCounts {
+            connections: count("SELECT COUNT(*) FROM src.connections")?,
+            new_connections: count(
+                "SELECT COUNT(*) FROM src.connections s
+                 WHERE NOT EXISTS (SELECT 1 FROM main.connections m WHERE m.ip = s.ip)"
+            )?,
+            ..Default::default()
+        };
+
+        // Geo first, while `main` still holds its own values to compare against
+        tx.execute_batch(
+            "UPDATE main.connections
+             SET (asn, as_name, as_domain, country_code, country, continent_code, continent) =
+                 (SELECT s.asn, s.as_name, s.as_domain, s.country_code, s.country, s.continent_code, s.continent
+                  FROM src.connections s WHERE s.ip = main.connections.ip)
+             WHERE ip IN (
+                 SELECT s.ip FROM src.connections s
+                 JOIN main.connections m ON m.ip = s.ip
+                 LEFT JOIN src.geo_cache sg ON sg.ip = s.ip
+                 LEFT JOIN main.geo_cache mg ON mg.ip = s.ip
+                 WHERE s.country_code IS NOT NULL AND s.country_code != ''
+                   AND (m.country_code IS NULL OR m.country_code = ''
+                        OR COALESCE(sg.fetched_at, '') > COALESCE(mg.fetched_at, ''))
+             );
+
+             INSERT INTO main.geo_cache (ip, asn, as_name, as_domain, country_code, country,
+                                         continent_code, continent, error, fetched_at)
+             SELECT ip, asn, as_name, as_domain, country_code, country,
+                    continent_code, continent, error, fetched_at
+             FROM src.geo_cache WHERE true
+             ON CONFLICT(ip) DO UPDATE SET
+                asn = excluded.asn,
+                as_name = excluded.as_name,
+                as_domain = excluded.as_domain,
+                country_code = excluded.country_code,
+                country = excluded.country,
+                continent_code = excluded.continent_code,
+                continent = excluded.continent,
+                error = excluded.error,
+                fetched_at = excluded.fetched_at
+             WHERE excluded.fetched_at > geo_cache.fetched_at;
+
+             INSERT INTO main.rdns_cache (ip, hostname, expires_at)
+             SELECT ip, hostname, expires_at FROM src.rdns_cache WHERE true
+             ON CONFLICT(ip) DO UPDATE SET
+                hostname = excluded.hostname,
+                expires_at = excluded.expires_at
+             WHERE excluded.expires_at > rdns_cache.expires_at;"
+        ).map_err(|e| format!("Failed to merge geo data: {}", e))?;
+
+        tx.execute(
+            "INSERT INTO main.connections (ip, asn, as_name, as_domain, country_code, country,
+                                           continent_code, continent, hit_count, packet_count, byte_count,
+                                           packets_in, packets_out, bytes_in, bytes_out, direction,
+                                           first_seen, last_seen, source_host, hostname, ip_class)
+             SELECT ip, asn, as_name, as_domain, country_code, country,
+                    continent_code, continent, hit_count, packet_count, byte_count,
+                    packets_in, packets_out, bytes_in, bytes_out, direction,
+                    first_seen, last_seen, COALESCE(NULLIF(source_host, ''), ?1), hostname, ip_class(ip)
+             FROM src.connections WHERE true
+             ON CONFLICT(ip) DO UPDATE SET
+                hit_count = hit_count + excluded.hit_count,
+                packet_count = packet_count + excluded.packet_count,
+                byte_count = byte_count + excluded.byte_count,
+                packets_in = packets_in + excluded.packets_in,
+                packets_out = packets_out + excluded.packets_out,
+                bytes_in = bytes_in + excluded.bytes_in,
+                bytes_out = bytes_out + excluded.bytes_out,
+                direction = COALESCE(direction, excluded.direction),
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen),
+                hostname = COALESCE(hostname, excluded.hostname),
+                source_host = CASE
+                    WHEN source_host IS NULL OR source_host = '' THEN excluded.source_host
+                    WHEN instr(',' || source_host || ',', ',' || excluded.source_host || ',') > 0 THEN source_host
+                    ELSE source_host || ',' || excluded.source_host
+                END",
+            params![source_host],
+        ).map_err(|e| format!("Failed to merge connections: {}", e))?;
+
+        // Another capture's flows are other connections, even on the same 5-tuple
+        counts.flows = tx.execute(
+            "INSERT INTO main.flows (src_ip, src_port, dst_ip, dst_port, protocol, remote_ip,
+                                     direction, tcp_flags, packet_count, byte_count, start_time, end_time,
+                                     pid, process_name, exe_path, interface, tunnel, tunnel_endpoint)
+             SELECT src_ip, src_port, dst_ip, dst_port, protocol, remote_ip,
+                    direction, tcp_flags, packet_count, byte_count, start_time, end_time,
+                    pid, process_name, exe_path, interface, tunnel, tunnel_endpoint
+             FROM src.flows",
+            [],
+        ).map_err(|e| format!("Failed to merge flows: {}", e))? as u64;
+
+        counts.buckets = tx.execute(
+            "INSERT INTO main.traffic_buckets (ip, resolution, bucket_start,
+                                               packets_in, packets_out, bytes_in, bytes_out)
+             SELECT ip, resolution, bucket_start, packets_in, packets_out, bytes_in, bytes_out
+             FROM src.traffic_buckets WHERE true
+             ON CONFLICT(ip, resolution, bucket_start) DO UPDATE SET
+                packets_in = packets_in + excluded.packets_in,
+                packets_out = packets_out + excluded.packets_out,
+                bytes_in = bytes_in + excluded.bytes_in,
+                bytes_out = bytes_out + excluded.bytes_out",
+            [],
+        ).map_err(|e| format!("Failed to merge time series: {}", e))? as u64;
+
+        tx.execute_batch(
+            "INSERT INTO main.dns_answers (ip, domain, ttl, first_seen, last_seen)
+             SELECT ip, domain, ttl, first_seen, last_seen FROM src.dns_answers WHERE true
+             ON CONFLICT(ip, domain) DO UPDATE SET
+                ttl = CASE WHEN excluded.last_seen > last_seen THEN excluded.ttl ELSE ttl END,
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen);"
+        ).map_err(|e| format!("Failed to merge DNS answers: {}", e))?;
+
+        tx.execute_batch(
+            "INSERT INTO main.server_names (src_ip, src_port, dst_ip, dst_port, protocol, ip,
+                                            name, source, first_seen, last_seen)
+             SELECT src_ip, src_port, dst_ip, dst_port, protocol, ip, name, source, first_seen, last_seen
+             FROM src.server_names WHERE true
+             ON CONFLICT(src_ip, src_port, dst_ip, dst_port, protocol) DO UPDATE SET
+                name = CASE WHEN excluded.last_seen > last_seen THEN excluded.name ELSE name END,
+                source = CASE WHEN excluded.last_seen > last_seen THEN excluded.source ELSE source END,
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen);"
+        ).map_err(|e| format!("Failed to merge server names: {}", e))?;
+
+        tx.execute_batch(
+            "INSERT INTO main.connection_interfaces (ip, interface, packet_count, byte_count,
+                                                    first_seen, last_seen)
+             SELECT ip, interface, packet_count, byte_count, first_seen, last_seen
+             FROM src.connection_interfaces WHERE true
+             ON CONFLICT(ip, interface) DO UPDATE SET
+                packet_count = packet_count + excluded.packet_count,
+                byte_count = byte_count + excluded.byte_count,
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen);"
+        ).map_err(|e| format!("Failed to merge interfaces: {}", e))?;
+
+        tx.commit().map_err(|e| format!("Failed to commit merge: {}", e))?;
+        Ok(counts)
+    }
+
+    /// Delete the data selected by `filter`. Does not vacuum.
+    pub fn purge(&self, filter: &PurgeFilter) -> Result<PurgeResult, String> {
+        if filter.is_empty() {
+            return Err("Purge needs at least one filter".to_string());
+        }
+
+        let since = filter.since.as_deref().filter(|s| !s.is_empty()).map(normalize_time).transpose()?;
+        let until = filter.until.as_deref().filter(|s| !s.is_empty()).map(normalize_time).transpose()?;
+        let attrs = ConnectionQuery {
+            ip: filter.ip.clone(),
+            asn: filter.asn.clone(),
+            country_code: filter.country_code.clone(),
+            ..Default::default()
+        };
+
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        // Whole connections: matching, and never active outside the range
+        let (mut conditions, mut args) = connection_filter(&attrs)?;
+        if let Some(since) = &since {
+            args.push(since.clone().into());
+            conditions.push(format!("first_seen >= ?{}", args.len()));
+        }
+        if let Some(until) = &until {
+            args.push(until.clone().into());
+            conditions.push(format!("last_seen <= ?{}", args.len()));
+        }
+        let mut result = delete_connections_where(&tx, &conditions.join(" AND "), &args)?;
+
+        // History inside the range for connections that outlive it
+        if since.is_some() || until.is_some() {
+            let (attr_conditions, attr_args) = connection_filter(&attrs)?;
+            let scope = |column: &str| {
+                if attr_conditions.is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        " AND {} IN (SELECT ip FROM connections WHERE {})",
+                        column,
+                        attr_conditions.join(" AND ")
+                    )
+                }
+            };
+
+            let mut flow_args = attr_args.clone();
+            let mut flow_conditions = Vec::new();
+            let mut bucket_args = attr_args;
+            let mut bucket_conditions = Vec::new();
+            if let Some(since) = &since {
+                flow_args.push(since.clone().into());
+                flow_conditions.push(format!("start_time >= ?{}", flow_args.len()));
+                bucket_args.push(parse_epoch(since)?.into());
+                bucket_conditions.push(format!("bucket_start >= ?{}", bucket_args.len()));
+            }
+            if let Some(until) = &until {
+                flow_args.push(until.clone().into());
+                flow_conditions.push(format!("end_time <= ?{}", flow_args.len()));
+                bucket_args.push(parse_epoch(until)?.into());
+                bucket_conditions.push(format!("bucket_start < ?{}", bucket_args.len()));
+            }
+
+            result.flows += tx.execute(
+                &format!("DELETE FROM flows WHERE {}{}", flow_conditions.join(" AND "), scope("remote_ip")),
+                params_from_iter(flow_args.iter()),
+            ).map_err(|e| format!("Failed to purge flows: {}", e))? as u64;
+            result.buckets += tx.execute(
+                &format!("DELETE FROM traffic_buckets WHERE {}{}", bucket_conditions.join(" AND "), scope("ip")),
+                params_from_iter(bucket_args.iter()),
+            ).map_err(|e| format!("Failed to purge time series: {}", e))? as u64;
         }
 
-        Ok(connections)
+        tx.commit().map_err(|e| format!("Failed to commit purge: {}", e))?;
+        Ok(result)
+    }
+
+    /// Delete connections last seen before `cutoff`, and flows and time-series
+    /// history older than it
+    pub fn prune_before(&self, cutoff: DateTime<Utc>) -> Result<PurgeResult, String> {
+        let cutoff_str = cutoff.to_rfc3339();
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        let mut result = delete_connections_where(&tx, "last_seen < ?1", &[cutoff_str.clone().into()])?;
+        result.flows += tx.execute("DELETE FROM flows WHERE end_time < ?1", params![cutoff_str])
+            .map_err(|e| format!("Failed to prune flows: {}", e))? as u64;
+        result.buckets += tx.execute("DELETE FROM traffic_buckets WHERE bucket_start < ?1", params![cutoff.timestamp()])
+            .map_err(|e| format!("Failed to prune time series: {}", e))? as u64;
+        tx.execute("DELETE FROM dns_answers WHERE last_seen < ?1", params![cutoff_str])
+            .map_err(|e| format!("Failed to prune DNS answers: {}", e))?;
+        tx.execute("DELETE FROM server_names WHERE last_seen < ?1", params![cutoff_str])
+            .map_err(|e| format!("Failed to prune server names: {}", e))?;
+        tx.execute("DELETE FROM connection_interfaces WHERE last_seen < ?1", params![cutoff_str])
+            .map_err(|e| format!("Failed to prune interfaces: {}", e))?;
+
+        tx.commit().map_err(|e| format!("Failed to commit prune: {}", e))?;
+        Ok(result)
+    }
+
+    /// Delete all but the `keep` most recently seen connections
+    pub fn prune_to_rows(&self, keep: u64) -> Result<PurgeResult, String> {
+        self.delete_by_recency("ip IN (SELECT ip FROM connections ORDER BY last_seen DESC LIMIT -1 OFFSET ?1)", keep)
+    }
+
+    /// Delete the `count` least recently seen connections
+    pub fn prune_oldest(&self, count: u64) -> Result<PurgeResult, String> {
+        self.delete_by_recency("ip IN (SELECT ip FROM connections ORDER BY last_seen ASC LIMIT ?1)", count)
+    }
+
+    fn delete_by_recency(&self, condition: &str, n: u64) -> Result<PurgeResult, String> {
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+        let result = delete_connections_where(&tx, condition, &[(n as i64).into()])?;
+        tx.commit().map_err(|e| format!("Failed to commit prune: {}", e))?;
+        Ok(result)
+    }
+
+    pub fn connection_count(&self) -> Result<u64, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        conn.query_row("SELECT COUNT(*) FROM connections", [], |row| row.get::<_, i64>(0))
+            .map(|n| n as u64)
+            .map_err(|e| format!("Failed to count connections: {}", e))
+    }
+
+    /// Bytes in use by the database, not counting free pages a vacuum would release
+    pub fn used_bytes(&self) -> Result<u64, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        conn.query_row(
+            "SELECT (page_count - freelist_count) * page_size
+             FROM pragma_page_count(), pragma_freelist_count(), pragma_page_size()",
+            [],
+            |row| row.get::<_, i64>(0),
+        )
+        .map(|n| n as u64)
+        .map_err(|e| format!("Failed to read database size: {}", e))
+    }
+
+    /// Rebuild the file to hand freed pages back to the OS; returns bytes reclaimed
+    pub fn vacuum(&self) -> Result<u64, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let file_size = |conn: &Connection| -> Result<i64, String> {
+            conn.query_row(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                [],
+                |row| row.get(0),
+            ).map_err(|e| format!("Failed to read database size: {}", e))
+        };
+
+        let before = file_size(&conn)?;
+        conn.execute_batch("VACUUM").map_err(|e| format!("Failed to vacuum database: {}", e))?;
+        let after = file_size(&conn)?;
+        Ok((before - after).max(0) as u64)
+    }
+
+    /// Fill `top_ports` on each connection from the flows table
+    fn attach_top_ports(conn: &Connection, connections: &mut [IpConnection]) -> Result<(), String> {
+        // Stay well under SQLite's bound parameter limit
+        for chunk in connections.chunks_mut(500) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let mut stmt = conn.prepare(&format!(
+                "SELECT remote_ip, protocol, dst_port, COUNT(*), SUM(packet_count) as packets, SUM(byte_count)
+                 FROM flows
+                 WHERE remote_ip IN ({})
+                 GROUP BY remote_ip, protocol, dst_port
+                 ORDER BY packets DESC",
+                placeholders
+            )).map_err(|e| format!("Failed to prepare query: {}", e))?;
+
+            let ips = chunk.iter().map(|c| c.ip.as_str());
+            let rows = stmt.query_map(rusqlite::params_from_iter(ips), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    PortUsage {
+                        protocol: row.get(1)?,
+                        port: row.get(2)?,
+                        flow_count: row.get::<_, i64>(3)? as u64,
+                        packet_count: row.get::<_, i64>(4)? as u64,
+                        byte_count: row.get::<_, i64>(5)? as u64,
+                    },
+                ))
+            }).map_err(|e| format!("Failed to query top ports: {}", e))?;
+
+            let mut by_ip: HashMap<String, Vec<PortUsage>> = HashMap::new();
+            for row in rows {
+                let (ip, usage) = row.map_err(|e| format!("Row error: {}", e))?;
+                let ports = by_ip.entry(ip).or_default();
+                if ports.len() < TOP_PORTS_PER_IP {
+                    ports.push(usage);
+                }
+            }
+
+            for c in chunk.iter_mut() {
+                c.top_ports = by_ip.remove(&c.ip).unwrap_or_default();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fill `domains` on each connection from recorded DNS answers
+    fn attach_domains(conn: &Connection, connections: &mut [IpConnection]) -> Result<(), String> {
+        Self::attach_names(
+            conn,
+            connections,
+            "SELECT ip, domain FROM dns_answers WHERE ip IN ({}) ORDER BY last_seen DESC",
+            DOMAINS_PER_IP,
+            |c, names| c.domains = names,
+        )
+    }
+
+    /// Fill `server_names` on each connection from recorded SNI and Host headers
+    fn attach_server_names(conn: &Connection, connections: &mut [IpConnection]) -> Result<(), String> {
+        Self::attach_names(
+            conn,
+            connections,
+            "SELECT ip, name FROM server_names WHERE ip IN ({})
+             GROUP BY ip, name ORDER BY MAX(last_seen) DESC",
+            SERVER_NAMES_PER_IP,
+            |c, names| c.server_names = names,
+        )
+    }
+
+    /// Fill `interfaces` on each connection from where it was captured
+    fn attach_interfaces(conn: &Connection, connections: &mut [IpConnection]) -> Result<(), String> {
+        Self::attach_names(
+            conn,
+            connections,
+            "SELECT ip, interface FROM connection_interfaces WHERE ip IN ({}) ORDER BY last_seen DESC",
+            INTERFACES_PER_IP,
+            |c, names| c.interfaces = names,
+        )
+    }
+
+    /// Run `sql`, which selects (ip, name) newest first for the IPs in its
+    /// `{}` placeholder list, and hand each connection up to `limit` names
+    fn attach_names(
+        conn: &Connection,
+        connections: &mut [IpConnection],
+        sql: &str,
+        limit: usize,
+        set: impl Fn(&mut IpConnection, Vec<String>),
+    ) -> Result<(), String> {
+        for chunk in connections.chunks_mut(500) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let mut stmt = conn.prepare(&sql.replace("{}", &placeholders))
+                .map_err(|e| format!("Failed to prepare query: {}", e))?;
+
+            let ips = chunk.iter().map(|c| c.ip.as_str());
+            let rows = stmt.query_map(rusqlite::params_from_iter(ips), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            }).map_err(|e| format!("Failed to query names: {}", e))?;
+
+            let mut by_ip: HashMap<String, Vec<String>> = HashMap::new();
+            for row in rows {
+                let (ip, name) = row.map_err(|e| format!("Row error: {}", e))?;
+                let names = by_ip.entry(ip).or_default();
+                if names.len() < limit {
+                    names.push(name);
+                }
+            }
+
+            for c in chunk.iter_mut() {
+                set(c, by_ip.remove(&c.ip).unwrap_or_default());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get flows involving a remote IP, most recent first
+    pub fn get_flows(&self, ip: &str, limit: usize) -> Result<Vec<FlowRecord>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT f.src_ip, f.src_port, f.dst_ip, f.dst_port, f.protocol, f.remote_ip, f.direction,
+                    f.tcp_flags, f.packet_count, f.byte_count, f.start_time, f.end_time,
+                    (SELECT s.name FROM server_names s
+                     WHERE s.protocol = f.protocol
+                       AND ((s.src_ip = f.src_ip AND s.src_port = f.src_port
+                             AND s.dst_ip = f.dst_ip AND s.dst_port = f.dst_port)
+                         OR (s.src_ip = f.dst_ip AND s.src_port = f.dst_port
+                             AND s.dst_ip = f.src_ip AND s.dst_port = f.src_port))),
+                    f.pid, f.process_name, f.exe_path, f.interface, f.tunnel, f.tunnel_endpoint
+             FROM flows f
+             WHERE f.remote_ip = ?1
+             ORDER BY f.end_time DESC
+             LIMIT ?2"
+        ).map_err(|e| format!("Failed to prepare query: {}", e))?;
+
+        let rows = stmt.query_map(params![ip, limit as i64], |row| {
+            Ok(FlowRecord {
+                src_ip: row.get(0)?,
+                src_port: row.get(1)?,
+                dst_ip: row.get(2)?,
+                dst_port: row.get(3)?,
+                protocol: row.get(4)?,
+                remote_ip: row.get(5)?,
+                direction: row.get(6)?,
+                tcp_flags: row.get(7)?,
+                packet_count: row.get::<_, i64>(8)? as u64,
+                byte_count: row.get::<_, i64>(9)? as u64,
+                start_time: row.get(10)?,
+                end_time: row.get(11)?,
+                server_name: row.get(12)?,
+                pid: row.get(13)?,
+                process_name: row.get(14)?,
+                exe_path: row.get(15)?,
+                interface: row.get(16)?,
+                tunnel: row.get(17)?,
+                tunnel_endpoint: row.get(18)?,
+            })
+        }).map_err(|e| format!("Failed to query flows: {}", e))?;
+
+        let mut flows = Vec::new();
+        for row in rows {
+            flows.push(row.map_err(|e| format!("Row error: {}", e))?);
+        }
+
+        Ok(flows)
     }
 
     /// Get country statistics for the heatmap
     pub fn get_country_stats(&self) -> Result<Vec<CountryStats>, String> {
+        self.get_country_stats_matching(&ConnectionQuery::default())
+    }
+
+    /// Per-country totals over the connections matching `query`'s filters
+    pub fn get_country_stats_matching(&self, query: &ConnectionQuery) -> Result<Vec<CountryStats>, String> {
+        let (mut conditions, args) = connection_filter(query)?;
+        conditions.push("country_code IS NOT NULL AND country_code != ''".to_string());
+
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
 
-        let mut stmt = conn.prepare(
-            "SELECT country_code, country, SUM(hit_count) as total_hits, COUNT(DISTINCT ip) as unique_ips
+        let mut stmt = conn.prepare(&format!(
+            "SELECT country_code, country, SUM(hit_count) as total_hits,
+                    SUM(packet_count) as total_packets, SUM(byte_count) as total_bytes,
+                    COUNT(DISTINCT ip) as unique_ips
              FROM connections
-             WHERE country_code IS NOT NULL AND country_code != ''
+             WHERE {}
              GROUP BY country_code
-             ORDER BY total_hits DESC"
-        ).map_err(|e| format!("Failed to prepare query: {}", e))?;
+             ORDER BY total_packets DESC",
+            conditions.join(" AND ")
+        )).map_err(|e| format!("Failed to prepare query: {}", e))?;
 
-        let rows = stmt.query_map([], |row| {
+        let rows = stmt.query_map(params_from_iter(args.iter()), |row| {
             Ok(CountryStats {
                 country_code: row.get(0)?,
                 country: row.get(1)?,
                 hit_count: row.get::<_, i64>(2)? as u64,
-                unique_ips: row.get::<_, i64>(3)? as u64,
+                packet_count: row.get::<_, i64>(3)? as u64,
+                byte_count: row.get::<_, i64>(4)? as u64,
+                unique_ips: row.get::<_, i64>(5)? as u64,
             })
         }).map_err(|e| format!("Failed to query country stats: {}", e))?;
 
@@ -186,16 +1642,100 @@ impl Database {
         Ok(stats)
     }
 
-    /// Get total stats
-    pub fn get_total_stats(&self) -> Result<(u64, u64, u64), String> {
+    /// Traffic per local program with the connections matching `query`,
+    /// busiest program first. Only flows that were attributed count.
+    pub fn get_connections_by_process(&self, query: &ConnectionQuery) -> Result<Vec<ProcessUsage>, String> {
+        let (conditions, args) = connection_filter(query)?;
+        let scope = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" AND f.remote_ip IN (SELECT ip FROM connections WHERE {})", conditions.join(" AND "))
+        };
+
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
 
-        let total_ips: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM connections", [], |row| row.get(0)
-        ).map_err(|e| format!("Query error: {}", e))?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT f.process_name, f.exe_path, f.remote_ip, c.country_code, c.country, c.as_name,
+                    COUNT(*), SUM(f.packet_count), SUM(f.byte_count) as bytes, MAX(f.end_time),
+                    GROUP_CONCAT(DISTINCT f.pid)
+             FROM flows f
+             LEFT JOIN connections c ON c.ip = f.remote_ip
+             WHERE f.process_name IS NOT NULL{}
+             GROUP BY f.process_name, f.exe_path, f.remote_ip
+             ORDER BY bytes DESC",
+            scope
+        )).map_err(|e| format!("Failed to prepare query: {}", e))?;
 
-        let total_hits: i64 = conn.query_row(
-            "SELECT COALESCE(SUM(hit_count), 0) FROM connections", [], |row| row.get(0)
+        let rows = stmt.query_map(params_from_iter(args.iter()), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                ProcessConnection {
+                    ip: row.get(2)?,
+                    country_code: row.get(3)?,
+                    country: row.get(4)?,
+                    as_name: row.get(5)?,
+                    flow_count: row.get::<_, i64>(6)? as u64,
+                    packet_count: row.get::<_, i64>(7)? as u64,
+                    byte_count: row.get::<_, i64>(8)? as u64,
+                    last_seen: row.get(9)?,
+                },
+                row.get::<_, Option<String>>(10)?,
+            ))
+        }).map_err(|e| format!("Failed to query processes: {}", e))?;
+
+        let mut processes: Vec<ProcessUsage> = Vec::new();
+        let mut index: HashMap<(String, Option<String>), usize> = HashMap::new();
+        for row in rows {
+            let (name, exe_path, remote, pids) = row.map_err(|e| format!("Row error: {}", e))?;
+            let i = *index.entry((name.clone(), exe_path.clone())).or_insert_with(|| {
+                processes.push(ProcessUsage {
+                    process_name: name,
+                    exe_path,
+                    pids: Vec::new(),
+                    flow_count: 0,
+                    packet_count: 0,
+                    byte_count: 0,
+                    last_seen: String::new(),
+                    connections: Vec::new(),
+                });
+                processes.len() - 1
+            });
+
+            let usage = &mut processes[i];
+            for pid in pids.iter().flat_map(|p| p.split(',')).filter_map(|p| p.parse().ok()) {
+                if !usage.pids.contains(&pid) {
+                    usage.pids.push(pid);
+                }
+            }
+            usage.flow_count += remote.flow_count;
+            usage.packet_count += remote.packet_count;
+            usage.byte_count += remote.byte_count;
+            if remote.last_seen > usage.last_seen {
+                usage.last_seen = remote.last_seen.clone();
+            }
+            if usage.connections.len() < IPS_PER_PROCESS {
+                usage.connections.push(remote);
+            }
+        }
+
+        processes.sort_by_key(|p| std::cmp::Reverse(p.byte_count));
+        for usage in &mut processes {
+            usage.pids.sort_unstable();
+        }
+        Ok(processes)
+    }
+
+    /// Get total stats
+    pub fn get_total_stats(&self) -> Result<TotalStats, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let (total_ips, total_hits, total_packets, total_bytes): (i64, i64, i64, i64) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(hit_count), 0),
+                    COALESCE(SUM(packet_count), 0), COALESCE(SUM(byte_count), 0)
+             FROM connections",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
         ).map_err(|e| format!("Query error: {}", e))?;
 
         let total_countries: i64 = conn.query_row(
@@ -203,7 +1743,137 @@ impl Database {
             [], |row| row.get(0)
         ).map_err(|e| format!("Query error: {}", e))?;
 
-        Ok((total_ips as u64, total_hits as u64, total_countries as u64))
+        Ok(TotalStats {
+            total_ips: total_ips as u64,
+            total_hits: total_hits as u64,
+            total_packets: total_packets as u64,
+            total_bytes: total_bytes as u64,
+            total_countries: total_countries as u64,
+        })
+    }
+
+    /// Read a persisted geolocation result, fresh or not
+    pub fn get_geo_cache(&self, ip: &str) -> Result<Option<GeoCacheEntry>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let row = conn.query_row(
+            "SELECT asn, as_name, as_domain, country_code, country, continent_code, continent,
+                    error, fetched_at
+             FROM geo_cache WHERE ip = ?1",
+            params![ip],
+            |row| {
+                let error: Option<String> = row.get(7)?;
+                let fetched_at: String = row.get(8)?;
+                let info = IpInfoResponse {
+                    ip: Some(ip.to_string()),
+                    asn: row.get(0)?,
+                    as_name: row.get(1)?,
+                    as_domain: row.get(2)?,
+                    country_code: row.get(3)?,
+                    country: row.get(4)?,
+                    continent_code: row.get(5)?,
+                    continent: row.get(6)?,
+                    bogon: false,
+                };
+                Ok((info, error, fetched_at))
+            },
+        );
+
+        let (info, error, fetched_at) = match row {
+            Ok(row) => row,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(format!("Query error: {}", e)),
+        };
+
+        let fetched_at = DateTime::parse_from_rfc3339(&fetched_at)
+            .map_err(|e| format!("Bad fetched_at for {}: {}", ip, e))?
+            .with_timezone(&Utc);
+
+        Ok(Some(GeoCacheEntry {
+            result: match error {
+                Some(e) => Err(e),
+                None => Ok(info),
+            },
+            fetched_at,
+        }))
+    }
+
+    /// Persist a geolocation result; an error result becomes a negative entry
+    pub fn put_geo_cache(&self, ip: &str, entry: &GeoCacheEntry) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let empty = IpInfoResponse::default();
+        let (info, error) = match &entry.result {
+            Ok(info) => (info, None),
+            Err(e) => (&empty, Some(e.as_str())),
+        };
+
+        conn.execute(
+            "INSERT OR REPLACE INTO geo_cache (ip, asn, as_name, as_domain, country_code, country,
+                                               continent_code, continent, error, fetched_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                ip,
+                info.asn,
+                info.as_name,
+                info.as_domain,
+                info.country_code,
+                info.country,
+                info.continent_code,
+                info.continent,
+                error,
+                entry.fetched_at.to_rfc3339(),
+            ],
+        ).map_err(|e| format!("Failed to write geo cache: {}", e))?;
+
+        Ok(())
+    }
+
+    /// Public IPs recorded without geo info, e.g. lookups interrupted by a restart
+    pub fn get_ips_missing_geo(&self) -> Result<Vec<String>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT ip FROM connections
+             WHERE (country_code IS NULL OR country_code = '') AND ip_class = 'public'
+             ORDER BY last_seen DESC"
+        ).map_err(|e| format!("Failed to prepare query: {}", e))?;
+
+        let rows = stmt.query_map([], |row| row.get(0))
+            .map_err(|e| format!("Failed to query pending IPs: {}", e))?;
+
+        let mut ips = Vec::new();
+        for row in rows {
+            ips.push(row.map_err(|e| format!("Row error: {}", e))?);
+        }
+
+        Ok(ips)
+    }
+
+    /// Addresses that geo data places in any of `asns`
+    pub fn get_ips_in_asns(&self, asns: &[String]) -> Result<Vec<String>, String> {
+        if asns.is_empty() {
+            return Ok(Vec::new());
+        }
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        // Numbered so both halves of the UNION share the same arguments
+        let placeholders = (1..=asns.len()).map(|i| format!("?{}", i)).collect::<Vec<_>>().join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT ip FROM connections WHERE asn IN ({0})
+             UNION
+             SELECT ip FROM geo_cache WHERE asn IN ({0})",
+            placeholders
+        )).map_err(|e| format!("Failed to prepare query: {}", e))?;
+
+        let rows = stmt.query_map(params_from_iter(asns.iter()), |row| row.get(0))
+            .map_err(|e| format!("Failed to query IPs by ASN: {}", e))?;
+
+        let mut ips = Vec::new();
+        for row in rows {
+            ips.push(row.map_err(|e| format!("Row error: {}", e))?);
+        }
+
+        Ok(ips)
     }
 
     /// Check if an IP has geo info already
@@ -218,4 +1888,718 @@ impl Database {
 
         Ok(count > 0)
     }
+
+    pub fn get_rdns_cache(&self, ip: &str) -> Result<Option<RdnsCacheEntry>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let row = conn.query_row(
+            "SELECT hostname, expires_at FROM rdns_cache WHERE ip = ?1",
+            params![ip],
+            |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?)),
+        );
+        let (hostname, expires_at) = match row {
+            Ok(row) => row,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(format!("Query error: {}", e)),
+        };
+
+        let expires_at = DateTime::parse_from_rfc3339(&expires_at)
+            .map_err(|e| format!("Bad expires_at for {}: {}", ip, e))?
+            .with_timezone(&Utc);
+
+        Ok(Some(RdnsCacheEntry { hostname, expires_at }))
+    }
+
+    /// Cache a PTR lookup and copy the hostname onto the connection
+    pub fn put_rdns_cache(&self, ip: &str, entry: &RdnsCacheEntry) -> Result<(), String> {
+        let mut conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO rdns_cache (ip, hostname, expires_at) VALUES (?1, ?2, ?3)",
+            params![ip, entry.hostname, entry.expires_at.to_rfc3339()],
+        ).map_err(|e| format!("Failed to write rdns cache: {}", e))?;
+        tx.execute(
+            "UPDATE connections SET hostname = ?2 WHERE ip = ?1",
+            params![ip, entry.hostname],
+        ).map_err(|e| format!("Failed to update hostname: {}", e))?;
+
+        tx.commit().map_err(|e| format!("Failed to commit hostname: {}", e))
+    }
+
+    pub fn set_hostname(&self, ip: &str, hostname: Option<&str>) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        conn.execute(
+            "UPDATE connections SET hostname = ?2 WHERE ip = ?1",
+            params![ip, hostname],
+        ).map_err(|e| format!("Failed to update hostname: {}", e))?;
+        Ok(())
+    }
+
+    /// IPs with neither a hostname nor an unexpired cached lookup
+    pub fn get_ips_missing_hostname(&self) -> Result<Vec<String>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT c.ip FROM connections c
+             LEFT JOIN rdns_cache r ON r.ip = c.ip
+             WHERE c.hostname IS NULL AND (r.ip IS NULL OR r.expires_at < ?1)
+             ORDER BY c.last_seen DESC"
+        ).map_err(|e| format!("Failed to prepare query: {}", e))?;
+
+        let rows = stmt.query_map(params![Utc::now().to_rfc3339()], |row| row.get(0))
+            .map_err(|e| format!("Failed to query pending IPs: {}", e))?;
+
+        let mut ips = Vec::new();
+        for row in rows {
+            ips.push(row.map_err(|e| format!("Row error: {}", e))?);
+        }
+
+        Ok(ips)
+    }
+}
+
+/// Smallest friendly step that keeps a range within about
+/// `TIMESERIES_TARGET_POINTS` points
+fn auto_step(range_secs: i64) -> i64 {
+    const STEPS: [i64; 8] = [60, 300, 900, 1800, 3600, 6 * 3600, 12 * 3600, 86400];
+    STEPS
+        .iter()
+        .copied()
+        .find(|step| range_secs / step <= TIMESERIES_TARGET_POINTS)
+        .unwrap_or(86400)
+}
+
+/// WHERE conditions and their arguments for the filters in `query`.
+/// Placeholders are numbered from 1, so callers append further arguments after these.
+fn connection_filter(query: &ConnectionQuery) -> Result<(Vec<String>, Vec<Value>), String> {
+    let mut conditions: Vec<String> = Vec::new();
+    let mut args: Vec<Value> = Vec::new();
+
+    if let Some(search) = query.search.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        let escaped = search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        args.push(format!("%{}%", escaped).into());
+        let n = args.len();
+        conditions.push(format!(
+            "(ip LIKE ?{n} ESCAPE '\\' OR asn LIKE ?{n} ESCAPE '\\' OR as_name LIKE ?{n} ESCAPE '\\'
+              OR country LIKE ?{n} ESCAPE '\\' OR country_code LIKE ?{n} ESCAPE '\\'
+              OR hostname LIKE ?{n} ESCAPE '\\'
+              OR ip IN (SELECT ip FROM dns_answers WHERE domain LIKE ?{n} ESCAPE '\\')
+              OR ip IN (SELECT ip FROM server_names WHERE name LIKE ?{n} ESCAPE '\\'))"
+        ));
+    }
+    if let Some(ip) = query.ip.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        let cidr: Cidr = ip.parse()?;
+        args.push(cidr.to_string().into());
+        conditions.push(format!("ip_in_cidr(ip, ?{})", args.len()));
+    }
+    if let Some(asn) = query.asn.as_deref().filter(|s| !s.is_empty()) {
+        args.push(asn.to_string().into());
+        let n = args.len();
+        conditions.push(format!("(asn = ?{n} OR as_name = ?{n})"));
+    }
+    if let Some(code) = query.country_code.as_deref().filter(|s| !s.is_empty()) {
+        args.push(code.to_string().into());
+        conditions.push(format!("country_code = ?{}", args.len()));
+    }
+    if let Some(code) = query.continent_code.as_deref().filter(|s| !s.is_empty()) {
+        args.push(code.to_string().into());
+        conditions.push(format!("continent_code = ?{}", args.len()));
+    }
+    if let Some(interface) = query.interface.as_deref().filter(|s| !s.is_empty()) {
+        args.push(interface.to_string().into());
+        conditions.push(format!(
+            "ip IN (SELECT ip FROM connection_interfaces WHERE interface = ?{})",
+            args.len()
+        ));
+    }
+    if let Some(since) = query.since.as_deref().filter(|s| !s.is_empty()) {
+        args.push(normalize_time(since)?.into());
+        conditions.push(format!("last_seen >= ?{}", args.len()));
+    }
+    if let Some(until) = query.until.as_deref().filter(|s| !s.is_empty()) {
+        args.push(normalize_time(until)?.into());
+        conditions.push(format!("first_seen <= ?{}", args.len()));
+    }
+    if let Some(min_hits) = query.min_hits {
+        args.push((min_hits as i64).into());
+        conditions.push(format!("hit_count >= ?{}", args.len()));
+    }
+
+    Ok((conditions, args))
+}
+
+/// Delete the connections matching `condition` along with their flows and
+/// time-series history. An empty condition matches nothing.
+fn delete_connections_where(
+    conn: &Connection,
+    condition: &str,
+    args: &[Value],
+) -> Result<PurgeResult, String> {
+    if condition.is_empty() {
+        return Ok(PurgeResult::default());
+    }
+
+    conn.execute_batch(
+        "CREATE TEMP TABLE IF NOT EXISTS purge_ips (ip TEXT PRIMARY KEY);
+         DELETE FROM purge_ips;"
+    ).map_err(|e| format!("Failed to prepare purge: {}", e))?;
+    conn.execute(
+        &format!("INSERT INTO purge_ips SELECT ip FROM connections WHERE {}", condition),
+        params_from_iter(args.iter()),
+    ).map_err(|e| format!("Failed to select connections to purge: {}", e))?;
+
+    let flows = conn.execute("DELETE FROM flows WHERE remote_ip IN (SELECT ip FROM purge_ips)", [])
+        .map_err(|e| format!("Failed to purge flows: {}", e))?;
+    let buckets = conn.execute("DELETE FROM traffic_buckets WHERE ip IN (SELECT ip FROM purge_ips)", [])
+        .map_err(|e| format!("Failed to purge time series: {}", e))?;
+    conn.execute("DELETE FROM dns_answers WHERE ip IN (SELECT ip FROM purge_ips)", [])
+        .map_err(|e| format!("Failed to purge DNS answers: {}", e))?;
+    conn.execute("DELETE FROM server_names WHERE ip IN (SELECT ip FROM purge_ips)", [])
+        .map_err(|e| format!("Failed to purge server names: {}", e))?;
+    conn.execute("DELETE FROM connection_interfaces WHERE ip IN (SELECT ip FROM purge_ips)", [])
+        .map_err(|e| format!("Failed to purge interfaces: {}", e))?;
+    let connections = conn.execute("DELETE FROM connections WHERE ip IN (SELECT ip FROM purge_ips)", [])
+        .map_err(|e| format!("Failed to purge connections: {}", e))?;
+
+    Ok(PurgeResult {
+        connections: connections as u64,
+        flows: flows as u64,
+        buckets: buckets as u64,
+        bytes_reclaimed: 0,
+    })
+}
+
+fn parse_epoch(value: &str) -> Result<i64, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.timestamp())
+        .map_err(|e| format!("Invalid time {}: {}", value, e))
+}
+
+/// Parse an RFC 3339 time and re-format it the way timestamps are stored,
+/// so string comparisons in SQL order correctly
+fn normalize_time(value: &str) -> Result<String, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc).to_rfc3339())
+        .map_err(|e| format!("Invalid time {}: {}", value, e))
+}
+
+/// Opaque page cursor: the last row's sort value and IP, as JSON
+fn encode_cursor(sort_value: Value, ip: &str) -> String {
+    let value = match sort_value {
+        Value::Integer(i) => serde_json::Value::from(i),
+        Value::Real(f) => serde_json::Value::from(f),
+        Value::Text(s) => serde_json::Value::from(s),
+        Value::Null | Value::Blob(_) => serde_json::Value::Null,
+    };
+    serde_json::json!([value, ip]).to_string()
+}
+
+fn decode_cursor(cursor: &str) -> Result<(Value, String), String> {
+    let invalid = || "Invalid cursor".to_string();
+    let parsed: (serde_json::Value, String) = serde_json::from_str(cursor).map_err(|_| invalid())?;
+    let value = match parsed.0 {
+        serde_json::Value::Number(n) => n
+            .as_i64()
+            .map(Value::Integer)
+            .or_else(|| n.as_f64().map(Value::Real))
+            .ok_or_else(invalid)?,
+        serde_json::Value::String(s) => Value::Text(s),
+        _ => return Err(invalid()),
+    };
+    Ok((value, parsed.1))
+}
+
+/// A scratch database for tests, removed with its directory on drop
+#[cfg(test)]
+pub(crate) struct TempDb {
+    pub dir: crate::test_util::TempDir,
+    pub db: Database,
+}
+
+#[cfg(test)]
+impl TempDb {
+    pub fn new(name: &str) -> Self {
+        let dir = crate::test_util::TempDir::new(&format!("db-{}", name));
+        let db = Database::open(&dir.join(DB_FILE_NAME), DbPathSource::Default).unwrap();
+        Self { dir, db }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::ProcessInfo;
+    use crate::sniffer::{Direction, TunnelKind};
+    use chrono::TimeZone;
+    use std::collections::HashSet;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn flow(start: i64, end: i64, packets: u64) -> FlowTraffic {
+        FlowTraffic {
+            src_ip: "192.168.1.20".to_string(),
+            src_port: 40000,
+            dst_ip: "1.1.1.1".to_string(),
+            dst_port: 443,
+            protocol: "tcp".to_string(),
+            remote_ip: "1.1.1.1".to_string(),
+            direction: Direction::Outbound,
+            tcp_flags: 0,
+            packets,
+            bytes: packets * 100,
+            start_time: at(start),
+            end_time: at(end),
+            tunnel: None,
+            tunnel_endpoint: None,
+        }
+    }
+
+    fn flow_packets(db: &Database) -> Vec<u64> {
+        let mut flows = db.get_flows("1.1.1.1", 10).unwrap();
+        flows.reverse();
+        flows.iter().map(|f| f.packet_count).collect()
+    }
+
+    #[test]
+    fn flows_continue_until_idle_then_start_a_new_row() {
+        let t = TempDb::new("flows");
+        let idle = FLOW_IDLE_TIMEOUT.num_seconds();
+
+        t.db.record_flows(&[flow(0, 10, 3)], None).unwrap();
+        // The next flush of the same connection
+        t.db.record_flows(&[flow(12, 20, 2)], None).unwrap();
+        assert_eq!(flow_packets(&t.db), vec![5]);
+
+        // The tuple reused after the first connection went idle
+        t.db.record_flows(&[flow(20 + idle + 1, 20 + idle + 5, 4)], None).unwrap();
+        assert_eq!(flow_packets(&t.db), vec![5, 4]);
+
+        let flows = t.db.get_flows("1.1.1.1", 10).unwrap();
+        assert_eq!(flows[1].start_time, at(0).to_rfc3339());
+        assert_eq!(flows[1].end_time, at(20).to_rfc3339());
+        assert_eq!(flows[0].start_time, at(20 + idle + 1).to_rfc3339());
+    }
+
+    #[test]
+    fn a_5_tuple_on_two_interfaces_is_two_flows() {
+        let t = TempDb::new("interfaces");
+        t.db.record_flows(&[flow(0, 10, 3)], Some("en0")).unwrap();
+        t.db.record_flows(&[flow(0, 10, 2)], Some("utun3")).unwrap();
+        t.db.record_flows(&[flow(12, 20, 1)], Some("en0")).unwrap();
+
+        let flows = t.db.get_flows("1.1.1.1", 10).unwrap();
+        let mut per_interface: Vec<_> = flows.iter().map(|f| (f.interface.clone(), f.packet_count)).collect();
+        per_interface.sort();
+        assert_eq!(
+            per_interface,
+            vec![(Some("en0".to_string()), 4), (Some("utun3".to_string()), 2)]
+        );
+
+        let process = ProcessInfo { pid: 42, name: "curl".to_string(), exe_path: None };
+        let f = flow(0, 0, 0);
+        t.db.record_flow_processes(&[FlowProcess {
+            src_ip: f.src_ip,
+            src_port: f.src_port,
+            dst_ip: f.dst_ip,
+            dst_port: f.dst_port,
+            protocol: f.protocol,
+            process,
+        }])
+        .unwrap();
+        let flows = t.db.get_flows("1.1.1.1", 10).unwrap();
+        assert!(flows.iter().all(|f| f.pid == Some(42)), "both interfaces' rows are the same socket");
+    }
+
+    #[test]
+    fn a_5_tuple_through_two_tunnels_is_two_flows() {
+        let t = TempDb::new("tunnels");
+        let through = |endpoint: Option<&str>, packets| FlowTraffic {
+            tunnel: endpoint.map(|_| TunnelKind::Gre),
+            tunnel_endpoint: endpoint.map(str::to_string),
+            ..flow(0, 10, packets)
+        };
+        t.db.record_flows(&[through(None, 1)], Some("en0")).unwrap();
+        t.db.record_flows(&[through(Some("203.0.113.10"), 2)], Some("en0")).unwrap();
+        t.db.record_flows(&[through(Some("203.0.113.20"), 3)], Some("en0")).unwrap();
+        t.db.record_flows(&[through(Some("203.0.113.10"), 4)], Some("en0")).unwrap();
+
+        let flows = t.db.get_flows("1.1.1.1", 10).unwrap();
+        let mut per_endpoint: Vec<_> = flows.iter().map(|f| (f.tunnel_endpoint.clone(), f.packet_count)).collect();
+        per_endpoint.sort();
+        assert_eq!(
+            per_endpoint,
+            vec![
+                (None, 1),
+                (Some("203.0.113.10".to_string()), 6),
+                (Some("203.0.113.20".to_string()), 3),
+            ]
+        );
+    }
+
+    fn traffic(ip: &str, packets_in: u64, first: i64, last: i64) -> IpTraffic {
+        IpTraffic {
+            ip: ip.to_string(),
+            direction: Direction::Outbound,
+            packets_in,
+            packets_out: 1,
+            bytes_in: packets_in * 100,
+            bytes_out: 100,
+            first_seen: at(first),
+            last_seen: at(last),
+        }
+    }
+
+    fn locate(db: &Database, ip: &str, country_code: &str, fetched_at: i64) {
+        db.update_geo_info(ip, None, None, None, Some(country_code), None, None, None).unwrap();
+        let info = IpInfoResponse { country_code: Some(country_code.to_string()), ..Default::default() };
+        db.put_geo_cache(ip, &GeoCacheEntry { result: Ok(info), fetched_at: at(fetched_at) }).unwrap();
+    }
+
+    fn connection(db: &Database, ip: &str) -> IpConnection {
+        db.get_connections(10).unwrap().into_iter().find(|c| c.ip == ip).unwrap()
+    }
+
+    #[test]
+    fn merging_sums_counters_widens_times_and_labels_connections() {
+        let main = TempDb::new("merge-main");
+        main.db.record_traffic(&[traffic("1.1.1.1", 10, 100, 200)], None).unwrap();
+        locate(&main.db, "1.1.1.1", "US", 0);
+        main.db.record_flows(&[flow(100, 110, 4)], None).unwrap();
+
+        let laptop = TempDb::new("merge-laptop");
+        laptop.db.record_traffic(&[traffic("1.1.1.1", 3, 50, 300), traffic("8.8.8.8", 1, 60, 70)], None).unwrap();
+        locate(&laptop.db, "1.1.1.1", "AU", 1000);
+        laptop.db.record_flows(&[flow(100, 110, 2)], None).unwrap();
+
+        let counts = main.db.merge_from(&laptop.dir.join("snifff.db"), "laptop").unwrap();
+        assert_eq!((counts.connections, counts.new_connections, counts.flows), (2, 1, 1));
+
+        let merged = connection(&main.db, "1.1.1.1");
+        assert_eq!(merged.hit_count, 2);
+        assert_eq!(merged.packet_count, 11 + 4);
+        assert_eq!(merged.byte_count, 1100 + 400);
+        assert_eq!(merged.first_seen, at(50).to_rfc3339());
+        assert_eq!(merged.last_seen, at(300).to_rfc3339());
+        assert_eq!(merged.country_code.as_deref(), Some("AU"), "the newer lookup wins");
+        assert_eq!(merged.source_host.as_deref(), Some("laptop"));
+        assert_eq!(connection(&main.db, "8.8.8.8").source_host.as_deref(), Some("laptop"));
+        let mut packets = flow_packets(&main.db);
+        packets.sort_unstable();
+        assert_eq!(packets, vec![2, 4], "a merged flow is another connection");
+
+        // A stale lookup doesn't overwrite a newer one
+        let desktop = TempDb::new("merge-desktop");
+        desktop.db.record_traffic(&[traffic("1.1.1.1", 1, 150, 160)], None).unwrap();
+        locate(&desktop.db, "1.1.1.1", "NZ", 500);
+        main.db.merge_from(&desktop.dir.join("snifff.db"), "desktop").unwrap();
+
+        let merged = connection(&main.db, "1.1.1.1");
+        assert_eq!(merged.packet_count, 15 + 2);
+        assert_eq!(merged.country_code.as_deref(), Some("AU"));
+        assert_eq!(merged.source_host.as_deref(), Some("laptop,desktop"));
+    }
+
+    #[test]
+    fn ndjson_imports_sum_repeated_ips_and_keep_their_labels() {
+        let main = TempDb::new("ndjson-main");
+        main.db.record_traffic(&[traffic("1.1.1.1", 10, 100, 200)], None).unwrap();
+
+        // An export of a database that was itself merged from two hosts
+        let fleet = TempDb::new("ndjson-fleet");
+        fleet.db.record_traffic(&[traffic("1.1.1.1", 3, 50, 300), traffic("8.8.8.8", 1, 60, 70)], None).unwrap();
+        let line = |ip: &str, source_host: Option<&str>| {
+            let c = IpConnection { source_host: source_host.map(str::to_string), ip_class: None, ..connection(&fleet.db, ip) };
+            serde_json::to_string(&c).unwrap()
+        };
+        let export = fleet.dir.join("fleet.ndjson");
+        let lines = [line("1.1.1.1", Some("laptop")), line("1.1.1.1", Some("desktop")), line("8.8.8.8", None)];
+        std::fs::write(&export, lines.join("\n")).unwrap();
+
+        let summary = crate::merge::import(&main.db, &export, None).unwrap();
+        assert_eq!((summary.connections, summary.new_connections), (2, 1));
+
+        let merged = connection(&main.db, "1.1.1.1");
+        assert_eq!(merged.packet_count, 11 + 4 + 4, "both lines are summed");
+        assert_eq!((merged.first_seen, merged.last_seen), (at(50).to_rfc3339(), at(300).to_rfc3339()));
+        assert_eq!(merged.source_host.as_deref(), Some("laptop,desktop"));
+        let unlabelled = connection(&main.db, "8.8.8.8");
+        assert_eq!(unlabelled.source_host.as_deref(), Some("fleet"));
+        assert_eq!(unlabelled.ip_class.as_deref(), Some("public"));
+
+        // The hosts inside the export count as merged too
+        let laptop = fleet.dir.join("laptop-again.ndjson");
+        std::fs::write(&laptop, line("8.8.8.8", Some("laptop"))).unwrap();
+        let err = crate::merge::import(&main.db, &laptop, None).unwrap_err();
+        assert!(err.contains("\"laptop\""), "{}", err);
+        assert_eq!(connection(&main.db, "8.8.8.8").packet_count, 2);
+    }
+
+    #[test]
+    fn a_source_host_is_only_merged_once() {
+        let main = TempDb::new("remerge-main");
+        let laptop = TempDb::new("remerge-laptop");
+        laptop.db.record_traffic(&[traffic("1.1.1.1", 3, 50, 300)], None).unwrap();
+        let source = laptop.dir.join("snifff.db");
+
+        main.db.merge_from(&source, "laptop").unwrap();
+        let err = main.db.merge_from(&source, "laptop").unwrap_err();
+        assert!(err.contains("already been merged"), "{}", err);
+        assert_eq!(connection(&main.db, "1.1.1.1").packet_count, 4);
+
+        // Nor can it come back inside another host's database
+        let desktop = TempDb::new("remerge-desktop");
+        desktop.db.merge_from(&source, "laptop").unwrap();
+        let err = main.db.merge_from(&desktop.dir.join("snifff.db"), "desktop").unwrap_err();
+        assert!(err.contains("\"laptop\""), "{}", err);
+        assert_eq!(connection(&main.db, "1.1.1.1").packet_count, 4);
+    }
+
+    fn set_asn(db: &Database, ip: &str, asn: Option<&str>, as_name: Option<&str>) {
+        db.update_geo_info(ip, asn, as_name, None, None, None, None, None).unwrap();
+    }
+
+    /// IPs matching `query`, in IP order
+    fn matching(db: &Database, query: ConnectionQuery) -> Vec<String> {
+        let query = ConnectionQuery { sort: ConnectionSort::Ip, order: SortOrder::Asc, ..query };
+        db.query_connections(&query).unwrap().connections.into_iter().map(|c| c.ip).collect()
+    }
+
+    /// Every IP `query` reaches by following cursors four rows at a time
+    fn walk(db: &Database, query: &ConnectionQuery) -> Vec<String> {
+        let mut ips = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = db
+                .query_page_uncounted(&ConnectionQuery { cursor, limit: Some(4), ..query.clone() })
+                .unwrap();
+            ips.extend(page.connections.into_iter().map(|c| c.ip));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return ips,
+            }
+        }
+    }
+
+    #[test]
+    fn cursors_walk_every_match_once_in_order() {
+        let t = TempDb::new("cursor");
+        for i in 0..11 {
+            let ip = format!("198.51.100.{}", i);
+            // Repeated last_seen and packet counts, so ties are broken by IP
+            t.db.record_traffic(&[traffic(&ip, i % 3, 0, i as i64 / 2 * 10)], None).unwrap();
+            // Some without an ASN, sorted as ''
+            if i % 3 != 0 {
+                set_asn(&t.db, &ip, Some(&format!("AS{}", i % 2)), None);
+            }
+        }
+
+        let sorts = [ConnectionSort::LastSeen, ConnectionSort::PacketCount, ConnectionSort::Asn, ConnectionSort::Ip];
+        for sort in sorts {
+            for order in [SortOrder::Asc, SortOrder::Desc] {
+                let query = ConnectionQuery { sort, order, ..Default::default() };
+                let all: Vec<String> = t.db
+                    .query_connections(&ConnectionQuery { limit: Some(100), ..query.clone() })
+                    .unwrap()
+                    .connections
+                    .into_iter()
+                    .map(|c| c.ip)
+                    .collect();
+                assert_eq!(all.iter().collect::<HashSet<_>>().len(), 11);
+                assert_eq!(walk(&t.db, &query), all, "{:?} {:?}", sort, order);
+            }
+        }
+
+        let query = ConnectionQuery { sort: ConnectionSort::Asn, limit: Some(4), ..Default::default() };
+        let first = t.db.query_connections(&query).unwrap();
+        assert_eq!((first.total, first.connections.len()), (11, 4));
+        let ips = |query: ConnectionQuery| -> Vec<String> {
+            t.db.query_connections(&query).unwrap().connections.into_iter().map(|c| c.ip).collect()
+        };
+        let second = ips(ConnectionQuery { cursor: first.next_cursor.clone(), ..query.clone() });
+        assert_eq!(ips(ConnectionQuery { offset: Some(4), ..query.clone() }), second);
+        assert_eq!(
+            ips(ConnectionQuery { cursor: first.next_cursor.clone(), offset: Some(4), ..query.clone() }),
+            second,
+            "a cursor takes precedence over the offset"
+        );
+        assert!(t.db.query_connections(&ConnectionQuery { cursor: Some("[1]".to_string()), ..query }).is_err());
+    }
+
+    #[test]
+    fn ip_filters_match_cidr_blocks_and_searches_match_wildcards_literally() {
+        let t = TempDb::new("filters");
+        let ips = ["1.1.1.1", "1.1.1.254", "1.1.2.1", "2606:4700::1111", "2606:4701::1"];
+        t.db.record_traffic(&ips.map(|ip| traffic(ip, 1, 0, 10)), None).unwrap();
+        set_asn(&t.db, "1.1.2.1", Some("AS13335"), Some("100% Transit"));
+        set_asn(&t.db, "2606:4701::1", Some("AS13335"), Some("edge_net"));
+
+        let by_ip = |ip: &str| matching(&t.db, ConnectionQuery { ip: Some(ip.to_string()), ..Default::default() });
+        assert_eq!(by_ip("1.1.1.0/24"), vec!["1.1.1.1", "1.1.1.254"]);
+        assert_eq!(by_ip("1.1.1.1"), vec!["1.1.1.1"]);
+        assert_eq!(by_ip("1.1.0.0/16"), vec!["1.1.1.1", "1.1.1.254", "1.1.2.1"]);
+        assert_eq!(by_ip("2606:4700::/32"), vec!["2606:4700::1111"]);
+        assert!(t.db.query_connections(&ConnectionQuery { ip: Some("1.1.1.0/33".to_string()), ..Default::default() }).is_err());
+
+        let search = |text: &str| matching(&t.db, ConnectionQuery { search: Some(text.to_string()), ..Default::default() });
+        assert_eq!(search("%"), vec!["1.1.2.1"]);
+        assert_eq!(search("_"), vec!["2606:4701::1"]);
+        assert_eq!(search("1.1.1"), vec!["1.1.1.1", "1.1.1.254"]);
+        assert_eq!(search("TRANSIT"), vec!["1.1.2.1"]);
+        let by_asn = matching(&t.db, ConnectionQuery { asn: Some("AS13335".to_string()), ..Default::default() });
+        assert_eq!(by_asn, vec!["1.1.2.1", "2606:4701::1"]);
+    }
+
+    #[test]
+    fn time_bounds_and_min_hits_narrow_the_total() {
+        let t = TempDb::new("bounds");
+        t.db.record_traffic(
+            &[traffic("1.1.1.1", 1, 0, 100), traffic("2.2.2.2", 1, 200, 300), traffic("3.3.3.3", 1, 400, 500)],
+            None,
+        ).unwrap();
+        // Seen again in a later capture session
+        t.db.upsert_connection("3.3.3.3", at(600)).unwrap();
+
+        let time = |secs: i64| Some(at(secs).to_rfc3339());
+        let between = |since, until| matching(&t.db, ConnectionQuery { since, until, ..Default::default() });
+        assert_eq!(between(time(250), None), vec!["2.2.2.2", "3.3.3.3"]);
+        assert_eq!(between(None, time(250)), vec!["1.1.1.1", "2.2.2.2"]);
+        assert_eq!(between(time(150), time(250)), vec!["2.2.2.2"], "active across the whole range");
+        assert!(t.db.query_connections(&ConnectionQuery { since: Some("yesterday".to_string()), ..Default::default() }).is_err());
+
+        let min_hits = matching(&t.db, ConnectionQuery { min_hits: Some(2), ..Default::default() });
+        assert_eq!(min_hits, vec!["3.3.3.3"]);
+
+        let query = ConnectionQuery { since: time(50), limit: Some(2), ..Default::default() };
+        let page = t.db.query_connections(&query).unwrap();
+        assert_eq!((page.total, page.connections.len()), (3, 2));
+        let last = t.db.query_connections(&ConnectionQuery { cursor: page.next_cursor, ..query.clone() }).unwrap();
+        assert_eq!((last.total, last.connections.len(), last.next_cursor), (3, 1, None));
+        assert_eq!(t.db.query_page_uncounted(&query).unwrap().total, 0, "not counted");
+    }
+
+    /// Start of an hour-aligned test period plus `secs`
+    fn hour_aligned(secs: i64) -> DateTime<Utc> {
+        let start = bucket_start(at(0), HOUR_BUCKET_SECS) + HOUR_BUCKET_SECS;
+        Utc.timestamp_opt(start + secs, 0).unwrap()
+    }
+
+    /// (resolution, bucket start relative to `hour_aligned(0)`, packets in) of every bucket
+    fn buckets(db: &Database) -> Vec<(i64, i64, i64)> {
+        let start = hour_aligned(0).timestamp();
+        let conn = db.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT resolution, bucket_start, packets_in FROM traffic_buckets ORDER BY resolution, bucket_start")
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? - start, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn rollups_fold_minutes_into_hours_exactly_once() {
+        let t = TempDb::new("rollup");
+        let record = |minute: i64, packets_in: u64| {
+            let seen = hour_aligned(minute * 60);
+            let traffic = IpTraffic { first_seen: seen, last_seen: seen, ..traffic("1.1.1.1", packets_in, 0, 0) };
+            t.db.record_traffic(&[traffic], None).unwrap();
+        };
+        // Three hours of minutes, and a second batch landing in some buckets
+        for minute in 0..180 {
+            record(minute, minute as u64 % 5 + 1);
+        }
+        record(30, 100);
+
+        // Rounded down to the hour, so only the first hour goes
+        assert_eq!(t.db.rollup_timeseries(hour_aligned(5400)).unwrap(), 60);
+        // The second covers the first again, with nothing left there to add
+        assert_eq!(t.db.rollup_timeseries(hour_aligned(9000)).unwrap(), 60);
+        assert_eq!(t.db.rollup_timeseries(hour_aligned(9000)).unwrap(), 0);
+        let hourly: Vec<_> = buckets(&t.db).into_iter().filter(|b| b.0 == HOUR_BUCKET_SECS).collect();
+        assert_eq!(hourly, vec![(3600, 0, 280), (3600, 3600, 180)]);
+        assert_eq!(buckets(&t.db).len(), 2 + 60, "the third hour's minutes are kept");
+
+        // Late minutes for an hour already rolled up are added to it
+        record(10, 7);
+        assert_eq!(t.db.rollup_timeseries(hour_aligned(10800)).unwrap(), 61);
+        assert_eq!(buckets(&t.db), vec![(3600, 0, 280 + 7), (3600, 3600, 180), (3600, 7200, 180)]);
+
+        let series = t.db.get_timeseries(&TimeSeriesQuery {
+            start: hour_aligned(0).to_rfc3339(),
+            end: hour_aligned(10800).to_rfc3339(),
+            group_by: TimeSeriesGroup::Ip,
+            keys: None,
+            step_secs: Some(3600),
+            limit: None,
+        }).unwrap();
+        let points: Vec<u64> = series.series[0].points.iter().map(|p| p.packets_in).collect();
+        assert_eq!(points, vec![287, 180, 180]);
+    }
+
+    #[test]
+    fn time_series_group_by_asn_and_country_and_filter_by_key() {
+        let t = TempDb::new("timeseries");
+        let record = |ip: &str, packets_in: u64, minute: i64| {
+            let seen = hour_aligned(minute * 60);
+            let traffic = IpTraffic { first_seen: seen, last_seen: seen, ..traffic(ip, packets_in, 0, 0) };
+            t.db.record_traffic(&[traffic], None).unwrap();
+        };
+        record("1.1.1.1", 10, 0);
+        record("1.0.0.1", 5, 1);
+        record("8.8.8.8", 3, 0);
+        record("9.9.9.9", 1, 2);
+        for (ip, asn, as_name, cc, country) in [
+            ("1.1.1.1", "AS13335", "Cloudflare", "AU", "Australia"),
+            ("1.0.0.1", "AS13335", "Cloudflare", "US", "United States"),
+            ("8.8.8.8", "AS15169", "Google", "US", "United States"),
+        ] {
+            t.db.update_geo_info(ip, Some(asn), Some(as_name), None, Some(cc), Some(country), None, None).unwrap();
+        }
+
+        let query = |group_by, keys: Option<&[&str]>| TimeSeriesQuery {
+            start: hour_aligned(0).to_rfc3339(),
+            end: hour_aligned(600).to_rfc3339(),
+            group_by,
+            keys: keys.map(|k| k.iter().map(|k| k.to_string()).collect()),
+            step_secs: Some(300),
+            limit: None,
+        };
+        let summary = |result: TimeSeriesResult| -> Vec<(String, Option<String>, u64)> {
+            result.series.into_iter().map(|s| (s.key, s.label, s.total_packets)).collect()
+        };
+
+        let by_asn = t.db.get_timeseries(&query(TimeSeriesGroup::Asn, None)).unwrap();
+        assert_eq!(by_asn.step_secs, 300);
+        assert_eq!(by_asn.series[0].points.len(), 1, "minutes 0-2 share one 5-minute point");
+        assert_eq!(
+            summary(by_asn),
+            vec![
+                ("AS13335".to_string(), Some("Cloudflare".to_string()), 17),
+                ("AS15169".to_string(), Some("Google".to_string()), 4),
+                ("unknown".to_string(), None, 2),
+            ]
+        );
+
+        let by_country = t.db.get_timeseries(&query(TimeSeriesGroup::Country, Some(&["US"]))).unwrap();
+        assert_eq!(summary(by_country), vec![("US".to_string(), Some("United States".to_string()), 10)]);
+        let by_asn = t.db.get_timeseries(&query(TimeSeriesGroup::Asn, Some(&["AS15169", "unknown"]))).unwrap();
+        assert_eq!(by_asn.series.iter().map(|s| s.key.as_str()).collect::<Vec<_>>(), vec!["AS15169", "unknown"]);
+
+        let backwards = TimeSeriesQuery { end: hour_aligned(0).to_rfc3339(), ..query(TimeSeriesGroup::Ip, None) };
+        assert!(t.db.get_timeseries(&backwards).is_err());
+        let empty = TimeSeriesQuery { start: hour_aligned(0).to_rfc3339(), ..backwards };
+        assert!(t.db.get_timeseries(&empty).is_err());
+    }
+
+    #[test]
+    fn automatic_steps_keep_ranges_near_the_target_point_count() {
+        assert_eq!(auto_step(3600), 60);
+        assert_eq!(auto_step(300 * 60), 60);
+        assert_eq!(auto_step(300 * 60 + 60), 300);
+        assert_eq!(auto_step(7 * 86400), 3600);
+        assert_eq!(auto_step(10 * 365 * 86400), 86400);
+    }
 }
@@ -0,0 +1,350 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_NXDOMAIN: u8 = 3;
+
+/// Compression pointers followed before a name is treated as a loop
+const MAX_POINTER_HOPS: usize = 32;
+
+/// The parts of a DNS message SNIFFF cares about
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub id: u16,
+    pub is_response: bool,
+    pub rcode: u8,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ptr(String),
+    /// Any other record type, kept only so counts line up
+    Other(u16),
+}
+
+/// Encode a recursive query for `name`
+pub fn build_query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut out = Vec::with_capacity(18 + name.len());
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&0x0100u16.to_be_bytes()); // RD
+    out.extend_from_slice(&1u16.to_be_bytes()); // QDCOUNT
+    out.extend_from_slice(&[0; 6]); // AN/NS/AR counts
+    for label in name.trim_end_matches('.').split('.').filter(|l| !l.is_empty()) {
+        out.push(label.len().min(63) as u8);
+        out.extend_from_slice(&label.as_bytes()[..label.len().min(63)]);
+    }
+    out.push(0);
+    out.extend_from_slice(&qtype.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    out
+}
+
+/// The `in-addr.arpa` / `ip6.arpa` name a PTR query for `ip` asks about
+pub fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(v6) => {
+            let mut name = String::with_capacity(72);
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// Decode a DNS message. Authority and additional records are skipped.
+pub fn parse(buf: &[u8]) -> Result<Message, String> {
+    let mut reader = Reader { buf, pos: 0 };
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    let qdcount = reader.u16()?;
+    let ancount = reader.u16()?;
+    reader.skip(4)?;
+
+    let mut questions = Vec::with_capacity(qdcount.min(16) as usize);
+    for _ in 0..qdcount {
+        let name = reader.name()?;
+        let qtype = reader.u16()?;
+        reader.skip(2)?;
+        questions.push(Question { name, qtype });
+    }
+
+    let mut answers = Vec::with_capacity(ancount.min(64) as usize);
+    for _ in 0..ancount {
+        answers.push(reader.record()?);
+    }
+
+    Ok(Message {
+        id,
+        is_response: flags & 0x8000 != 0,
+        rcode: (flags & 0x000f) as u8,
+        questions,
+        answers,
+    })
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| "Truncated DNS message".to_string())?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), String> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Read a possibly compressed name at the current position
+    fn name(&mut self) -> Result<String, String> {
+        let (name, end) = read_name(self.buf, self.pos)?;
+        self.pos = end;
+        Ok(name)
+    }
+
+    fn record(&mut self) -> Result<Record, String> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        self.skip(2)?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        let start = self.pos;
+        let rdata = self.take(len)?;
+
+        let data = match (rtype, len) {
+            (TYPE_A, 4) => RecordData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                RecordData::Aaaa(Ipv6Addr::from(octets))
+            }
+            // Names inside rdata may point back into the rest of the message
+            (TYPE_CNAME, _) => RecordData::Cname(read_name(self.buf, start)?.0),
+            (TYPE_PTR, _) => RecordData::Ptr(read_name(self.buf, start)?.0),
+            (other, _) => RecordData::Other(other),
+        };
+
+        Ok(Record { name, ttl, data })
+    }
+}
+
+/// Read the name at `pos`, returning it without a trailing dot along with the
+/// offset just past it in the original (uncompressed) position
+fn read_name(buf: &[u8], mut pos: usize) -> Result<(String, usize), String> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    let mut hops = 0;
+
+    loop {
+        let len = *buf.get(pos).ok_or("Truncated DNS name")? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => {
+                end.get_or_insert(pos + 1);
+                break;
+            }
+            0x00 => {
+                let label = buf.get(pos + 1..pos + 1 + len).ok_or("Truncated DNS label")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+            0xc0 => {
+                let low = *buf.get(pos + 1).ok_or("Truncated DNS pointer")? as usize;
+                end.get_or_insert(pos + 2);
+                hops += 1;
+                if hops > MAX_POINTER_HOPS {
+                    return Err("DNS name compression loop".to_string());
+                }
+                pos = ((len & 0x3f) << 8) | low;
+            }
+            _ => return Err("Unsupported DNS label type".to_string()),
+        }
+    }
+
+    Ok((labels.join(".").to_ascii_lowercase(), end.unwrap_or(pos)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(flags: u16, qdcount: u16, ancount: u16) -> Vec<u8> {
+        let mut out = vec![0x12, 0x34];
+        for field in [flags, qdcount, ancount, 0, 0] {
+            out.extend_from_slice(&field.to_be_bytes());
+        }
+        out
+    }
+
+    fn labels(name: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        for label in name.split('.') {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+        out.push(0);
+        out
+    }
+
+    fn question(name: &[u8], qtype: u16) -> Vec<u8> {
+        [name, &qtype.to_be_bytes(), &CLASS_IN.to_be_bytes()].concat()
+    }
+
+    fn record(name: &[u8], rtype: u16, ttl: u32, rdata: &[u8]) -> Vec<u8> {
+        [
+            name,
+            &rtype.to_be_bytes(),
+            &CLASS_IN.to_be_bytes(),
+            &ttl.to_be_bytes(),
+            &(rdata.len() as u16).to_be_bytes(),
+            rdata,
+        ]
+        .concat()
+    }
+
+    /// `WWW.Example.com` → CNAME `cdn.example.com` → A and AAAA, every name
+    /// after the question compressed
+    fn cname_chain() -> Vec<u8> {
+        let mut msg = header(0x8180, 1, 3);
+        msg.extend(question(&labels("WWW.Example.com"), TYPE_A));
+        // The question's "example.com" starts at 16; this rdata at 45
+        msg.extend(record(&[0xc0, 12], TYPE_CNAME, 300, &[3, b'c', b'd', b'n', 0xc0, 16]));
+        msg.extend(record(&[0xc0, 45], TYPE_A, 60, &[93, 184, 216, 34]));
+        msg.extend(record(&[0xc0, 45], TYPE_AAAA, 60, &"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets()));
+        msg
+    }
+
+    #[test]
+    fn parses_compressed_answers() {
+        let msg = parse(&cname_chain()).unwrap();
+        assert_eq!(msg.id, 0x1234);
+        assert!(msg.is_response);
+        assert_eq!(msg.rcode, RCODE_NOERROR);
+        assert_eq!(msg.questions, vec![Question { name: "www.example.com".to_string(), qtype: TYPE_A }]);
+        assert_eq!(
+            msg.answers,
+            vec![
+                Record {
+                    name: "www.example.com".to_string(),
+                    ttl: 300,
+                    data: RecordData::Cname("cdn.example.com".to_string()),
+                },
+                Record {
+                    name: "cdn.example.com".to_string(),
+                    ttl: 60,
+                    data: RecordData::A(Ipv4Addr::new(93, 184, 216, 34)),
+                },
+                Record {
+                    name: "cdn.example.com".to_string(),
+                    ttl: 60,
+                    data: RecordData::Aaaa("2001:db8::1".parse().unwrap()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn query_round_trips() {
+        let msg = parse(&build_query(7, "example.com.", TYPE_PTR)).unwrap();
+        assert!(!msg.is_response);
+        assert_eq!(msg.questions, vec![Question { name: "example.com".to_string(), qtype: TYPE_PTR }]);
+        assert!(msg.answers.is_empty());
+    }
+
+    #[test]
+    fn malformed_messages_are_errors() {
+        let chain = cname_chain();
+        let cases: Vec<(&str, Vec<u8>, &str)> = vec![
+            ("short header", vec![0x12, 0x34, 0x81], "Truncated DNS message"),
+            (
+                "pointer to itself",
+                [header(0x8180, 1, 0), vec![0xc0, 12]].concat(),
+                "DNS name compression loop",
+            ),
+            (
+                "two pointers to each other",
+                [header(0x8180, 1, 0), vec![0xc0, 14, 0xc0, 12]].concat(),
+                "DNS name compression loop",
+            ),
+            (
+                "pointer past the end",
+                [header(0x8180, 1, 0), vec![0xc0, 99]].concat(),
+                "Truncated DNS name",
+            ),
+            (
+                "label longer than the message",
+                [header(0x8180, 1, 0), vec![7, b'e', b'x']].concat(),
+                "Truncated DNS label",
+            ),
+            (
+                "reserved label type",
+                [header(0x8180, 1, 0), vec![0x40, 0]].concat(),
+                "Unsupported DNS label type",
+            ),
+            (
+                "A record cut short",
+                [header(0x8180, 0, 1), record(&[0], TYPE_A, 60, &[93, 184, 216, 34])].concat()
+                    [..12 + 11 + 2]
+                    .to_vec(),
+                "Truncated DNS message",
+            ),
+            ("answer missing", chain[..chain.len() - 28].to_vec(), "Truncated DNS name"),
+        ];
+
+        for (case, data, expected) in cases {
+            match parse(&data) {
+                Err(e) => assert_eq!(e, expected, "{}", case),
+                Ok(msg) => panic!("{}: parsed {:?}", case, msg),
+            }
+        }
+    }
+
+    #[test]
+    fn odd_sized_addresses_are_not_addresses() {
+        let msg = [header(0x8180, 0, 1), record(&[0], TYPE_A, 60, &[1, 2, 3])].concat();
+        assert_eq!(parse(&msg).unwrap().answers[0].data, RecordData::Other(TYPE_A));
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cidr::Cidr;
+use crate::sniffer::CaptureBatch;
+
+/// Remote addresses the sniffer should never record, e.g. our own VPN
+/// concentrator
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExclusionList {
+    /// Addresses or CIDR blocks, e.g. `203.0.113.7` or `2001:db8::/32`
+    #[serde(default)]
+    pub networks: Vec<String>,
+    /// AS numbers, e.g. `AS13335` or `13335`
+    #[serde(default)]
+    pub asns: Vec<String>,
+}
+
+impl ExclusionList {
+    /// Read `CAPTURE_EXCLUDE_NETWORKS` and `CAPTURE_EXCLUDE_ASNS`, both comma-separated
+    pub fn from_env() -> Self {
+        let list = |name: &str| -> Vec<String> {
+            std::env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        Self {
+            networks: list("CAPTURE_EXCLUDE_NETWORKS"),
+            asns: list("CAPTURE_EXCLUDE_ASNS"),
+        }
+    }
+}
+
+/// Normalize `13335`, `as13335` etc. to the `AS13335` form geo lookups use
+fn normalize_asn(asn: &str) -> Result<String, String> {
+    let s = asn.trim();
+    let digits = match s.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("as") => &s[2..],
+        _ => s,
+    };
+    match digits.parse::<u32>() {
+        Ok(n) => Ok(format!("AS{}", n)),
+        Err(_) => Err(format!("Invalid ASN: {}", asn)),
+    }
+}
+
+#[derive(Default)]
+struct Compiled {
+    list: ExclusionList,
+    networks: Vec<Cidr>,
+    asns: HashSet<String>,
+    /// Addresses known to belong to an excluded ASN
+    asn_ips: HashSet<IpAddr>,
+}
+
+impl Compiled {
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.asn_ips.contains(ip) || self.networks.iter().any(|n| n.contains(ip))
+    }
+
+    fn contains_str(&self, ip: &str) -> bool {
+        ip.parse::<IpAddr>().is_ok_and(|ip| self.contains(&ip))
+    }
+}
+
+/// The active exclusion list, shared by the capture threads and the commands
+/// that change it. ASNs only match once geo data has tied an address to one.
+#[derive(Default)]
+pub struct Exclusions {
+    inner: RwLock<Compiled>,
+}
+
+impl Exclusions {
+    /// Validate and install `list`, returning it normalized. Addresses learned
+    /// for the previous ASNs are forgotten; seed new ones with `add_asn_ips`.
+    pub fn set(&self, list: ExclusionList) -> Result<ExclusionList, String> {
+        let entries: Vec<String> = list
+            .networks
+            .iter()
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .collect();
+        let networks = entries
+            .iter()
+            .map(|n| n.parse::<Cidr>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut asns: Vec<String> = list
+            .asns
+            .iter()
+            .filter(|a| !a.trim().is_empty())
+            .map(|a| normalize_asn(a))
+            .collect::<Result<_, _>>()?;
+        asns.sort();
+        asns.dedup();
+
+        let list = ExclusionList {
+            networks: entries,
+            asns: asns.clone(),
+        };
+        let mut inner = self.inner.write().map_err(|e| e.to_string())?;
+        *inner = Compiled {
+            list: list.clone(),
+            networks,
+            asns: asns.into_iter().collect(),
+            asn_ips: HashSet::new(),
+        };
+        Ok(list)
+    }
+
+    pub fn get(&self) -> ExclusionList {
+        self.inner.read().map(|i| i.list.clone()).unwrap_or_default()
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.inner.read().is_ok_and(|i| i.contains(ip))
+    }
+
+    pub fn excludes_asn(&self, asn: &str) -> bool {
+        self.inner.read().is_ok_and(|i| i.asns.contains(asn))
+    }
+
+    /// Start dropping these addresses, which belong to an excluded ASN
+    pub fn add_asn_ips<I: IntoIterator<Item = String>>(&self, ips: I) {
+        if let Ok(mut inner) = self.inner.write() {
+            inner.asn_ips.extend(ips.into_iter().filter_map(|ip| ip.parse::<IpAddr>().ok()));
+        }
+    }
+
+    /// Drop everything in `batch` that concerns an excluded address
+    pub fn retain(&self, batch: &mut CaptureBatch) {
+        let Ok(inner) = self.inner.read() else {
+            return;
+        };
+        if inner.networks.is_empty() && inner.asn_ips.is_empty() {
+            return;
+        }
+        batch.ips.retain(|t| !inner.contains_str(&t.ip));
+        batch.flows.retain(|f| !inner.contains_str(&f.remote_ip));
+        batch.dns.retain(|a| !inner.contains_str(&a.ip));
+        batch.server_names.retain(|n| !inner.contains_str(&n.dst_ip));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sniffer::{Direction, DnsAnswer, IpTraffic};
+    use chrono::Utc;
+
+    fn list(networks: &[&str], asns: &[&str]) -> ExclusionList {
+        ExclusionList {
+            networks: networks.iter().map(|n| n.to_string()).collect(),
+            asns: asns.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn excludes(exclusions: &Exclusions, ip: &str) -> bool {
+        exclusions.contains(&ip.parse().unwrap())
+    }
+
+    #[test]
+    fn asns_are_normalized() {
+        let cases = [
+            ("13335", Ok("AS13335")),
+            ("AS13335", Ok("AS13335")),
+            ("as13335", Ok("AS13335")),
+            (" As0013335 ", Ok("AS13335")),
+            ("AS", Err("Invalid ASN: AS")),
+            ("AS-1", Err("Invalid ASN: AS-1")),
+            ("AS4294967296", Err("Invalid ASN: AS4294967296")),
+            ("Cloudflare", Err("Invalid ASN: Cloudflare")),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(normalize_asn(input), expected.map(str::to_string).map_err(str::to_string), "{}", input);
+        }
+    }
+
+    #[test]
+    fn set_validates_and_normalizes_the_list() {
+        let exclusions = Exclusions::default();
+        let installed = exclusions
+            .set(list(&[" 203.0.113.7 ", "", "2001:db8::/32"], &["as15169", "13335", " ", "AS13335"]))
+            .unwrap();
+        assert_eq!(installed, list(&["203.0.113.7", "2001:db8::/32"], &["AS13335", "AS15169"]));
+        assert_eq!(exclusions.get(), installed);
+
+        let before = exclusions.get();
+        for bad in [list(&["10.0.0.0/33"], &[]), list(&["vpn.example.com"], &[]), list(&[], &["AS13335x"])] {
+            assert!(exclusions.set(bad.clone()).is_err(), "{:?}", bad);
+            assert_eq!(exclusions.get(), before, "a rejected list leaves the old one in place");
+        }
+    }
+
+    #[test]
+    fn matches_networks_and_addresses_learned_for_asns() {
+        let exclusions = Exclusions::default();
+        exclusions.set(list(&["203.0.113.0/24", "2001:db8::1"], &["13335"])).unwrap();
+
+        let cases = [
+            ("203.0.113.7", true),
+            ("::ffff:203.0.113.7", true),
+            ("203.0.114.7", false),
+            ("2001:db8::1", true),
+            ("2001:db8::2", false),
+            ("1.1.1.1", false),
+        ];
+        for (ip, expected) in cases {
+            assert_eq!(excludes(&exclusions, ip), expected, "{}", ip);
+        }
+
+        assert!(exclusions.excludes_asn("AS13335"));
+        assert!(!exclusions.excludes_asn("AS15169"));
+        exclusions.add_asn_ips(["1.1.1.1".to_string(), "not an ip".to_string()]);
+        assert!(excludes(&exclusions, "1.1.1.1"));
+        assert!(!excludes(&exclusions, "1.0.0.1"));
+
+        // A new list forgets what the old ASNs covered
+        exclusions.set(list(&[], &["13335"])).unwrap();
+        assert!(!excludes(&exclusions, "1.1.1.1"));
+        assert!(!excludes(&exclusions, "203.0.113.7"));
+    }
+
+    #[test]
+    fn retain_drops_excluded_addresses_from_a_batch() {
+        let exclusions = Exclusions::default();
+        exclusions.set(list(&["10.8.0.0/16"], &[])).unwrap();
+
+        let now = Utc::now();
+        let traffic = |ip: &str| IpTraffic {
+            ip: ip.to_string(),
+            direction: Direction::Outbound,
+            packets_in: 1,
+            packets_out: 1,
+            bytes_in: 60,
+            bytes_out: 60,
+            first_seen: now,
+            last_seen: now,
+        };
+        let answer = |ip: &str| DnsAnswer { domain: "vpn.example.com".to_string(), ip: ip.to_string(), ttl: 60, seen_at: now };
+        let mut batch = CaptureBatch {
+            ips: vec![traffic("10.8.0.1"), traffic("1.1.1.1")],
+            dns: vec![answer("10.8.3.4"), answer("1.0.0.1")],
+            ..Default::default()
+        };
+
+        exclusions.retain(&mut batch);
+        assert_eq!(batch.ips.iter().map(|t| t.ip.as_str()).collect::<Vec<_>>(), vec!["1.1.1.1"]);
+        assert_eq!(batch.dns.iter().map(|a| a.ip.as_str()).collect::<Vec<_>>(), vec!["1.0.0.1"]);
+    }
+}
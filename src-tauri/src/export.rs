@@ -0,0 +1,354 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{ConnectionQuery, ConnectionSort, CountryStats, Database, IpConnection, SortOrder};
+
+/// Connections fetched per query while exporting; the DB lock is released between pages
+const EXPORT_PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON array
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportDataset {
+    Connections,
+    CountryStats,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportRequest {
+    pub path: String,
+    pub format: ExportFormat,
+    pub dataset: ExportDataset,
+    /// Filters. Connections are always exported in IP order, so rows updated
+    /// mid-export can't move past the cursor; `sort`, `order`, `limit`,
+    /// `offset` and `cursor` are ignored.
+    #[serde(default)]
+    pub query: ConnectionQuery,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportSummary {
+    pub path: String,
+    pub rows: u64,
+}
+
+/// A row type that can be written as CSV
+trait CsvRow: Serialize {
+    const HEADER: &'static [&'static str];
+    fn csv_fields(&self) -> Vec<String>;
+}
+
+impl CsvRow for IpConnection {
+    const HEADER: &'static [&'static str] = &[
+        "ip", "asn", "as_name", "as_domain", "country_code", "country", "continent_code",
+        "continent", "hit_count", "packet_count", "byte_count", "packets_in", "packets_out",
+        "bytes_in", "bytes_out", "direction", "first_seen", "last_seen", "source_host", "hostname",
+        "ip_class", "domains", "server_names", "interfaces", "top_ports",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        let opt = |v: &Option<String>| v.clone().unwrap_or_default();
+        vec![
+            self.ip.clone(),
+            opt(&self.asn),
+            opt(&self.as_name),
+            opt(&self.as_domain),
+            opt(&self.country_code),
+            opt(&self.country),
+            opt(&self.continent_code),
+            opt(&self.continent),
+            self.hit_count.to_string(),
+            self.packet_count.to_string(),
+            self.byte_count.to_string(),
+            self.packets_in.to_string(),
+            self.packets_out.to_string(),
+            self.bytes_in.to_string(),
+            self.bytes_out.to_string(),
+            opt(&self.direction),
+            self.first_seen.clone(),
+            self.last_seen.clone(),
+            opt(&self.source_host),
+            opt(&self.hostname),
+            opt(&self.ip_class),
+            self.domains.join(" "),
+            self.server_names.join(" "),
+            self.interfaces.join(" "),
+            self.top_ports
+                .iter()
+                .map(|p| format!("{}/{}", p.protocol, p.port))
+                .collect::<Vec<_>>()
+                .join(" "),
+        ]
+    }
+}
+
+impl CsvRow for CountryStats {
+    const HEADER: &'static [&'static str] = &[
+        "country_code", "country", "hit_count", "packet_count", "byte_count", "unique_ips",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.country_code.clone(),
+            self.country.clone(),
+            self.hit_count.to_string(),
+            self.packet_count.to_string(),
+            self.byte_count.to_string(),
+            self.unique_ips.to_string(),
+        ]
+    }
+}
+
+/// Writes rows one at a time in the chosen format
+struct RowWriter<W: Write> {
+    out: W,
+    format: ExportFormat,
+    rows: u64,
+}
+
+impl<W: Write> RowWriter<W> {
+    fn begin<T: CsvRow>(mut out: W, format: ExportFormat) -> Result<Self, String> {
+        match format {
+            ExportFormat::Csv => writeln!(out, "{}", T::HEADER.join(",")),
+            ExportFormat::Json => write!(out, "["),
+            ExportFormat::Ndjson => Ok(()),
+        }
+        .map_err(write_error)?;
+        Ok(Self { out, format, rows: 0 })
+    }
+
+    fn row<T: CsvRow>(&mut self, row: &T) -> Result<(), String> {
+        match self.format {
+            ExportFormat::Csv => {
+                let line = row.csv_fields().iter().map(|f| csv_escape(f)).collect::<Vec<_>>().join(",");
+                writeln!(self.out, "{}", line).map_err(write_error)?;
+            }
+            ExportFormat::Json => {
+                let sep = if self.rows == 0 { "\n" } else { ",\n" };
+                self.out.write_all(sep.as_bytes()).map_err(write_error)?;
+                serde_json::to_writer(&mut self.out, row).map_err(|e| format!("Failed to encode row: {}", e))?;
+            }
+            ExportFormat::Ndjson => {
+                serde_json::to_writer(&mut self.out, row).map_err(|e| format!("Failed to encode row: {}", e))?;
+                self.out.write_all(b"\n").map_err(write_error)?;
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<u64, String> {
+        if let ExportFormat::Json = self.format {
+            writeln!(self.out, "{}]", if self.rows == 0 { "" } else { "\n" }).map_err(write_error)?;
+        }
+        self.out.flush().map_err(write_error)?;
+        Ok(self.rows)
+    }
+}
+
+fn write_error(e: std::io::Error) -> String {
+    format!("Failed to write export: {}", e)
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Write the requested dataset to `request.path`. Rows go to a `.partial`
+/// file that is renamed into place once complete, so a reader never sees a
+/// half-written export.
+pub fn export(db: &Database, request: &ExportRequest) -> Result<ExportSummary, String> {
+    let path = PathBuf::from(&request.path);
+    let partial = partial_path(&path);
+
+    let result = write_export(db, request, &partial);
+    let rows = match result {
+        Ok(rows) => rows,
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+
+    fs::rename(&partial, &path).map_err(|e| format!("Failed to move export into place: {}", e))?;
+    log::info!("[SNIFFF] Exported {} rows to {}", rows, path.display());
+
+    Ok(ExportSummary {
+        path: path.display().to_string(),
+        rows,
+    })
+}
+
+fn write_export(db: &Database, request: &ExportRequest, path: &Path) -> Result<u64, String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let out = BufWriter::new(file);
+
+    match request.dataset {
+        ExportDataset::Connections => {
+            let mut writer = RowWriter::begin::<IpConnection>(out, request.format)?;
+            // Connections first seen after the export started are left for the next one
+            let started = Utc::now();
+            let until = match request.query.until.as_deref().filter(|u| !u.is_empty()) {
+                // An invalid bound is passed on for the query to reject
+                Some(until) if DateTime::parse_from_rfc3339(until).map_or(true, |u| u < started) => {
+                    until.to_string()
+                }
+                _ => started.to_rfc3339(),
+            };
+            let mut query = ConnectionQuery {
+                until: Some(until),
+                sort: ConnectionSort::Ip,
+                order: SortOrder::Asc,
+                limit: Some(EXPORT_PAGE_SIZE),
+                offset: None,
+                cursor: None,
+                ..request.query.clone()
+            };
+            loop {
+                let page = db.query_page_uncounted(&query)?;
+                for connection in &page.connections {
+                    writer.row(connection)?;
+                }
+                match page.next_cursor {
+                    Some(cursor) => query.cursor = Some(cursor),
+                    None => break,
+                }
+            }
+            writer.finish()
+        }
+        ExportDataset::CountryStats => {
+            let mut writer = RowWriter::begin::<CountryStats>(out, request.format)?;
+            for stats in db.get_country_stats_matching(&request.query)? {
+                writer.row(&stats)?;
+            }
+            writer.finish()
+        }
+    }
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TempDb;
+    use crate::sniffer::{Direction, IpTraffic};
+
+    /// More connections than fit in one export page, with IPs whose text
+    /// order differs from their numeric order
+    fn fill(db: &Database) -> usize {
+        let now = Utc::now();
+        let batch: Vec<IpTraffic> = (0..EXPORT_PAGE_SIZE + 234)
+            .map(|i| IpTraffic {
+                ip: format!("198.18.{}.{}", i / 250, i % 250),
+                direction: Direction::Outbound,
+                packets_in: i as u64,
+                packets_out: 1,
+                bytes_in: 0,
+                bytes_out: 0,
+                first_seen: now - chrono::Duration::seconds(i as i64),
+                last_seen: now - chrono::Duration::seconds(i as i64),
+            })
+            .collect();
+        db.record_traffic(&batch, None).unwrap();
+        batch.len()
+    }
+
+    fn request(t: &TempDb, format: ExportFormat, query: ConnectionQuery) -> ExportRequest {
+        ExportRequest {
+            path: t.dir.join("export.out").display().to_string(),
+            format,
+            dataset: ExportDataset::Connections,
+            query,
+        }
+    }
+
+    /// Rows in the order a live capture would reorder them by: most recent first
+    fn by_recency() -> ConnectionQuery {
+        ConnectionQuery { order: SortOrder::Desc, ..Default::default() }
+    }
+
+    fn sorted_ips(count: usize) -> Vec<String> {
+        let mut ips: Vec<String> = (0..count).map(|i| format!("198.18.{}.{}", i / 250, i % 250)).collect();
+        ips.sort();
+        ips
+    }
+
+    #[test]
+    fn csv_exports_every_connection_across_pages() {
+        let t = TempDb::new("export-csv");
+        let count = fill(&t.db);
+
+        let summary = export(&t.db, &request(&t, ExportFormat::Csv, by_recency())).unwrap();
+        assert_eq!(summary.rows, count as u64);
+
+        let text = fs::read_to_string(&summary.path).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next().unwrap(), IpConnection::HEADER.join(","));
+        let rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+        assert!(rows.iter().all(|r| r.len() == IpConnection::HEADER.len()));
+
+        let ips: Vec<String> = rows.iter().map(|r| r[0].to_string()).collect();
+        assert_eq!(ips, sorted_ips(count), "in IP order, each once");
+        let packets = IpConnection::HEADER.iter().position(|h| *h == "packet_count").unwrap();
+        let row = rows.iter().find(|r| r[0] == "198.18.4.1").unwrap();
+        assert_eq!(row[packets], "1002");
+    }
+
+    #[test]
+    fn ndjson_exports_every_connection_across_pages() {
+        let t = TempDb::new("export-ndjson");
+        let count = fill(&t.db);
+        // Seen first after the export started
+        let later = Utc::now() + chrono::Duration::hours(1);
+        t.db.upsert_connection("192.0.2.1", later).unwrap();
+
+        let summary = export(&t.db, &request(&t, ExportFormat::Ndjson, by_recency())).unwrap();
+        assert_eq!(summary.rows, count as u64);
+
+        let connections: Vec<IpConnection> = fs::read_to_string(&summary.path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let ips: Vec<String> = connections.iter().map(|c| c.ip.clone()).collect();
+        assert_eq!(ips, sorted_ips(count));
+        let row = connections.iter().find(|c| c.ip == "198.18.4.1").unwrap();
+        assert_eq!((row.packets_in, row.packet_count), (1001, 1002));
+    }
+
+    #[test]
+    fn a_failed_export_leaves_no_files_behind() {
+        let t = TempDb::new("export-failed");
+        fill(&t.db);
+        let query = ConnectionQuery { until: Some("yesterday".to_string()), ..Default::default() };
+        let request = request(&t, ExportFormat::Csv, query);
+
+        assert!(export(&t.db, &request).is_err());
+        let path = PathBuf::from(&request.path);
+        assert!(!partial_path(&path).exists());
+        assert!(!path.exists());
+    }
+}
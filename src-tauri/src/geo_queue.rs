@@ -0,0 +1,426 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::db::Database;
+use crate::geolocator::{GeoError, Geolocator, IpInfoResponse};
+
+/// Limits for the geolocation lookup queue
+#[derive(Debug, Clone)]
+pub struct GeoQueueConfig {
+    /// Backend requests allowed in flight at once
+    pub concurrency: usize,
+    /// Sustained backend requests per second
+    pub requests_per_sec: f64,
+    /// Requests allowed back-to-back before the rate limit kicks in
+    pub burst: f64,
+    /// Most IPs sent in one batch request
+    pub batch_size: usize,
+    /// How long to wait for more IPs before sending a partial batch
+    pub batch_window: Duration,
+    /// Retries for rate-limited or failed requests before giving up until next start
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for GeoQueueConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            requests_per_sec: 2.0,
+            burst: 5.0,
+            batch_size: 100,
+            batch_window: Duration::from_millis(250),
+            max_retries: 6,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+        }
+    }
+}
+
+impl GeoQueueConfig {
+    /// Delay before retry `attempt + 1`: doubling from `initial_backoff`, capped at `max_backoff`
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+}
+
+/// Outcome of a queued lookup, handed to whoever listens on the receiver
+#[derive(Debug, Clone)]
+pub struct GeoResolved {
+    pub ip: String,
+    pub result: Result<IpInfoResponse, GeoError>,
+}
+
+/// Classic token bucket: `rate` tokens per second, holding at most `capacity`
+struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64) -> Self {
+        Self {
+            capacity,
+            rate,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take one token, or report how long until one is available
+    fn try_take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+/// Queue between the sniffer and the geolocator: deduplicates IPs already
+/// being looked up, batches them, and throttles and retries backend requests.
+/// Successful results are written to the DB before being reported.
+pub struct GeoQueue {
+    tx: mpsc::UnboundedSender<String>,
+    in_flight: Arc<Mutex<HashSet<String>>>,
+}
+
+impl GeoQueue {
+    /// Spawn the queue's worker on `rt`. Every finished lookup is sent on the
+    /// returned receiver.
+    pub fn start(
+        rt: &tokio::runtime::Runtime,
+        geo: Arc<Geolocator>,
+        db: Arc<Database>,
+        config: GeoQueueConfig,
+    ) -> (Self, mpsc::UnboundedReceiver<GeoResolved>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (results_tx, results_rx) = mpsc::unbounded_channel();
+        let in_flight = Arc::new(Mutex::new(HashSet::new()));
+
+        let worker = Worker {
+            geo,
+            db,
+            bucket: Arc::new(tokio::sync::Mutex::new(TokenBucket::new(
+                config.requests_per_sec,
+                config.burst.max(1.0),
+            ))),
+            permits: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            in_flight: in_flight.clone(),
+            results: results_tx,
+            config,
+        };
+        rt.spawn(worker.run(rx));
+
+        (Self { tx, in_flight }, results_rx)
+    }
+
+    /// Queue an IP for lookup unless it is already queued or in flight
+    pub fn enqueue(&self, ip: String) {
+        let is_new = match self.in_flight.lock() {
+            Ok(mut in_flight) => in_flight.insert(ip.clone()),
+            Err(_) => return,
+        };
+        if is_new && self.tx.send(ip).is_err() {
+            log::error!("[SNIFFF:GEO] Lookup queue is closed");
+        }
+    }
+
+    /// Queue every recorded IP that still has no geo data, e.g. lookups that
+    /// were pending or being retried when the app last exited
+    pub fn enqueue_missing(&self, db: &Database) {
+        match db.get_ips_missing_geo() {
+            Ok(ips) => {
+                if !ips.is_empty() {
+                    log::info!("[SNIFFF:GEO] Resuming {} pending lookups", ips.len());
+                }
+                for ip in ips {
+                    self.enqueue(ip);
+                }
+            }
+            Err(e) => log::error!("[SNIFFF:GEO] Failed to scan for pending lookups: {}", e),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Worker {
+    geo: Arc<Geolocator>,
+    db: Arc<Database>,
+    bucket: Arc<tokio::sync::Mutex<TokenBucket>>,
+    permits: Arc<Semaphore>,
+    in_flight: Arc<Mutex<HashSet<String>>>,
+    results: mpsc::UnboundedSender<GeoResolved>,
+    config: GeoQueueConfig,
+}
+
+impl Worker {
+    async fn run(self, mut rx: mpsc::UnboundedReceiver<String>) {
+        while let Some(first) = rx.recv().await {
+            // Gather whatever else arrives shortly after into the same batch
+            let mut batch = vec![first];
+            let deadline = tokio::time::Instant::now() + self.config.batch_window;
+            while batch.len() < self.config.batch_size {
+                match tokio::time::timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(ip)) => batch.push(ip),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            let permit = match self.permits.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => break,
+            };
+            let worker = self.clone();
+            tokio::spawn(async move {
+                worker.process(batch).await;
+                drop(permit);
+            });
+        }
+    }
+
+    async fn wait_for_token(&self) {
+        loop {
+            let wait = match self.bucket.lock().await.try_take() {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Look up a batch, retrying the retryable part with exponential backoff
+    async fn process(&self, mut pending: Vec<String>) {
+        let mut attempt = 0;
+
+        while !pending.is_empty() {
+            self.wait_for_token().await;
+            let results = self.geo.lookup_batch(&pending).await;
+
+            let mut retry = Vec::new();
+            for ip in pending {
+                match results.get(&ip).cloned() {
+                    Some(Err(GeoError::Retryable(e))) if attempt < self.config.max_retries => {
+                        log::debug!("[SNIFFF:GEO] Will retry {}: {}", ip, e);
+                        retry.push(ip);
+                    }
+                    Some(result) => self.finish(ip, result),
+                    None => self.finish(ip.clone(), Err(GeoError::Failed(format!("No result for {}", ip)))),
+                }
+            }
+
+            if !retry.is_empty() {
+                let backoff = self.config.backoff(attempt);
+                log::warn!(
+                    "[SNIFFF:GEO] Retrying {} lookups in {:?} (attempt {})",
+                    retry.len(),
+                    backoff,
+                    attempt + 1
+                );
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            pending = retry;
+        }
+    }
+
+    fn finish(&self, ip: String, result: Result<IpInfoResponse, GeoError>) {
+        match &result {
+            Ok(info) => {
+                if let Err(e) = self.db.update_geo_info(
+                    &ip,
+                    info.asn.as_deref(),
+                    info.as_name.as_deref(),
+                    info.as_domain.as_deref(),
+                    info.country_code.as_deref(),
+                    info.country.as_deref(),
+                    info.continent_code.as_deref(),
+                    info.continent.as_deref(),
+                ) {
+                    log::error!("[SNIFFF] DB geo update failed for {}: {}", ip, e);
+                }
+            }
+            Err(e) => log::error!("[SNIFFF] Geo lookup FAILED for {}: {}", ip, e),
+        }
+
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(&ip);
+        }
+        let _ = self.results.send(GeoResolved { ip, result });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DbPathSource;
+    use crate::geolocator::{GeoBackend, GeoCacheConfig};
+    use crate::test_util::TempDir;
+    use async_trait::async_trait;
+
+    /// Answers `Retryable` to the first `failures` lookups of each IP, then
+    /// succeeds. Each lookup waits for a permit from `release`.
+    struct FlakyBackend {
+        failures: usize,
+        calls: Arc<Mutex<Vec<(String, Instant)>>>,
+        release: Arc<Semaphore>,
+    }
+
+    #[async_trait]
+    impl GeoBackend for FlakyBackend {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        async fn lookup(&self, ip: &str) -> Result<IpInfoResponse, GeoError> {
+            let _permit = self.release.acquire().await.unwrap();
+            let mut calls = self.calls.lock().unwrap();
+            calls.push((ip.to_string(), Instant::now()));
+            if calls.iter().filter(|(called, _)| called == ip).count() <= self.failures {
+                return Err(GeoError::Retryable("429 Too Many Requests".to_string()));
+            }
+            Ok(IpInfoResponse { country_code: Some("AU".to_string()), ..Default::default() })
+        }
+    }
+
+    struct Fixture {
+        geo: Arc<Geolocator>,
+        db: Arc<Database>,
+        calls: Arc<Mutex<Vec<(String, Instant)>>>,
+        release: Arc<Semaphore>,
+        // Dropped last, after the database is closed
+        _dir: TempDir,
+    }
+
+    fn fixture(name: &str, failures: usize, permits: usize) -> Fixture {
+        let dir = TempDir::new(&format!("geo-queue-{}", name));
+        let db = Arc::new(Database::open(&dir.join("snifff.db"), DbPathSource::Default).unwrap());
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let release = Arc::new(Semaphore::new(permits));
+        let backend = FlakyBackend { failures, calls: calls.clone(), release: release.clone() };
+        let geo = Arc::new(Geolocator::new(Box::new(backend), db.clone(), GeoCacheConfig::default()));
+        Fixture { geo, db, calls, release, _dir: dir }
+    }
+
+    fn quick_config() -> GeoQueueConfig {
+        GeoQueueConfig {
+            requests_per_sec: 1000.0,
+            burst: 100.0,
+            batch_window: Duration::from_millis(1),
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_millis(40),
+            ..Default::default()
+        }
+    }
+
+    fn worker(f: &Fixture, config: GeoQueueConfig) -> (Worker, mpsc::UnboundedReceiver<GeoResolved>) {
+        let (results, rx) = mpsc::unbounded_channel();
+        let worker = Worker {
+            geo: f.geo.clone(),
+            db: f.db.clone(),
+            bucket: Arc::new(tokio::sync::Mutex::new(TokenBucket::new(config.requests_per_sec, config.burst))),
+            permits: Arc::new(Semaphore::new(1)),
+            in_flight: Arc::default(),
+            results,
+            config,
+        };
+        (worker, rx)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let config = GeoQueueConfig::default();
+        let delays: Vec<u64> = (0..11).map(|attempt| config.backoff(attempt).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 64, 128, 256, 300, 300]);
+        assert_eq!(config.backoff(u32::MAX), config.max_backoff, "no overflow");
+    }
+
+    #[test]
+    fn the_token_bucket_allows_a_burst_then_refills_at_its_rate() {
+        let mut bucket = TokenBucket::new(10.0, 2.0);
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_ok());
+        let wait = bucket.try_take().unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(100), "{:?}", wait);
+
+        bucket.last_refill -= Duration::from_millis(100);
+        assert!(bucket.try_take().is_ok(), "one token back after 1/rate seconds");
+
+        // Idle time refills no further than the capacity
+        bucket.last_refill -= Duration::from_secs(10);
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_err());
+    }
+
+    #[tokio::test]
+    async fn retryable_failures_are_retried_with_growing_delays() {
+        let f = fixture("retry", 3, Semaphore::MAX_PERMITS);
+        let (worker, mut results) = worker(&f, quick_config());
+
+        worker.process(vec!["1.1.1.1".to_string()]).await;
+        let resolved = results.recv().await.unwrap();
+        assert_eq!(resolved.result.unwrap().country_code.as_deref(), Some("AU"));
+        assert!(results.try_recv().is_err(), "reported once");
+
+        let calls = f.calls.lock().unwrap();
+        assert_eq!(calls.len(), 4, "three retryable failures, then success");
+        let config = quick_config();
+        for (attempt, pair) in calls.windows(2).enumerate() {
+            let gap = pair[1].1 - pair[0].1;
+            assert!(gap >= config.backoff(attempt as u32), "retry {} after {:?}", attempt + 1, gap);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_max_retries() {
+        let f = fixture("give-up", usize::MAX, Semaphore::MAX_PERMITS);
+        let (worker, mut results) = worker(&f, GeoQueueConfig { max_retries: 2, ..quick_config() });
+
+        worker.process(vec!["1.1.1.1".to_string()]).await;
+        assert!(matches!(results.recv().await.unwrap().result, Err(GeoError::Retryable(_))));
+        assert_eq!(f.calls.lock().unwrap().len(), 3, "the first try and two retries");
+    }
+
+    #[test]
+    fn an_ip_queued_again_while_in_flight_is_looked_up_once() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let f = fixture("dedupe", 0, 0);
+        let (queue, mut results) = GeoQueue::start(&rt, f.geo.clone(), f.db.clone(), quick_config());
+
+        queue.enqueue("1.1.1.1".to_string());
+        // Let the batch go out; the backend holds it until released
+        rt.block_on(async { tokio::time::sleep(Duration::from_millis(50)).await });
+        queue.enqueue("1.1.1.1".to_string());
+        queue.enqueue("8.8.8.8".to_string());
+        f.release.add_permits(1);
+
+        let mut resolved: Vec<String> = rt.block_on(async {
+            let mut ips = Vec::new();
+            while let Ok(Some(r)) = tokio::time::timeout(Duration::from_millis(500), results.recv()).await {
+                ips.push(r.ip);
+            }
+            ips
+        });
+        resolved.sort();
+        assert_eq!(resolved, vec!["1.1.1.1", "8.8.8.8"]);
+        let mut looked_up: Vec<String> = f.calls.lock().unwrap().iter().map(|(ip, _)| ip.clone()).collect();
+        looked_up.sort();
+        assert_eq!(looked_up, vec!["1.1.1.1", "8.8.8.8"]);
+        assert!(queue.in_flight.lock().unwrap().is_empty());
+    }
+}
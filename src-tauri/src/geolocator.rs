@@ -1,9 +1,21 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lru::LruCache;
 use reqwest::Client;
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::fmt;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
-#[derive(Debug, Clone, Deserialize)]
+use crate::db::Database;
+
+/// Error recorded for addresses ipinfo reports as bogons
+const BOGON_ERROR: &str = "bogon";
+
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct IpInfoResponse {
     pub ip: Option<String>,
     pub asn: Option<String>,
@@ -13,41 +25,311 @@ pub struct IpInfoResponse {
     pub country: Option<String>,
     pub continent_code: Option<String>,
     pub continent: Option<String>,
+    /// Set by ipinfo for reserved/unroutable addresses
+    #[serde(default)]
+    pub bogon: bool,
+}
+
+impl IpInfoResponse {
+    fn empty(ip: &str) -> Self {
+        Self {
+            ip: Some(ip.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Fill any fields still missing from `other`
+    fn merge(&mut self, other: IpInfoResponse) {
+        self.asn = self.asn.take().or(other.asn);
+        self.as_name = self.as_name.take().or(other.as_name);
+        self.as_domain = self.as_domain.take().or(other.as_domain);
+        self.country_code = self.country_code.take().or(other.country_code);
+        self.country = self.country.take().or(other.country);
+        self.continent_code = self.continent_code.take().or(other.continent_code);
+        self.continent = self.continent.take().or(other.continent);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum GeoError {
+    /// Rate limiting, server errors or network trouble — worth retrying later
+    Retryable(String),
+    /// The lookup itself failed; retrying now won't help
+    Failed(String),
+}
+
+impl fmt::Display for GeoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoError::Retryable(msg) => write!(f, "{} (will retry)", msg),
+            GeoError::Failed(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// A source of geolocation data for an IP
+#[async_trait]
+pub trait GeoBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether lookups can succeed at all; failures from an unconfigured
+    /// backend are not negative-cached
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    async fn lookup(&self, ip: &str) -> Result<IpInfoResponse, GeoError>;
+
+    /// Look up several IPs; backends with a bulk API override this
+    async fn lookup_batch(&self, ips: &[String]) -> HashMap<String, Result<IpInfoResponse, GeoError>> {
+        let mut results = HashMap::new();
+        for ip in ips {
+            results.insert(ip.clone(), self.lookup(ip).await);
+        }
+        results
+    }
+}
+
+/// Pick the backend from the environment: `GEO_BACKEND=ipinfo|mmdb`.
+/// Without `GEO_BACKEND`, MMDB is used whenever `MMDB_PATHS` is set.
+pub fn backend_from_env(token: String) -> Result<Box<dyn GeoBackend>, String> {
+    let mmdb_paths: Vec<PathBuf> = std::env::var("MMDB_PATHS")
+        .map(|v| std::env::split_paths(&v).collect())
+        .unwrap_or_default();
+
+    let choice = std::env::var("GEO_BACKEND").unwrap_or_else(|_| {
+        if mmdb_paths.is_empty() { "ipinfo" } else { "mmdb" }.to_string()
+    });
+
+    match choice.as_str() {
+        "ipinfo" => Ok(Box::new(IpInfoBackend::new(token))),
+        "mmdb" => Ok(Box::new(MmdbBackend::open(&mmdb_paths)?)),
+        other => Err(format!("Unknown GEO_BACKEND '{}', expected 'ipinfo' or 'mmdb'", other)),
+    }
+}
+
+/// How long geo results are trusted and how many are kept in memory
+#[derive(Debug, Clone)]
+pub struct GeoCacheConfig {
+    /// Successful and bogon lookups are refreshed after this long
+    pub ttl: chrono::Duration,
+    /// Failed lookups are retried after this long
+    pub negative_ttl: chrono::Duration,
+    /// Entries kept in the in-memory layer in front of SQLite
+    pub memory_entries: usize,
+}
+
+impl Default for GeoCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: chrono::Duration::days(30),
+            negative_ttl: chrono::Duration::hours(1),
+            memory_entries: 10_000,
+        }
+    }
+}
+
+impl GeoCacheConfig {
+    /// Defaults, overridden by `GEO_CACHE_TTL_SECS`, `GEO_NEGATIVE_TTL_SECS`
+    /// and `GEO_CACHE_MEMORY_ENTRIES`
+    pub fn from_env() -> Self {
+        let env_num = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<i64>().ok());
+        let defaults = Self::default();
+
+        Self {
+            ttl: env_num("GEO_CACHE_TTL_SECS")
+                .map(chrono::Duration::seconds)
+                .unwrap_or(defaults.ttl),
+            negative_ttl: env_num("GEO_NEGATIVE_TTL_SECS")
+                .map(chrono::Duration::seconds)
+                .unwrap_or(defaults.negative_ttl),
+            memory_entries: env_num("GEO_CACHE_MEMORY_ENTRIES")
+                .map(|n| n.max(1) as usize)
+                .unwrap_or(defaults.memory_entries),
+        }
+    }
+}
+
+/// A cached lookup outcome; an `Err` is a negative entry
+#[derive(Debug, Clone)]
+pub struct GeoCacheEntry {
+    pub result: Result<IpInfoResponse, String>,
+    pub fetched_at: DateTime<Utc>,
 }
 
 pub struct Geolocator {
+    backend: Box<dyn GeoBackend>,
+    db: Arc<Database>,
+    config: GeoCacheConfig,
+    cache: Mutex<LruCache<String, GeoCacheEntry>>,
+}
+
+impl Geolocator {
+    pub fn new(backend: Box<dyn GeoBackend>, db: Arc<Database>, config: GeoCacheConfig) -> Self {
+        log::info!("[SNIFFF:GEO] Using {} backend", backend.name());
+        let capacity = NonZeroUsize::new(config.memory_entries).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            backend,
+            db,
+            config,
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    fn is_fresh(&self, entry: &GeoCacheEntry) -> bool {
+        let ttl = match &entry.result {
+            Ok(_) => self.config.ttl,
+            Err(e) if e == BOGON_ERROR => self.config.ttl,
+            Err(_) => self.config.negative_ttl,
+        };
+        Utc::now() - entry.fetched_at < ttl
+    }
+
+    /// A fresh cached result from memory or SQLite, if there is one
+    fn cached(&self, ip: &str) -> Option<Result<IpInfoResponse, String>> {
+        if let Ok(mut cache) = self.cache.lock() {
+            if let Some(cached) = cache.get(ip) {
+                if self.is_fresh(cached) {
+                    log::debug!("[SNIFFF:GEO] Cache hit for {}", ip);
+                    return Some(cached.result.clone());
+                }
+            }
+        }
+
+        match self.db.get_geo_cache(ip) {
+            Ok(Some(stored)) if self.is_fresh(&stored) => {
+                log::debug!("[SNIFFF:GEO] Persistent cache hit for {}", ip);
+                let result = stored.result.clone();
+                if let Ok(mut cache) = self.cache.lock() {
+                    cache.put(ip.to_string(), stored);
+                }
+                Some(result)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                log::error!("[SNIFFF:GEO] Failed to read geo cache for {}: {}", ip, e);
+                None
+            }
+        }
+    }
+
+    /// Cache a lookup outcome, good or bad, in memory and SQLite
+    fn store(&self, ip: &str, result: Result<IpInfoResponse, String>) {
+        let entry = GeoCacheEntry {
+            result,
+            fetched_at: Utc::now(),
+        };
+        if let Err(e) = self.db.put_geo_cache(ip, &entry) {
+            log::error!("[SNIFFF:GEO] Failed to persist geo cache for {}: {}", ip, e);
+        }
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.put(ip.to_string(), entry);
+        }
+    }
+
+    /// Look up IP geolocation through the in-memory cache, then the SQLite
+    /// cache, then the configured backend
+    pub async fn lookup(&self, ip: &str) -> Result<IpInfoResponse, GeoError> {
+        let mut results = self.lookup_batch(&[ip.to_string()]).await;
+        results
+            .remove(ip)
+            .unwrap_or_else(|| Err(GeoError::Failed(format!("No result for {}", ip))))
+    }
+
+    /// Look up several IPs at once, only sending cache misses to the backend.
+    /// Retryable errors are not cached; everything else is.
+    pub async fn lookup_batch(&self, ips: &[String]) -> HashMap<String, Result<IpInfoResponse, GeoError>> {
+        let mut results = HashMap::new();
+        let mut misses = Vec::new();
+
+        for ip in ips {
+            match self.cached(ip) {
+                Some(result) => {
+                    results.insert(ip.clone(), result.map_err(GeoError::Failed));
+                }
+                None => misses.push(ip.clone()),
+            }
+        }
+
+        if misses.is_empty() {
+            return results;
+        }
+
+        if !self.backend.is_ready() {
+            let err = GeoError::Failed(format!("{} backend is not configured", self.backend.name()));
+            results.extend(misses.into_iter().map(|ip| (ip, Err(err.clone()))));
+            return results;
+        }
+
+        for (ip, result) in self.backend.lookup_batch(&misses).await {
+            let result = match result {
+                Ok(info) if info.bogon => Err(GeoError::Failed(BOGON_ERROR.to_string())),
+                other => other,
+            };
+
+            match &result {
+                Ok(info) => {
+                    log::info!(
+                        "[SNIFFF:GEO] Resolved {}: country={:?} asn={:?}",
+                        ip,
+                        info.country_code,
+                        info.asn
+                    );
+                    self.store(&ip, Ok(info.clone()));
+                }
+                Err(GeoError::Failed(e)) => {
+                    log::warn!("[SNIFFF:GEO] Caching failed lookup for {}: {}", ip, e);
+                    self.store(&ip, Err(e.clone()));
+                }
+                Err(GeoError::Retryable(e)) => {
+                    log::warn!("[SNIFFF:GEO] Lookup for {} should be retried: {}", ip, e);
+                }
+            }
+
+            results.insert(ip, result);
+        }
+
+        results
+    }
+}
+
+/// IPinfo Lite HTTP API
+pub struct IpInfoBackend {
     client: Client,
     token: String,
-    cache: Mutex<HashMap<String, IpInfoResponse>>,
 }
 
-impl Geolocator {
+impl IpInfoBackend {
     pub fn new(token: String) -> Self {
         let client = Client::builder()
             .timeout(std::time::Duration::from_secs(10))
             .build()
             .expect("Failed to create HTTP client");
 
-        Self {
-            client,
-            token,
-            cache: Mutex::new(HashMap::new()),
+        if token.is_empty() || token == "your_token_here" {
+            log::warn!("[SNIFFF] ⚠ IPINFO_TOKEN is not set! Geo lookups will fail. Set it in .env");
+        } else {
+            log::info!("[SNIFFF] IPinfo token loaded ({}...)", &token[..std::cmp::min(8, token.len())]);
         }
+
+        Self { client, token }
     }
+}
 
-    /// Look up IP geolocation via IPinfo Lite API
-    pub async fn lookup(&self, ip: &str) -> Result<IpInfoResponse, String> {
-        // Check cache first
-        {
-            let cache = self.cache.lock().map_err(|e| e.to_string())?;
-            if let Some(cached) = cache.get(ip) {
-                log::debug!("[SNIFFF:GEO] Cache hit for {}", ip);
-                return Ok(cached.clone());
-            }
-        }
+#[async_trait]
+impl GeoBackend for IpInfoBackend {
+    fn name(&self) -> &'static str {
+        "ipinfo"
+    }
 
-        if self.token.is_empty() || self.token == "your_token_here" {
-            return Err("IPINFO_TOKEN not configured".to_string());
+    fn is_ready(&self) -> bool {
+        !self.token.is_empty() && self.token != "your_token_here"
+    }
+
+    /// Look up IP geolocation via IPinfo Lite API
+    async fn lookup(&self, ip: &str) -> Result<IpInfoResponse, GeoError> {
+        if !self.is_ready() {
+            return Err(GeoError::Failed("IPINFO_TOKEN not configured".to_string()));
         }
 
         let url = format!(
@@ -61,36 +343,261 @@ impl Geolocator {
             .get(&url)
             .send()
             .await
-            .map_err(|e| format!("Request failed: {}", e))?;
+            .map_err(|e| GeoError::Retryable(format!("Request failed: {}", e)))?;
+
+        let body = Self::read_body(response, ip).await?;
+
+        serde_json::from_str(&body)
+            .map_err(|e| GeoError::Failed(format!("Failed to parse response: {} — body: {}", e, body)))
+    }
+
+    /// Look up many IPs in one request via the batch endpoint
+    async fn lookup_batch(&self, ips: &[String]) -> HashMap<String, Result<IpInfoResponse, GeoError>> {
+        if ips.len() == 1 {
+            let ip = &ips[0];
+            return HashMap::from([(ip.clone(), self.lookup(ip).await)]);
+        }
+
+        match self.request_batch(ips).await {
+            Ok(mut found) => ips
+                .iter()
+                .map(|ip| {
+                    let result = found
+                        .remove(&format!("lite/{}", ip))
+                        .ok_or_else(|| GeoError::Failed("Missing from batch response".to_string()))
+                        .and_then(|value| {
+                            serde_json::from_value(value).map_err(|e| {
+                                GeoError::Failed(format!("Failed to parse batch entry: {}", e))
+                            })
+                        });
+                    (ip.clone(), result)
+                })
+                .collect(),
+            Err(e) => ips.iter().map(|ip| (ip.clone(), Err(e.clone()))).collect(),
+        }
+    }
+}
+
+impl IpInfoBackend {
+    async fn request_batch(&self, ips: &[String]) -> Result<HashMap<String, serde_json::Value>, GeoError> {
+        if !self.is_ready() {
+            return Err(GeoError::Failed("IPINFO_TOKEN not configured".to_string()));
+        }
+
+        let paths: Vec<String> = ips.iter().map(|ip| format!("lite/{}", ip)).collect();
+        log::debug!("[SNIFFF:GEO] Batch request for {} IPs", ips.len());
+
+        let response = self.client
+            .post("https://api.ipinfo.io/batch")
+            .query(&[("token", self.token.as_str())])
+            .json(&paths)
+            .send()
+            .await
+            .map_err(|e| GeoError::Retryable(format!("Batch request failed: {}", e)))?;
 
+        let body = Self::read_body(response, "batch").await?;
+
+        serde_json::from_str(&body)
+            .map_err(|e| GeoError::Failed(format!("Failed to parse batch response: {}", e)))
+    }
+
+    /// Read a response body, classifying HTTP errors: 429 and 5xx are retryable
+    async fn read_body(response: reqwest::Response, what: &str) -> Result<String, GeoError> {
         let status = response.status();
         if !status.is_success() {
             let body = response.text().await.unwrap_or_default();
-            log::error!("[SNIFFF:GEO] API error {} for {}: {}", status, ip, body);
-            return Err(format!("API returned status: {} — {}", status, body));
+            log::error!("[SNIFFF:GEO] API error {} for {}: {}", status, what, body);
+            let msg = format!("API returned status: {} — {}", status, body);
+            return if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+                Err(GeoError::Retryable(msg))
+            } else {
+                Err(GeoError::Failed(msg))
+            };
         }
 
         // Read raw body for debug
         let body = response.text().await
-            .map_err(|e| format!("Failed to read response body: {}", e))?;
-        log::debug!("[SNIFFF:GEO] Raw response for {}: {}", ip, body);
+            .map_err(|e| GeoError::Retryable(format!("Failed to read response body: {}", e)))?;
+        log::debug!("[SNIFFF:GEO] Raw response for {}: {}", what, body);
+        Ok(body)
+    }
+}
 
-        let info: IpInfoResponse = serde_json::from_str(&body)
-            .map_err(|e| format!("Failed to parse response: {} — body: {}", e, body))?;
+/// Local MMDB files, e.g. MaxMind GeoLite2 Country/ASN or IPinfo's country_asn.
+/// Every file is consulted and the first value found for each field wins.
+pub struct MmdbBackend {
+    readers: Vec<(PathBuf, maxminddb::Reader<Vec<u8>>)>,
+}
 
-        log::info!(
-            "[SNIFFF:GEO] Resolved {}: country={:?} asn={:?}",
-            ip,
-            info.country_code,
-            info.asn
-        );
+impl MmdbBackend {
+    pub fn open(paths: &[PathBuf]) -> Result<Self, String> {
+        if paths.is_empty() {
+            return Err("MMDB backend selected but MMDB_PATHS is not set".to_string());
+        }
 
-        // Cache the result
-        {
-            let mut cache = self.cache.lock().map_err(|e| e.to_string())?;
-            cache.insert(ip.to_string(), info.clone());
+        let mut readers = Vec::new();
+        for path in paths {
+            let reader = maxminddb::Reader::open_readfile(path)
+                .map_err(|e| format!("Failed to open MMDB {}: {}", path.display(), e))?;
+            log::info!(
+                "[SNIFFF:GEO] Loaded MMDB {} ({})",
+                path.display(),
+                reader.metadata.database_type
+            );
+            readers.push((path.clone(), reader));
         }
 
+        Ok(Self { readers })
+    }
+}
+
+#[async_trait]
+impl GeoBackend for MmdbBackend {
+    fn name(&self) -> &'static str {
+        "mmdb"
+    }
+
+    /// Addresses in none of the files fail, so they are negative-cached and
+    /// looked up again once the files may have been updated
+    async fn lookup(&self, ip: &str) -> Result<IpInfoResponse, GeoError> {
+        let addr: IpAddr = ip
+            .parse()
+            .map_err(|e| GeoError::Failed(format!("Invalid IP {}: {}", ip, e)))?;
+        let mut info = IpInfoResponse::empty(ip);
+        let mut found = false;
+
+        for (path, reader) in &self.readers {
+            match reader.lookup::<serde_json::Value>(addr) {
+                Ok(record) => {
+                    info.merge(mmdb_record_to_info(ip, &record));
+                    found = true;
+                }
+                Err(maxminddb::MaxMindDBError::AddressNotFoundError(_)) => {}
+                Err(e) => {
+                    return Err(GeoError::Failed(format!(
+                        "MMDB lookup in {} failed: {}",
+                        path.display(),
+                        e
+                    )));
+                }
+            }
+        }
+
+        if !found {
+            return Err(GeoError::Failed("Not found in MMDB".to_string()));
+        }
         Ok(info)
     }
 }
+
+/// Map either MaxMind's nested layout or IPinfo's flat layout onto our fields
+fn mmdb_record_to_info(ip: &str, record: &serde_json::Value) -> IpInfoResponse {
+    let text = |v: &serde_json::Value| v.as_str().map(str::to_string);
+    let english_name = |v: &serde_json::Value| text(&v["names"]["en"]);
+
+    let mut info = IpInfoResponse::empty(ip);
+
+    // MaxMind GeoLite2/GeoIP2
+    if record["country"].is_object() {
+        info.country_code = text(&record["country"]["iso_code"]);
+        info.country = english_name(&record["country"]);
+    }
+    if record["continent"].is_object() {
+        info.continent_code = text(&record["continent"]["code"]);
+        info.continent = english_name(&record["continent"]);
+    }
+    if let Some(number) = record["autonomous_system_number"].as_u64() {
+        info.asn = Some(format!("AS{}", number));
+    }
+    info.as_name = text(&record["autonomous_system_organization"]);
+
+    // IPinfo
+    let ipinfo = IpInfoResponse {
+        ip: None,
+        asn: text(&record["asn"]),
+        as_name: text(&record["as_name"]),
+        as_domain: text(&record["as_domain"]),
+        country_code: text(&record["country"]),
+        country: text(&record["country_name"]),
+        continent_code: text(&record["continent"]),
+        continent: text(&record["continent_name"]),
+        bogon: false,
+    };
+    info.merge(ipinfo);
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields(info: &IpInfoResponse) -> [Option<&str>; 7] {
+        [
+            info.asn.as_deref(),
+            info.as_name.as_deref(),
+            info.as_domain.as_deref(),
+            info.country_code.as_deref(),
+            info.country.as_deref(),
+            info.continent_code.as_deref(),
+            info.continent.as_deref(),
+        ]
+    }
+
+    #[test]
+    fn maps_maxmind_country_and_asn_records() {
+        let country = json!({
+            "continent": {"code": "OC", "geoname_id": 6255151, "names": {"de": "Ozeanien", "en": "Oceania"}},
+            "country": {"geoname_id": 2077456, "iso_code": "AU", "names": {"en": "Australia", "fr": "Australie"}},
+            "registered_country": {"iso_code": "AU", "names": {"en": "Australia"}},
+        });
+        let asn = json!({"autonomous_system_number": 13335, "autonomous_system_organization": "CLOUDFLARENET"});
+
+        let mut info = mmdb_record_to_info("1.1.1.1", &country);
+        assert_eq!(info.ip.as_deref(), Some("1.1.1.1"));
+        assert_eq!(fields(&info), [None, None, None, Some("AU"), Some("Australia"), Some("OC"), Some("Oceania")]);
+
+        info.merge(mmdb_record_to_info("1.1.1.1", &asn));
+        assert_eq!(
+            fields(&info),
+            [Some("AS13335"), Some("CLOUDFLARENET"), None, Some("AU"), Some("Australia"), Some("OC"), Some("Oceania")]
+        );
+    }
+
+    #[test]
+    fn maps_ipinfo_flat_records() {
+        let record = json!({
+            "asn": "AS13335",
+            "as_name": "Cloudflare, Inc.",
+            "as_domain": "cloudflare.com",
+            "country": "AU",
+            "country_name": "Australia",
+            "continent": "OC",
+            "continent_name": "Oceania",
+        });
+        let info = mmdb_record_to_info("1.1.1.1", &record);
+        assert_eq!(
+            fields(&info),
+            [
+                Some("AS13335"),
+                Some("Cloudflare, Inc."),
+                Some("cloudflare.com"),
+                Some("AU"),
+                Some("Australia"),
+                Some("OC"),
+                Some("Oceania"),
+            ]
+        );
+
+        let empty = mmdb_record_to_info("1.1.1.1", &json!({"network": "1.1.1.0/24"}));
+        assert_eq!(fields(&empty), [None; 7]);
+    }
+
+    #[tokio::test]
+    async fn addresses_in_no_mmdb_are_a_failed_lookup() {
+        let backend = MmdbBackend { readers: Vec::new() };
+        assert!(matches!(backend.lookup("1.1.1.1").await, Err(GeoError::Failed(_))));
+        assert!(matches!(backend.lookup("not an ip").await, Err(GeoError::Failed(_))));
+    }
+}
@@ -1,19 +1,58 @@
+mod address_class;
+mod cidr;
 mod db;
+mod dns;
+mod exclusions;
+mod export;
+mod geo_queue;
 mod geolocator;
+mod merge;
+mod migrations;
 mod permissions;
+mod process;
+mod rdns;
+mod recorder;
+mod retention;
+mod sni;
 mod sniffer;
-
-use db::{Database, IpConnection, CountryStats};
-use geolocator::Geolocator;
-use sniffer::Sniffer;
+#[cfg(test)]
+mod test_util;
+
+use db::{
+    ConnectionPage, ConnectionQuery, Database, DatabaseInfo, IpConnection, CountryStats, FlowRecord,
+    ProcessUsage, PurgeFilter, PurgeResult, TimeSeriesQuery, TimeSeriesResult,
+};
+use exclusions::ExclusionList;
+use export::{ExportRequest, ExportSummary};
+use geo_queue::{GeoQueue, GeoQueueConfig, GeoResolved};
+use geolocator::{GeoCacheConfig, Geolocator};
+use merge::MergeSummary;
+use permissions::PermissionStatus;
+use process::ProcessTracker;
+use rdns::{RdnsConfig, ReverseDns};
+use recorder::RecordingConfig;
+use retention::RetentionPolicy;
+use sniffer::{CaptureBatch, CaptureSession, ImportSummary, Sniffer};
+use chrono::{DateTime, Utc};
 use serde::Serialize;
-use std::sync::Arc;
-use tauri::{Emitter, AppHandle};
+use std::sync::{Arc, Mutex};
+use tauri::{Emitter, AppHandle, Manager};
+
+/// Minute-resolution history kept before it is rolled up into hours
+const MINUTE_BUCKET_RETENTION: chrono::Duration = chrono::Duration::hours(48);
+/// How often the rollup runs
+const ROLLUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+/// How often the retention policy is enforced
+const RETENTION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
 
 pub struct AppState {
     pub db: Arc<Database>,
     pub sniffer: Arc<Sniffer>,
     pub geolocator: Arc<Geolocator>,
+    pub geo_queue: Arc<GeoQueue>,
+    pub rdns: Arc<ReverseDns>,
+    pub processes: Arc<ProcessTracker>,
+    pub retention: Mutex<RetentionPolicy>,
     pub start_time: std::time::Instant,
     pub tokio_rt: Arc<tokio::runtime::Runtime>,
 }
@@ -31,21 +70,25 @@ struct NewIpEvent {
 pub struct AppStats {
     pub total_ips: u64,
     pub total_hits: u64,
+    pub total_packets: u64,
+    pub total_bytes: u64,
     pub total_countries: u64,
     pub uptime_seconds: u64,
     pub is_running: bool,
+    /// Interfaces being captured on
+    pub interfaces: Vec<String>,
 }
 
 // ─── Tauri Commands ────────────────────────────────────────────
 
 #[tauri::command]
-fn check_permissions() -> Result<bool, String> {
-    Ok(permissions::check_bpf_access())
+fn check_permissions() -> PermissionStatus {
+    permissions::check_status()
 }
 
 #[tauri::command]
-fn request_permissions() -> Result<bool, String> {
-    permissions::ensure_bpf_access()
+fn request_permissions() -> Result<PermissionStatus, String> {
+    permissions::ensure_access()
 }
 
 #[tauri::command]
@@ -53,105 +96,258 @@ fn get_interfaces() -> Vec<String> {
     Sniffer::list_interfaces()
 }
 
+/// Build the sniffer's `on_new_ip` callback: insert the row, then queue PTR
+/// lookups, and geo lookups for public addresses
+fn new_ip_handler(state: &AppState) -> impl Fn(String, DateTime<Utc>) + Send + 'static {
+    let db = state.db.clone();
+    let geo_queue = state.geo_queue.clone();
+    let rdns = state.rdns.clone();
+
+    move |ip, seen_at| {
+        log::info!("[SNIFFF] New IP detected: {}", ip);
+
+        // Insert into DB immediately
+        if let Err(e) = db.upsert_connection(&ip, seen_at) {
+            log::error!("[SNIFFF] DB upsert error for {}: {}", ip, e);
+            return;
+        }
+
+        rdns.enqueue(ip.clone());
+
+        // LAN peers have nothing for a geo backend to find
+        if !address_class::classify_str(&ip).is_some_and(|c| c.is_public()) {
+            return;
+        }
+
+        // Skip if we already have geo info
+        if db.has_geo_info(&ip).unwrap_or(false) {
+            log::debug!("[SNIFFF] Geo info already cached for {}, skipping", ip);
+            return;
+        }
+
+        geo_queue.enqueue(ip);
+    }
+}
+
+/// Start dropping addresses the database already places in an excluded ASN
+fn seed_excluded_asns(state: &AppState) {
+    let exclusions = state.sniffer.exclusions();
+    match state.db.get_ips_in_asns(&exclusions.get().asns) {
+        Ok(ips) => exclusions.add_asn_ips(ips),
+        Err(e) => log::error!("[SNIFFF] Failed to look up excluded ASNs: {}", e),
+    }
+}
+
+/// Handle a finished geo lookup. An address that turns out to be in an
+/// excluded ASN is dropped from then on and its rows so far are purged.
+fn on_geo_resolved(app: &AppHandle, state: &AppState, resolved: GeoResolved) {
+    let exclusions = state.sniffer.exclusions();
+    let excluded = resolved.result.as_ref().ok()
+        .and_then(|info| info.asn.as_deref())
+        .is_some_and(|asn| exclusions.excludes_asn(asn));
+    if !excluded {
+        emit_geo_result(app, resolved);
+        return;
+    }
+
+    exclusions.add_asn_ips([resolved.ip.clone()]);
+    let filter = PurgeFilter { ip: Some(resolved.ip.clone()), ..Default::default() };
+    match state.db.purge(&filter) {
+        Ok(_) => log::info!("[SNIFFF] Dropped {}: its ASN is excluded", resolved.ip),
+        Err(e) => log::error!("[SNIFFF] Failed to purge excluded {}: {}", resolved.ip, e),
+    }
+}
+
+/// Emit a `new-ip` event to the frontend for a finished geo lookup, with or without geo info
+fn emit_geo_result(app: &AppHandle, resolved: GeoResolved) {
+    let event = match resolved.result {
+        Ok(info) => NewIpEvent {
+            ip: resolved.ip,
+            country_code: info.country_code,
+            country: info.country,
+            asn: info.asn,
+            as_name: info.as_name,
+        },
+        Err(_) => NewIpEvent {
+            ip: resolved.ip,
+            country_code: None,
+            country: None,
+            asn: None,
+            as_name: None,
+        },
+    };
+    let _ = app.emit("new-ip", &event);
+}
+
+/// Build the sniffer's `on_flush` callback: persist per-IP and per-flow totals, DNS answers and server names
+fn flush_handler(state: &AppState) -> impl Fn(CaptureBatch) + Send + Sync + 'static {
+    let db = state.db.clone();
+
+    move |batch| {
+        let interface = batch.interface.as_deref();
+        if let Err(e) = db.record_traffic(&batch.ips, interface) {
+            log::error!("[SNIFFF] Failed to record traffic for {} IPs: {}", batch.ips.len(), e);
+        }
+        if let Err(e) = db.record_flows(&batch.flows, interface) {
+            log::error!("[SNIFFF] Failed to record {} flows: {}", batch.flows.len(), e);
+        }
+        if let Err(e) = db.record_dns(&batch.dns) {
+            log::error!("[SNIFFF] Failed to record {} DNS answers: {}", batch.dns.len(), e);
+        }
+        if let Err(e) = db.record_server_names(&batch.server_names) {
+            log::error!("[SNIFFF] Failed to record {} server names: {}", batch.server_names.len(), e);
+        }
+    }
+}
+
+/// `flush_handler` for live captures: flows are also queued for attribution
+/// to local processes, while their sockets are still open
+fn live_flush_handler(state: &AppState) -> impl Fn(CaptureBatch) + Send + Sync + 'static {
+    let processes = state.processes.clone();
+    let on_flush = flush_handler(state);
+
+    move |batch| {
+        let flows = batch.flows.clone();
+        on_flush(batch);
+        processes.attribute(flows);
+    }
+}
+
 #[tauri::command]
 fn start_sniffing(
-    app: AppHandle,
     state: tauri::State<'_, Arc<AppState>>,
     interface: Option<String>,
+    recording: Option<RecordingConfig>,
+    filter: Option<String>,
 ) -> Result<(), String> {
     let iface = interface
         .or_else(|| Sniffer::default_interface())
         .ok_or("No network interface found")?;
 
     log::info!("[SNIFFF] Starting capture on interface: {}", iface);
+    if let Some(config) = &recording {
+        log::info!(
+            "[SNIFFF] Recording to {} (full payload: {})",
+            config.directory.display(),
+            config.full_payload
+        );
+    }
+    if let Some(filter) = filter.as_deref().filter(|f| !f.trim().is_empty()) {
+        log::info!("[SNIFFF] Capture filter: {}", filter);
+    }
 
-    let db = state.db.clone();
-    let geo = state.geolocator.clone();
-    let app_handle = app.clone();
-    let rt = state.tokio_rt.clone();
+    state.sniffer.start(
+        &iface,
+        recording,
+        filter.as_deref(),
+        new_ip_handler(&state),
+        live_flush_handler(&state),
+    )?;
 
-    state.sniffer.start(&iface, move |ip_str| {
-        let db = db.clone();
-        let geo = geo.clone();
-        let app_handle = app_handle.clone();
-        let ip = ip_str.clone();
+    log::info!("[SNIFFF] Capture started on {}", iface);
+    Ok(())
+}
 
-        log::info!("[SNIFFF] New IP detected: {}", ip);
+#[tauri::command]
+async fn import_pcap(
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+    path: String,
+) -> Result<ImportSummary, String> {
+    log::info!("[SNIFFF] Importing capture file: {}", path);
+
+    let state = state.inner().clone();
+    let on_new_ip = new_ip_handler(&state);
+    let on_flush = flush_handler(&state);
+
+    // Reading a large file takes a while; keep it off the async workers
+    tauri::async_runtime::spawn_blocking(move || {
+        state.sniffer.import_file(&path, on_new_ip, on_flush, |progress| {
+            let _ = app.emit("import-progress", &progress);
+        })
+    })
+    .await
+    .map_err(|e| format!("Import task failed: {}", e))?
+}
 
-        // Insert into DB immediately
-        if let Err(e) = db.upsert_connection(&ip) {
-            log::error!("[SNIFFF] DB upsert error for {}: {}", ip, e);
-            return;
+#[tauri::command]
+fn cancel_import(state: tauri::State<'_, Arc<AppState>>) -> Result<(), String> {
+    log::info!("[SNIFFF] Cancelling capture import");
+    state.sniffer.cancel_import();
+    Ok(())
+}
+
+/// Stop the capture on `interface`, or every capture if none is given
+#[tauri::command]
+fn stop_sniffing(state: tauri::State<'_, Arc<AppState>>, interface: Option<String>) -> Result<(), String> {
+    match interface {
+        Some(iface) => {
+            log::info!("[SNIFFF] Stopping capture on {}", iface);
+            state.sniffer.stop(&iface)
+        }
+        None => {
+            log::info!("[SNIFFF] Stopping all captures");
+            state.sniffer.stop_all();
+            Ok(())
         }
+    }
+}
 
-        // Geo lookup using dedicated runtime (pcap thread has no tokio runtime)
-        rt.spawn(async move {
-            // Skip if we already have geo info
-            if db.has_geo_info(&ip).unwrap_or(false) {
-                log::debug!("[SNIFFF] Geo info already cached for {}, skipping", ip);
-                return;
-            }
+#[tauri::command]
+fn get_capture_sessions(state: tauri::State<'_, Arc<AppState>>) -> Vec<CaptureSession> {
+    state.sniffer.sessions()
+}
 
-            log::info!("[SNIFFF] Looking up geo info for {}", ip);
-
-            match geo.lookup(&ip).await {
-                Ok(info) => {
-                    log::info!(
-                        "[SNIFFF] Geo result for {}: country={:?} asn={:?} as_name={:?}",
-                        ip,
-                        info.country_code,
-                        info.asn,
-                        info.as_name
-                    );
-
-                    if let Err(e) = db.update_geo_info(
-                        &ip,
-                        info.asn.as_deref(),
-                        info.as_name.as_deref(),
-                        info.as_domain.as_deref(),
-                        info.country_code.as_deref(),
-                        info.country.as_deref(),
-                        info.continent_code.as_deref(),
-                        info.continent.as_deref(),
-                    ) {
-                        log::error!("[SNIFFF] DB geo update failed for {}: {}", ip, e);
-                    }
+#[tauri::command]
+fn get_record_lan_peers(state: tauri::State<'_, Arc<AppState>>) -> bool {
+    state.sniffer.records_lan_peers()
+}
 
-                    // Emit event to frontend
-                    let event = NewIpEvent {
-                        ip: ip.clone(),
-                        country_code: info.country_code,
-                        country: info.country,
-                        asn: info.asn,
-                        as_name: info.as_name,
-                    };
-                    let _ = app_handle.emit("new-ip", &event);
-                }
-                Err(e) => {
-                    log::error!("[SNIFFF] Geo lookup FAILED for {}: {}", ip, e);
-                    // Still emit the event without geo info
-                    let event = NewIpEvent {
-                        ip: ip.clone(),
-                        country_code: None,
-                        country: None,
-                        asn: None,
-                        as_name: None,
-                    };
-                    let _ = app_handle.emit("new-ip", &event);
-                }
-            }
-        });
-    })?;
+/// Also record peers on private, CGNAT and link-local addresses. They are
+/// never geolocated.
+#[tauri::command]
+fn set_record_lan_peers(state: tauri::State<'_, Arc<AppState>>, enabled: bool) {
+    if enabled != state.sniffer.records_lan_peers() {
+        log::info!("[SNIFFF] LAN peer recording {}", if enabled { "enabled" } else { "disabled" });
+    }
+    state.sniffer.set_record_lan_peers(enabled);
+}
 
-    log::info!("[SNIFFF] Capture started successfully");
-    Ok(())
+#[tauri::command]
+fn get_decapsulate_tunnels(state: tauri::State<'_, Arc<AppState>>) -> bool {
+    state.sniffer.decapsulates_tunnels()
 }
 
+/// Attribute GRE, VXLAN, IP-in-IP and GTP-U traffic to the inner remote
+/// address instead of the tunnel endpoint
 #[tauri::command]
-fn stop_sniffing(state: tauri::State<'_, Arc<AppState>>) -> Result<(), String> {
-    log::info!("[SNIFFF] Stopping capture");
-    state.sniffer.stop();
-    Ok(())
+fn set_decapsulate_tunnels(state: tauri::State<'_, Arc<AppState>>, enabled: bool) {
+    if enabled != state.sniffer.decapsulates_tunnels() {
+        log::info!("[SNIFFF] Tunnel decapsulation {}", if enabled { "enabled" } else { "disabled" });
+    }
+    state.sniffer.set_decapsulate_tunnels(enabled);
+}
+
+#[tauri::command]
+fn get_capture_exclusions(state: tauri::State<'_, Arc<AppState>>) -> ExclusionList {
+    state.sniffer.exclusions().get()
+}
+
+/// Replace the addresses, networks and ASNs the sniffer drops. Applies to a
+/// running capture straight away; data already recorded is left alone.
+#[tauri::command]
+fn set_capture_exclusions(
+    state: tauri::State<'_, Arc<AppState>>,
+    exclusions: ExclusionList,
+) -> Result<ExclusionList, String> {
+    let list = state.sniffer.exclusions().set(exclusions)?;
+    seed_excluded_asns(&state);
+    log::info!(
+        "[SNIFFF] Excluding {} networks and {} ASNs from capture",
+        list.networks.len(),
+        list.asns.len()
+    );
+    Ok(list)
 }
 
 #[tauri::command]
@@ -162,6 +358,32 @@ fn get_connections(
     state.db.get_connections(limit.unwrap_or(500))
 }
 
+#[tauri::command]
+fn query_connections(
+    state: tauri::State<'_, Arc<AppState>>,
+    query: ConnectionQuery,
+) -> Result<ConnectionPage, String> {
+    state.db.query_connections(&query)
+}
+
+#[tauri::command]
+fn get_flows(
+    state: tauri::State<'_, Arc<AppState>>,
+    ip: String,
+    limit: Option<usize>,
+) -> Result<Vec<FlowRecord>, String> {
+    state.db.get_flows(&ip, limit.unwrap_or(200))
+}
+
+/// Traffic per local program, optionally limited to the connections matching `query`
+#[tauri::command]
+fn get_connections_by_process(
+    state: tauri::State<'_, Arc<AppState>>,
+    query: Option<ConnectionQuery>,
+) -> Result<Vec<ProcessUsage>, String> {
+    state.db.get_connections_by_process(&query.unwrap_or_default())
+}
+
 #[tauri::command]
 fn get_country_stats(
     state: tauri::State<'_, Arc<AppState>>,
@@ -169,19 +391,154 @@ fn get_country_stats(
     state.db.get_country_stats()
 }
 
+#[tauri::command]
+fn get_timeseries(
+    state: tauri::State<'_, Arc<AppState>>,
+    query: TimeSeriesQuery,
+) -> Result<TimeSeriesResult, String> {
+    state.db.get_timeseries(&query)
+}
+
 #[tauri::command]
 fn get_stats(
     state: tauri::State<'_, Arc<AppState>>,
 ) -> Result<AppStats, String> {
-    let (total_ips, total_hits, total_countries) = state.db.get_total_stats()?;
+    let totals = state.db.get_total_stats()?;
 
     Ok(AppStats {
-        total_ips,
-        total_hits,
-        total_countries,
+        total_ips: totals.total_ips,
+        total_hits: totals.total_hits,
+        total_packets: totals.total_packets,
+        total_bytes: totals.total_bytes,
+        total_countries: totals.total_countries,
         uptime_seconds: state.start_time.elapsed().as_secs(),
         is_running: state.sniffer.is_running(),
+        interfaces: state.sniffer.sessions().into_iter().map(|s| s.interface).collect(),
+    })
+}
+
+#[tauri::command]
+fn get_retention_policy(state: tauri::State<'_, Arc<AppState>>) -> Result<RetentionPolicy, String> {
+    Ok(state.retention.lock().map_err(|e| e.to_string())?.clone())
+}
+
+/// Replace the retention policy; it takes effect on the next hourly run
+#[tauri::command]
+fn set_retention_policy(
+    state: tauri::State<'_, Arc<AppState>>,
+    policy: RetentionPolicy,
+) -> Result<(), String> {
+    log::info!("[SNIFFF] Retention policy set to {:?}", policy);
+    *state.retention.lock().map_err(|e| e.to_string())? = policy;
+    Ok(())
+}
+
+/// Delete data matching `filter` and vacuum the database
+#[tauri::command]
+async fn purge_data(
+    state: tauri::State<'_, Arc<AppState>>,
+    filter: PurgeFilter,
+) -> Result<PurgeResult, String> {
+    let state = state.inner().clone();
+
+    // Vacuuming rewrites the whole file; keep it off the async workers
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut result = state.db.purge(&filter)?;
+        if !result.is_empty() {
+            result.bytes_reclaimed = state.db.vacuum()?;
+        }
+        log::info!("[SNIFFF] Purged {:?}", result);
+        Ok(result)
     })
+    .await
+    .map_err(|e| format!("Purge task failed: {}", e))?
+}
+
+/// Write connections or country stats to a CSV, JSON or NDJSON file
+#[tauri::command]
+async fn export_data(
+    state: tauri::State<'_, Arc<AppState>>,
+    request: ExportRequest,
+) -> Result<ExportSummary, String> {
+    let state = state.inner().clone();
+
+    tauri::async_runtime::spawn_blocking(move || export::export(&state.db, &request))
+        .await
+        .map_err(|e| format!("Export task failed: {}", e))?
+}
+
+/// Merge another machine's database, or an NDJSON connections export, into
+/// the current one. Merged rows are tagged with `label` (default: file name).
+#[tauri::command]
+async fn import_database(
+    state: tauri::State<'_, Arc<AppState>>,
+    path: String,
+    label: Option<String>,
+) -> Result<MergeSummary, String> {
+    let state = state.inner().clone();
+
+    let summary = tauri::async_runtime::spawn_blocking({
+        let state = state.clone();
+        move || merge::import(&state.db, std::path::Path::new(path.trim()), label)
+    })
+    .await
+    .map_err(|e| format!("Merge task failed: {}", e))??;
+
+    // Rows that arrived without geo data or hostnames still need a lookup
+    state.geo_queue.enqueue_missing(&state.db);
+    state.rdns.enqueue_missing(&state.db);
+    Ok(summary)
+}
+
+#[tauri::command]
+fn get_database_info(state: tauri::State<'_, Arc<AppState>>) -> Result<DatabaseInfo, String> {
+    state.db.info()
+}
+
+/// Switch to another database file, e.g. one per investigation.
+/// Without a path, go back to the default one in the app data dir.
+#[tauri::command]
+fn open_database(
+    app: AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+    path: Option<String>,
+) -> Result<DatabaseInfo, String> {
+    if state.sniffer.is_importing() {
+        return Err("Cannot switch databases while an import is running".to_string());
+    }
+
+    let path = match path.filter(|p| !p.trim().is_empty()) {
+        Some(path) => std::path::PathBuf::from(path.trim()),
+        None => app.path().app_data_dir()
+            .map_err(|e| format!("Failed to resolve app data dir: {}", e))?
+            .join(db::DB_FILE_NAME),
+    };
+    let info = state.db.switch_to(&path)?;
+    log::info!("[SNIFFF] Switched database to {}", info.path);
+
+    // Hit counts are per session, and this database has never seen this session's IPs
+    state.sniffer.reset_seen();
+    state.geo_queue.enqueue_missing(&state.db);
+    state.rdns.enqueue_missing(&state.db);
+    seed_excluded_asns(&state);
+    Ok(info)
+}
+
+#[tauri::command]
+fn get_reverse_dns_enabled(state: tauri::State<'_, Arc<AppState>>) -> bool {
+    state.rdns.is_enabled()
+}
+
+/// Turn PTR lookups on or off. While off, no queries are sent.
+#[tauri::command]
+fn set_reverse_dns_enabled(state: tauri::State<'_, Arc<AppState>>, enabled: bool) {
+    if enabled != state.rdns.is_enabled() {
+        log::info!("[SNIFFF:DNS] Reverse DNS {}", if enabled { "enabled" } else { "disabled" });
+    }
+    state.rdns.set_enabled(enabled);
+    if enabled {
+        state.rdns.enqueue_missing(&state.db);
+    }
 }
 
 // ─── App Setup ─────────────────────────────────────────────────
@@ -199,12 +556,6 @@ pub fn run() {
     let token = std::env::var("IPINFO_TOKEN")
         .unwrap_or_else(|_| String::new());
 
-    if token.is_empty() || token == "your_token_here" {
-        log::warn!("[SNIFFF] ⚠ IPINFO_TOKEN is not set! Geo lookups will fail. Set it in .env");
-    } else {
-        log::info!("[SNIFFF] IPinfo token loaded ({}...)", &token[..std::cmp::min(8, token.len())]);
-    }
-
     // Create a dedicated tokio runtime for async work (geo lookups)
     let tokio_rt = Arc::new(
         tokio::runtime::Builder::new_multi_thread()
@@ -214,21 +565,6 @@ pub fn run() {
             .expect("Failed to create tokio runtime")
     );
 
-    let db = Arc::new(Database::new().expect("Failed to initialize database"));
-    let sniffer = Arc::new(Sniffer::new());
-    let geolocator = Arc::new(Geolocator::new(token));
-
-    log::info!("[SNIFFF] Database initialized");
-    log::info!("[SNIFFF] Available interfaces: {:?}", Sniffer::list_interfaces());
-
-    let state = Arc::new(AppState {
-        db,
-        sniffer,
-        geolocator,
-        start_time: std::time::Instant::now(),
-        tokio_rt,
-    });
-
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_shell::init())
@@ -237,16 +573,132 @@ pub fn run() {
             tauri_plugin_autostart::MacosLauncher::LaunchAgent,
             None,
         ))
-        .manage(state)
+        .setup(move |app| {
+            let data_dir = app.path().app_data_dir()
+                .map_err(|e| format!("Failed to resolve app data dir: {}", e))?;
+            let (db_path, source) = db::resolve_db_path(&data_dir);
+            let db = Arc::new(Database::open(&db_path, source)?);
+            let sniffer = Arc::new(Sniffer::new());
+            // A bad MMDB_PATHS or GEO_BACKEND shouldn't keep the app from starting
+            let geo_backend = geolocator::backend_from_env(token.clone()).unwrap_or_else(|e| {
+                log::error!("[SNIFFF] {}; falling back to ipinfo.io", e);
+                Box::new(geolocator::IpInfoBackend::new(token))
+            });
+            let geolocator = Arc::new(Geolocator::new(geo_backend, db.clone(), GeoCacheConfig::from_env()));
+            let (geo_queue, mut geo_results) =
+                GeoQueue::start(&tokio_rt, geolocator.clone(), db.clone(), GeoQueueConfig::default());
+            let geo_queue = Arc::new(geo_queue);
+            geo_queue.enqueue_missing(&db);
+            // The backlog is looked up once the frontend has applied the saved setting
+            let rdns = Arc::new(ReverseDns::start(&tokio_rt, db.clone(), RdnsConfig::from_env()));
+            let processes = Arc::new(ProcessTracker::start(process::platform_resolver(), db.clone()));
+
+            log::info!("[SNIFFF] Database initialized at {}", db_path.display());
+            log::info!("[SNIFFF] Available interfaces: {:?}", Sniffer::list_interfaces());
+
+            let state = Arc::new(AppState {
+                db,
+                sniffer,
+                geolocator,
+                geo_queue,
+                rdns,
+                processes,
+                retention: Mutex::new(RetentionPolicy::from_env()),
+                start_time: std::time::Instant::now(),
+                tokio_rt: tokio_rt.clone(),
+            });
+            app.manage(state.clone());
+
+            state.sniffer.set_record_lan_peers(
+                std::env::var("CAPTURE_LAN_PEERS").is_ok_and(|v| matches!(v.trim(), "1" | "true" | "on")),
+            );
+            state.sniffer.set_decapsulate_tunnels(
+                std::env::var("CAPTURE_DECAPSULATE_TUNNELS").is_ok_and(|v| matches!(v.trim(), "1" | "true" | "on")),
+            );
+            if let Err(e) = state.sniffer.exclusions().set(ExclusionList::from_env()) {
+                log::error!("[SNIFFF] Ignoring capture exclusions from the environment: {}", e);
+            }
+            seed_excluded_asns(&state);
+
+            // Downsample old time-series buckets
+            let rollup_state = state.clone();
+            tokio_rt.spawn(async move {
+                let mut interval = tokio::time::interval(ROLLUP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    match rollup_state.db.rollup_timeseries(Utc::now() - MINUTE_BUCKET_RETENTION) {
+                        Ok(0) => {}
+                        Ok(n) => log::info!("[SNIFFF] Rolled up {} minute buckets into hours", n),
+                        Err(e) => log::error!("[SNIFFF] Time series rollup failed: {}", e),
+                    }
+                }
+            });
+
+            // Enforce the retention policy, first an interval from now so the
+            // frontend has pushed its saved policy by then
+            let retention_state = state.clone();
+            tokio_rt.spawn(async move {
+                let mut interval = tokio::time::interval_at(
+                    tokio::time::Instant::now() + RETENTION_INTERVAL,
+                    RETENTION_INTERVAL,
+                );
+                loop {
+                    interval.tick().await;
+                    let policy = match retention_state.retention.lock() {
+                        Ok(policy) => policy.clone(),
+                        Err(_) => continue,
+                    };
+                    // Vacuuming can take a while; don't hold up the geo workers
+                    let db = retention_state.db.clone();
+                    match tokio::task::spawn_blocking(move || retention::enforce(&db, &policy)).await {
+                        Ok(Ok(result)) if result.is_empty() => {}
+                        Ok(Ok(result)) => log::info!("[SNIFFF] Retention removed {:?}", result),
+                        Ok(Err(e)) => log::error!("[SNIFFF] Retention failed: {}", e),
+                        Err(e) => log::error!("[SNIFFF] Retention task failed: {}", e),
+                    }
+                }
+            });
+
+            // Forward finished geo lookups to the frontend
+            let app_handle = app.handle().clone();
+            tokio_rt.spawn(async move {
+                while let Some(resolved) = geo_results.recv().await {
+                    on_geo_resolved(&app_handle, &state, resolved);
+                }
+            });
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             check_permissions,
             request_permissions,
             get_interfaces,
             start_sniffing,
             stop_sniffing,
+            get_capture_sessions,
+            get_record_lan_peers,
+            set_record_lan_peers,
+            get_decapsulate_tunnels,
+            set_decapsulate_tunnels,
+            get_capture_exclusions,
+            set_capture_exclusions,
+            import_pcap,
+            cancel_import,
             get_connections,
+            query_connections,
+            get_flows,
+            get_connections_by_process,
             get_country_stats,
+            get_timeseries,
             get_stats,
+            get_database_info,
+            open_database,
+            get_retention_policy,
+            set_retention_policy,
+            purge_data,
+            export_data,
+            import_database,
+            get_reverse_dns_enabled,
+            set_reverse_dns_enabled,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
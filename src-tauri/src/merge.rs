@@ -0,0 +1,193 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OpenFlags};
+use serde::Serialize;
+
+use crate::address_class;
+use crate::db::{Database, IpConnection};
+use crate::migrations;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeSummary {
+    pub path: String,
+    /// Label recorded in `source_host` on every merged connection
+    pub source_host: String,
+    pub connections: u64,
+    pub new_connections: u64,
+    pub flows: u64,
+    pub buckets: u64,
+}
+
+/// Merge another SNIFFF database, or an NDJSON connections export, into `db`.
+/// The source is first copied into a scratch database and migrated to the
+/// current schema so older files merge the same way as new ones; the source
+/// itself is never modified. `label` defaults to the file name without its
+/// extension.
+pub fn import(db: &Database, path: &Path, label: Option<String>) -> Result<MergeSummary, String> {
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.display()));
+    }
+    if same_file(path, Path::new(&db.info()?.path)) {
+        return Err("Cannot merge a database into itself".to_string());
+    }
+
+    let source_host = label
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .or_else(|| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .ok_or_else(|| "A source host label is required".to_string())?;
+    if source_host.contains(',') {
+        return Err("Source host labels cannot contain commas".to_string());
+    }
+
+    let staging_dir = std::env::temp_dir().join(format!(
+        "snifff-merge-{}-{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_millis()
+    ));
+    fs::create_dir_all(&staging_dir)
+        .map_err(|e| format!("Failed to create {}: {}", staging_dir.display(), e))?;
+
+    let result = stage(path, &staging_dir)
+        .and_then(|staged| db.merge_from(&staged, &source_host));
+    if let Err(e) = fs::remove_dir_all(&staging_dir) {
+        log::warn!("[SNIFFF] Failed to clean up {}: {}", staging_dir.display(), e);
+    }
+    let counts = result?;
+
+    log::info!(
+        "[SNIFFF] Merged {} connections ({} new), {} flows and {} time-series buckets from {} as \"{}\"",
+        counts.connections, counts.new_connections, counts.flows, counts.buckets, path.display(), source_host
+    );
+
+    Ok(MergeSummary {
+        path: path.display().to_string(),
+        source_host,
+        connections: counts.connections,
+        new_connections: counts.new_connections,
+        flows: counts.flows,
+        buckets: counts.buckets,
+    })
+}
+
+/// Build a current-schema copy of `path` in `dir` and return its location
+fn stage(path: &Path, dir: &Path) -> Result<PathBuf, String> {
+    let staged = dir.join("source.db");
+    if is_ndjson(path) {
+        stage_ndjson(path, &staged)?;
+    } else {
+        stage_database(path, &staged)?;
+    }
+    Ok(staged)
+}
+
+fn is_ndjson(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("ndjson") || e.eq_ignore_ascii_case("jsonl"))
+}
+
+fn stage_database(path: &Path, staged: &Path) -> Result<(), String> {
+    let source = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+    let has_connections: bool = source.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'connections'",
+        [],
+        |row| row.get(0),
+    ).map_err(|e| format!("{} is not a SQLite database: {}", path.display(), e))?;
+    if !has_connections {
+        return Err(format!("{} is not a SNIFFF database", path.display()));
+    }
+
+    // VACUUM INTO also picks up anything still sitting in the source's WAL
+    source.execute("VACUUM INTO ?1", params![staged.to_string_lossy()])
+        .map_err(|e| format!("Failed to copy {}: {}", path.display(), e))?;
+    drop(source);
+
+    let mut conn = Connection::open(staged)
+        .map_err(|e| format!("Failed to open copy of {}: {}", path.display(), e))?;
+    migrations::migrate(&mut conn, staged)
+}
+
+/// Stage an NDJSON connections export. Lines for the same IP are summed, and
+/// the hosts an export was merged from are kept as its own merged sources.
+fn stage_ndjson(path: &Path, staged: &Path) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut conn = Connection::open(staged)
+        .map_err(|e| format!("Failed to create staging database: {}", e))?;
+    migrations::migrate(&mut conn, staged)?;
+
+    let tx = conn.transaction().map_err(|e| format!("Failed to begin transaction: {}", e))?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO connections (ip, asn, as_name, as_domain, country_code, country,
+                                      continent_code, continent, hit_count, packet_count, byte_count,
+                                      packets_in, packets_out, bytes_in, bytes_out, direction,
+                                      first_seen, last_seen, hostname, source_host, ip_class)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+                     ?20, ?21)
+             ON CONFLICT(ip) DO UPDATE SET
+                asn = COALESCE(asn, excluded.asn),
+                as_name = COALESCE(as_name, excluded.as_name),
+                as_domain = COALESCE(as_domain, excluded.as_domain),
+                country_code = COALESCE(country_code, excluded.country_code),
+                country = COALESCE(country, excluded.country),
+                continent_code = COALESCE(continent_code, excluded.continent_code),
+                continent = COALESCE(continent, excluded.continent),
+                hit_count = hit_count + excluded.hit_count,
+                packet_count = packet_count + excluded.packet_count,
+                byte_count = byte_count + excluded.byte_count,
+                packets_in = packets_in + excluded.packets_in,
+                packets_out = packets_out + excluded.packets_out,
+                bytes_in = bytes_in + excluded.bytes_in,
+                bytes_out = bytes_out + excluded.bytes_out,
+                direction = COALESCE(direction, excluded.direction),
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen),
+                hostname = COALESCE(hostname, excluded.hostname),
+                source_host = CASE
+                    WHEN excluded.source_host IS NULL OR excluded.source_host = '' THEN source_host
+                    WHEN source_host IS NULL OR source_host = '' THEN excluded.source_host
+                    WHEN instr(',' || source_host || ',', ',' || excluded.source_host || ',') > 0 THEN source_host
+                    ELSE source_host || ',' || excluded.source_host
+                END"
+        ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+        let mut merged = tx.prepare(
+            "INSERT OR IGNORE INTO merged_sources (source_host, merged_at) VALUES (?1, ?2)"
+        ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+        let staged_at = chrono::Utc::now().to_rfc3339();
+
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let c: IpConnection = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid connection on line {}: {}", n + 1, e))?;
+            let ip_class = c.ip_class.clone()
+                .or_else(|| address_class::classify_str(&c.ip).map(|class| class.as_str().to_string()));
+            stmt.execute(params![
+                c.ip, c.asn, c.as_name, c.as_domain, c.country_code, c.country,
+                c.continent_code, c.continent, c.hit_count as i64, c.packet_count as i64,
+                c.byte_count as i64, c.packets_in as i64, c.packets_out as i64,
+                c.bytes_in as i64, c.bytes_out as i64, c.direction, c.first_seen, c.last_seen,
+                c.hostname, c.source_host, ip_class,
+            ]).map_err(|e| format!("Failed to stage {}: {}", c.ip, e))?;
+            for label in c.source_host.iter().flat_map(|h| h.split(',')).filter(|l| !l.is_empty()) {
+                merged.execute(params![label, staged_at])
+                    .map_err(|e| format!("Failed to stage merged source {}: {}", label, e))?;
+            }
+        }
+    }
+    tx.commit().map_err(|e| format!("Failed to commit staged rows: {}", e))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,594 @@
+use rusqlite::{params, Connection};
+use std::path::{Path, PathBuf};
+
+/// One schema change. `version` is what `PRAGMA user_version` reads once it
+/// has been applied.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    up: fn(&Connection) -> Result<(), String>,
+}
+
+/// Every schema change, oldest first. Append only — never edit a migration
+/// that has shipped. The early ones tolerate tables and columns that already
+/// exist, since databases from before versioning are all at version 0.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "connections table",
+        up: |conn| {
+            batch(conn, "CREATE TABLE IF NOT EXISTS connections (
+                ip TEXT PRIMARY KEY,
+                asn TEXT,
+                as_name TEXT,
+                as_domain TEXT,
+                country_code TEXT,
+                country TEXT,
+                continent_code TEXT,
+                continent TEXT,
+                hit_count INTEGER DEFAULT 1,
+                first_seen TEXT NOT NULL,
+                last_seen TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_country_code ON connections(country_code);
+            CREATE INDEX IF NOT EXISTS idx_last_seen ON connections(last_seen);")
+        },
+    },
+    Migration {
+        version: 2,
+        description: "per-direction traffic counters",
+        up: |conn| {
+            add_column_if_missing(conn, "connections", "packet_count", "INTEGER NOT NULL DEFAULT 0")?;
+            add_column_if_missing(conn, "connections", "byte_count", "INTEGER NOT NULL DEFAULT 0")?;
+            add_column_if_missing(conn, "connections", "packets_in", "INTEGER NOT NULL DEFAULT 0")?;
+            add_column_if_missing(conn, "connections", "packets_out", "INTEGER NOT NULL DEFAULT 0")?;
+            add_column_if_missing(conn, "connections", "bytes_in", "INTEGER NOT NULL DEFAULT 0")?;
+            add_column_if_missing(conn, "connections", "bytes_out", "INTEGER NOT NULL DEFAULT 0")?;
+            add_column_if_missing(conn, "connections", "direction", "TEXT")
+        },
+    },
+    Migration {
+        version: 3,
+        description: "flows table",
+        up: |conn| {
+            batch(conn, "CREATE TABLE IF NOT EXISTS flows (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                src_ip TEXT NOT NULL,
+                src_port INTEGER NOT NULL,
+                dst_ip TEXT NOT NULL,
+                dst_port INTEGER NOT NULL,
+                protocol TEXT NOT NULL,
+                remote_ip TEXT NOT NULL,
+                direction TEXT NOT NULL,
+                tcp_flags INTEGER NOT NULL DEFAULT 0,
+                packet_count INTEGER NOT NULL DEFAULT 0,
+                byte_count INTEGER NOT NULL DEFAULT 0,
+                start_time TEXT NOT NULL,
+                end_time TEXT NOT NULL,
+                UNIQUE(src_ip, src_port, dst_ip, dst_port, protocol)
+            );
+            CREATE INDEX IF NOT EXISTS idx_flows_remote_ip ON flows(remote_ip);")
+        },
+    },
+    Migration {
+        version: 4,
+        description: "geolocation cache",
+        up: |conn| {
+            batch(conn, "CREATE TABLE IF NOT EXISTS geo_cache (
+                ip TEXT PRIMARY KEY,
+                asn TEXT,
+                as_name TEXT,
+                as_domain TEXT,
+                country_code TEXT,
+                country TEXT,
+                continent_code TEXT,
+                continent TEXT,
+                error TEXT,
+                fetched_at TEXT NOT NULL
+            );")
+        },
+    },
+    Migration {
+        version: 5,
+        description: "traffic time series",
+        up: |conn| {
+            batch(conn, "CREATE TABLE traffic_buckets (
+                ip TEXT NOT NULL,
+                resolution INTEGER NOT NULL,
+                bucket_start INTEGER NOT NULL,
+                packets_in INTEGER NOT NULL DEFAULT 0,
+                packets_out INTEGER NOT NULL DEFAULT 0,
+                bytes_in INTEGER NOT NULL DEFAULT 0,
+                bytes_out INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (ip, resolution, bucket_start)
+            );
+            CREATE INDEX idx_traffic_buckets_time ON traffic_buckets(resolution, bucket_start);")
+        },
+    },
+    Migration {
+        version: 6,
+        description: "source host labels for merged databases",
+        up: |conn| batch(conn, "ALTER TABLE connections ADD COLUMN source_host TEXT;"),
+    },
+    Migration {
+        version: 7,
+        description: "reverse DNS hostnames and cache",
+        up: |conn| batch(conn, "ALTER TABLE connections ADD COLUMN hostname TEXT;
+            CREATE TABLE rdns_cache (
+                ip TEXT PRIMARY KEY,
+                hostname TEXT,
+                expires_at TEXT NOT NULL
+            );"),
+    },
+    Migration {
+        version: 8,
+        description: "passive DNS answers",
+        up: |conn| batch(conn, "CREATE TABLE dns_answers (
+                ip TEXT NOT NULL,
+                domain TEXT NOT NULL,
+                ttl INTEGER NOT NULL,
+                first_seen TEXT NOT NULL,
+                last_seen TEXT NOT NULL,
+                PRIMARY KEY (ip, domain)
+            );
+            CREATE INDEX idx_dns_answers_domain ON dns_answers(domain);"),
+    },
+    Migration {
+        version: 9,
+        description: "server names from TLS, QUIC and HTTP",
+        up: |conn| batch(conn, "CREATE TABLE server_names (
+                src_ip TEXT NOT NULL,
+                src_port INTEGER NOT NULL,
+                dst_ip TEXT NOT NULL,
+                dst_port INTEGER NOT NULL,
+                protocol TEXT NOT NULL,
+                ip TEXT NOT NULL,
+                name TEXT NOT NULL,
+                source TEXT NOT NULL,
+                first_seen TEXT NOT NULL,
+                last_seen TEXT NOT NULL,
+                PRIMARY KEY (src_ip, src_port, dst_ip, dst_port, protocol)
+            );
+            CREATE INDEX idx_server_names_ip ON server_names(ip, last_seen);"),
+    },
+    Migration {
+        version: 10,
+        description: "owning process per flow",
+        up: |conn| batch(conn, "ALTER TABLE flows ADD COLUMN pid INTEGER;
+            ALTER TABLE flows ADD COLUMN process_name TEXT;
+            ALTER TABLE flows ADD COLUMN exe_path TEXT;
+            CREATE INDEX idx_flows_process ON flows(process_name);"),
+    },
+    Migration {
+        version: 11,
+        description: "address class per connection",
+        // Filled in by Database::connect, which can classify addresses
+        up: |conn| batch(conn, "ALTER TABLE connections ADD COLUMN ip_class TEXT;"),
+    },
+    Migration {
+        version: 12,
+        description: "capture interface per observation",
+        up: |conn| batch(conn, "ALTER TABLE flows ADD COLUMN interface TEXT;
+            CREATE INDEX idx_flows_interface ON flows(interface);
+            CREATE TABLE connection_interfaces (
+                ip TEXT NOT NULL,
+                interface TEXT NOT NULL,
+                packet_count INTEGER NOT NULL DEFAULT 0,
+                byte_count INTEGER NOT NULL DEFAULT 0,
+                first_seen TEXT NOT NULL,
+                last_seen TEXT NOT NULL,
+                PRIMARY KEY (ip, interface)
+            );
+            CREATE INDEX idx_connection_interfaces_interface ON connection_interfaces(interface);"),
+    },
+    Migration {
+        version: 13,
+        description: "tunnel per flow",
+        up: |conn| batch(conn, "ALTER TABLE flows ADD COLUMN tunnel TEXT;
+            ALTER TABLE flows ADD COLUMN tunnel_endpoint TEXT;"),
+    },
+    Migration {
+        version: 14,
+        description: "one flows row per connection, not per 5-tuple",
+        // SQLite can't drop a UNIQUE constraint, so the table is rebuilt
+        up: |conn| batch(conn, "CREATE TABLE flows_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                src_ip TEXT NOT NULL,
+                src_port INTEGER NOT NULL,
+                dst_ip TEXT NOT NULL,
+                dst_port INTEGER NOT NULL,
+                protocol TEXT NOT NULL,
+                remote_ip TEXT NOT NULL,
+                direction TEXT NOT NULL,
+                tcp_flags INTEGER NOT NULL DEFAULT 0,
+                packet_count INTEGER NOT NULL DEFAULT 0,
+                byte_count INTEGER NOT NULL DEFAULT 0,
+                start_time TEXT NOT NULL,
+                end_time TEXT NOT NULL,
+                pid INTEGER,
+                process_name TEXT,
+                exe_path TEXT,
+                interface TEXT,
+                tunnel TEXT,
+                tunnel_endpoint TEXT
+            );
+            INSERT INTO flows_new (id, src_ip, src_port, dst_ip, dst_port, protocol, remote_ip,
+                                   direction, tcp_flags, packet_count, byte_count, start_time, end_time,
+                                   pid, process_name, exe_path, interface, tunnel, tunnel_endpoint)
+                SELECT id, src_ip, src_port, dst_ip, dst_port, protocol, remote_ip,
+                       direction, tcp_flags, packet_count, byte_count, start_time, end_time,
+                       pid, process_name, exe_path, interface, tunnel, tunnel_endpoint
+                FROM flows;
+            DROP TABLE flows;
+            ALTER TABLE flows_new RENAME TO flows;
+            CREATE INDEX idx_flows_remote_ip ON flows(remote_ip);
+            CREATE INDEX idx_flows_process ON flows(process_name);
+            CREATE INDEX idx_flows_interface ON flows(interface);
+            CREATE INDEX idx_flows_tuple ON flows(src_ip, src_port, dst_ip, dst_port, protocol, end_time);"),
+    },
+    Migration {
+        version: 15,
+        description: "source hosts already merged",
+        // Labels already on connections were merged before this table existed
+        up: |conn| batch(conn, "CREATE TABLE merged_sources (
+                source_host TEXT PRIMARY KEY,
+                merged_at TEXT NOT NULL
+            );
+            WITH RECURSIVE labels(label, rest) AS (
+                SELECT '', source_host || ',' FROM connections
+                WHERE source_host IS NOT NULL AND source_host != ''
+                UNION
+                SELECT substr(rest, 1, instr(rest, ',') - 1), substr(rest, instr(rest, ',') + 1)
+                FROM labels WHERE rest != ''
+            )
+            INSERT OR IGNORE INTO merged_sources (source_host, merged_at)
+                SELECT label, strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now') FROM labels WHERE label != '';"),
+    },
+];
+
+/// Schema version this build expects
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Bring the database at `db_path` up to the latest schema. An existing
+/// database is copied to `<db_path>.v<N>.bak` first, and each migration is
+/// applied in its own transaction together with the version bump.
+pub fn migrate(conn: &mut Connection, db_path: &Path) -> Result<(), String> {
+    let current = user_version(conn)?;
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(format!(
+            "Database schema v{} is newer than this version of SNIFFF supports (v{})",
+            current, latest
+        ));
+    }
+    if current == latest {
+        return Ok(());
+    }
+
+    if has_tables(conn)? {
+        let backup = backup_path(db_path, current);
+        backup_to(conn, &backup)?;
+        log::info!(
+            "[SNIFFF] Backed up database to {} before upgrading from schema v{}",
+            backup.display(),
+            current
+        );
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.transaction()
+            .map_err(|e| format!("Failed to start migration: {}", e))?;
+        (migration.up)(&tx)
+            .map_err(|e| format!("Migration v{} ({}) failed: {}", migration.version, migration.description, e))?;
+        tx.pragma_update(None, "user_version", migration.version)
+            .map_err(|e| format!("Failed to record schema v{}: {}", migration.version, e))?;
+        tx.commit()
+            .map_err(|e| format!("Failed to commit migration v{}: {}", migration.version, e))?;
+        log::info!("[SNIFFF] Applied schema v{}: {}", migration.version, migration.description);
+    }
+
+    Ok(())
+}
+
+fn user_version(conn: &Connection) -> Result<u32, String> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| format!("Failed to read schema version: {}", e))
+}
+
+fn has_tables(conn: &Connection) -> Result<bool, String> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'",
+        [],
+        |row| row.get(0),
+    ).map_err(|e| format!("Failed to inspect database: {}", e))?;
+    Ok(count > 0)
+}
+
+fn backup_path(db_path: &Path, version: u32) -> PathBuf {
+    let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    db_path.with_file_name(name)
+}
+
+/// Write a consistent copy of the database with `VACUUM INTO`
+fn backup_to(conn: &Connection, path: &Path) -> Result<(), String> {
+    // VACUUM INTO refuses to overwrite, and an older backup of the same
+    // version is no more useful than a fresh one
+    if path.exists() {
+        std::fs::remove_file(path)
+            .map_err(|e| format!("Failed to replace old backup {}: {}", path.display(), e))?;
+    }
+    conn.execute("VACUUM INTO ?1", params![path.to_string_lossy()])
+        .map_err(|e| format!("Failed to back up database to {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+fn batch(conn: &Connection, sql: &str) -> Result<(), String> {
+    conn.execute_batch(sql).map_err(|e| e.to_string())
+}
+
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), String> {
+    let exists: bool = conn.query_row(
+        &format!("SELECT COUNT(*) FROM pragma_table_info('{}') WHERE name = ?1", table),
+        params![column],
+        |row| row.get::<_, i64>(0),
+    ).map_err(|e| format!("Failed to inspect table {}: {}", table, e))? > 0;
+
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .map_err(|e| format!("Failed to add column {}.{}: {}", table, column, e))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// A scratch database file, removed with its directory on drop
+    struct TempDb {
+        _dir: TempDir,
+        path: PathBuf,
+    }
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let dir = TempDir::new(&format!("migrations-{}", name));
+            let path = dir.join("snifff.db");
+            Self { _dir: dir, path }
+        }
+
+        fn open(&self) -> Connection {
+            Connection::open(&self.path).unwrap()
+        }
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn table_exists(conn: &Connection, table: &str) -> bool {
+        conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            params![table],
+            |row| row.get::<_, i64>(0),
+        )
+        .unwrap()
+            > 0
+    }
+
+    fn index_exists(conn: &Connection, index: &str) -> bool {
+        conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = ?1",
+            params![index],
+            |row| row.get::<_, i64>(0),
+        )
+        .unwrap()
+            > 0
+    }
+
+    /// Apply migrations up to and including `version`, as an older build would have
+    fn migrate_to(conn: &Connection, version: u32) {
+        for migration in MIGRATIONS.iter().filter(|m| m.version <= version) {
+            (migration.up)(conn).unwrap();
+        }
+        conn.pragma_update(None, "user_version", version).unwrap();
+    }
+
+    fn assert_latest_schema(conn: &Connection) {
+        assert_eq!(user_version(conn).unwrap(), latest_version());
+        let cols = columns(conn, "connections");
+        for col in ["packet_count", "byte_count", "packets_in", "packets_out", "bytes_in", "bytes_out", "direction", "source_host", "hostname", "ip_class"] {
+            assert!(cols.iter().any(|c| c == col), "connections.{} missing", col);
+        }
+        assert!(table_exists(conn, "flows"));
+        let cols = columns(conn, "flows");
+        for col in ["pid", "process_name", "exe_path", "interface", "tunnel", "tunnel_endpoint"] {
+            assert!(cols.iter().any(|c| c == col), "flows.{} missing", col);
+        }
+        assert!(table_exists(conn, "geo_cache"));
+        assert!(table_exists(conn, "traffic_buckets"));
+        assert!(table_exists(conn, "rdns_cache"));
+        assert!(table_exists(conn, "dns_answers"));
+        assert!(table_exists(conn, "server_names"));
+        assert!(table_exists(conn, "connection_interfaces"));
+        assert!(index_exists(conn, "idx_flows_tuple"));
+        assert!(table_exists(conn, "merged_sources"));
+    }
+
+    #[test]
+    fn fresh_database_gets_latest_schema_without_backup() {
+        let db = TempDb::new("fresh");
+        let mut conn = db.open();
+
+        migrate(&mut conn, &db.path).unwrap();
+
+        assert_latest_schema(&conn);
+        assert!(!backup_path(&db.path, 0).exists());
+    }
+
+    #[test]
+    fn unversioned_original_schema_upgrades_and_keeps_rows() {
+        let db = TempDb::new("original");
+        let mut conn = db.open();
+        // The schema every release before traffic counters shipped with
+        conn.execute_batch(
+            "CREATE TABLE connections (
+                ip TEXT PRIMARY KEY,
+                asn TEXT,
+                as_name TEXT,
+                as_domain TEXT,
+                country_code TEXT,
+                country TEXT,
+                continent_code TEXT,
+                continent TEXT,
+                hit_count INTEGER DEFAULT 1,
+                first_seen TEXT NOT NULL,
+                last_seen TEXT NOT NULL
+            );
+            CREATE INDEX idx_country_code ON connections(country_code);
+            CREATE INDEX idx_last_seen ON connections(last_seen);
+            INSERT INTO connections (ip, country_code, hit_count, first_seen, last_seen)
+            VALUES ('1.1.1.1', 'AU', 7, '2024-01-01T00:00:00Z', '2024-01-02T00:00:00Z');",
+        )
+        .unwrap();
+
+        migrate(&mut conn, &db.path).unwrap();
+
+        assert_latest_schema(&conn);
+        let (hits, packets): (i64, i64) = conn
+            .query_row(
+                "SELECT hit_count, packet_count FROM connections WHERE ip = '1.1.1.1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((hits, packets), (7, 0));
+
+        // The backup holds the database as it was before the upgrade
+        let backup = Connection::open(backup_path(&db.path, 0)).unwrap();
+        assert!(!columns(&backup, "connections").iter().any(|c| c == "packet_count"));
+        assert_eq!(user_version(&backup).unwrap(), 0);
+    }
+
+    #[test]
+    fn unversioned_database_with_partial_columns_upgrades() {
+        // Built while columns were still added ad hoc: counters exist, version doesn't
+        let db = TempDb::new("partial");
+        let mut conn = db.open();
+        migrate_to(&conn, 3);
+        conn.pragma_update(None, "user_version", 0).unwrap();
+
+        migrate(&mut conn, &db.path).unwrap();
+
+        assert_latest_schema(&conn);
+    }
+
+    #[test]
+    fn every_older_version_upgrades_cleanly() {
+        for version in 1..latest_version() {
+            let db = TempDb::new(&format!("v{}", version));
+            let mut conn = db.open();
+            migrate_to(&conn, version);
+
+            migrate(&mut conn, &db.path).unwrap();
+
+            assert_latest_schema(&conn);
+            assert!(backup_path(&db.path, version).exists(), "no backup for v{}", version);
+        }
+    }
+
+    #[test]
+    fn flows_keep_their_rows_and_may_repeat_a_5_tuple() {
+        let db = TempDb::new("flows");
+        let mut conn = db.open();
+        migrate_to(&conn, 13);
+        let insert = "INSERT INTO flows (src_ip, src_port, dst_ip, dst_port, protocol, remote_ip,
+                                         direction, packet_count, start_time, end_time, interface)
+                      VALUES ('10.0.0.2', 40000, '1.1.1.1', 443, 'tcp', '1.1.1.1',
+                              'outbound', ?1, '2024-01-01T00:00:00Z', '2024-01-01T00:01:00Z', 'en0')";
+        conn.execute(insert, params![5]).unwrap();
+        assert!(conn.execute(insert, params![6]).is_err());
+
+        migrate(&mut conn, &db.path).unwrap();
+
+        conn.execute(insert, params![6]).unwrap();
+        let packets: Vec<i64> = conn
+            .prepare("SELECT packet_count FROM flows WHERE interface = 'en0' ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(packets, vec![5, 6]);
+    }
+
+    #[test]
+    fn labels_already_merged_are_remembered() {
+        let db = TempDb::new("merged");
+        let mut conn = db.open();
+        migrate_to(&conn, 14);
+        for (ip, label) in [("1.1.1.1", Some("laptop,desktop")), ("8.8.8.8", Some("laptop")), ("9.9.9.9", None)] {
+            conn.execute(
+                "INSERT INTO connections (ip, first_seen, last_seen, source_host)
+                 VALUES (?1, '2024-01-01T00:00:00Z', '2024-01-01T00:00:00Z', ?2)",
+                params![ip, label],
+            )
+            .unwrap();
+        }
+
+        migrate(&mut conn, &db.path).unwrap();
+
+        let labels: Vec<String> = conn
+            .prepare("SELECT source_host FROM merged_sources ORDER BY source_host")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(labels, vec!["desktop", "laptop"]);
+    }
+
+    #[test]
+    fn migrating_twice_is_a_no_op() {
+        let db = TempDb::new("twice");
+        let mut conn = db.open();
+        migrate(&mut conn, &db.path).unwrap();
+        migrate(&mut conn, &db.path).unwrap();
+
+        assert_latest_schema(&conn);
+        assert!(!backup_path(&db.path, latest_version()).exists());
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let db = TempDb::new("newer");
+        let mut conn = db.open();
+        conn.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+
+        assert!(migrate(&mut conn, &db.path).is_err());
+    }
+
+    #[test]
+    fn migrations_are_numbered_in_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1);
+        }
+    }
+}
@@ -1,22 +1,80 @@
-use std::process::Command;
-use std::path::Path;
-use std::fs;
+use serde::Serialize;
 
-/// Check if BPF devices are readable by the current user
-pub fn check_bpf_access() -> bool {
-    Path::new("/dev/bpf0").exists() && {
+/// Whether we can capture packets, and what to do about it if not
+#[derive(Debug, Clone, Serialize)]
+pub struct PermissionStatus {
+    pub platform: &'static str,
+    pub granted: bool,
+    /// How access is granted on this platform, e.g. "bpf-group" or "capabilities"
+    pub method: &'static str,
+    /// Plain description of the current state
+    pub detail: String,
+    /// Commands that grant access by hand
+    pub instructions: Vec<String>,
+    /// Whether `request_access` can grant access from inside the app
+    pub can_request: bool,
+    /// Access was granted but only takes effect after the app restarts
+    pub restart_required: bool,
+}
+
+/// Check whether packet capture is currently possible
+pub fn check_status() -> PermissionStatus {
+    platform::check_status()
+}
+
+/// Ensure capture access — check first, request if needed
+pub fn ensure_access() -> Result<PermissionStatus, String> {
+    let status = platform::check_status();
+    if status.granted {
+        log::info!("Capture access already available");
+        return Ok(status);
+    }
+
+    if !status.can_request {
+        return Ok(status);
+    }
+
+    log::info!("Capture access not available, requesting authorization...");
+    platform::request_access()
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::PermissionStatus;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+
+    /// Check if BPF devices are readable by the current user
+    fn check_bpf_access() -> bool {
         // Try to open /dev/bpf0 for reading
-        match fs::File::open("/dev/bpf0") {
-            Ok(_) => true,
-            Err(_) => false,
+        Path::new("/dev/bpf0").exists() && fs::File::open("/dev/bpf0").is_ok()
+    }
+
+    pub fn check_status() -> PermissionStatus {
+        let granted = check_bpf_access();
+        PermissionStatus {
+            platform: "macos",
+            granted,
+            method: "bpf-group",
+            detail: if granted {
+                "BPF devices are readable".to_string()
+            } else {
+                "BPF devices are not readable by this user".to_string()
+            },
+            instructions: vec![
+                "sudo dseditgroup -o edit -a $USER -t user access_bpf".to_string(),
+                "sudo chgrp access_bpf /dev/bpf* && sudo chmod g+r /dev/bpf*".to_string(),
+            ],
+            can_request: true,
+            restart_required: false,
         }
     }
-}
 
-/// Request BPF access via native macOS authorization dialog.
-/// Installs a LaunchDaemon that persists across reboots.
-pub fn request_bpf_access() -> Result<(), String> {
-    let script = r#"
+    /// Request BPF access via native macOS authorization dialog.
+    /// Installs a LaunchDaemon that persists across reboots.
+    fn request_bpf_access() -> Result<(), String> {
+        let script = r#"
         -- Create access_bpf group if it doesn't exist
         do shell script "
             /usr/sbin/dseditgroup -o read access_bpf 2>/dev/null || /usr/sbin/dseditgroup -o create access_bpf
@@ -55,36 +113,150 @@ PLIST
         " with administrator privileges
     "#;
 
-    let output = Command::new("osascript")
-        .arg("-e")
-        .arg(script)
-        .output()
-        .map_err(|e| format!("Failed to run osascript: {}", e))?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Authorization failed: {}", stderr))
+        let output = Command::new("osascript")
+            .arg("-e")
+            .arg(script)
+            .output()
+            .map_err(|e| format!("Failed to run osascript: {}", e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(format!("Authorization failed: {}", stderr))
+        }
+    }
+
+    pub fn request_access() -> Result<PermissionStatus, String> {
+        request_bpf_access()?;
+
+        // Verify access after setup
+        let mut status = check_status();
+        if status.granted {
+            log::info!("BPF access granted successfully");
+        } else {
+            // Might need a logout/login for group membership to take effect
+            status.detail = "Access configured; log out and back in for group membership to apply".to_string();
+        }
+        Ok(status)
     }
 }
 
-/// Ensure BPF access — check first, request if needed
-pub fn ensure_bpf_access() -> Result<bool, String> {
-    if check_bpf_access() {
-        log::info!("BPF access already available");
-        return Ok(true);
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::PermissionStatus;
+    use std::fs;
+    use std::process::Command;
+
+    const CAP_NET_ADMIN: u32 = 12;
+    const CAP_NET_RAW: u32 = 13;
+
+    /// Effective UID and capability mask of this process, from /proc/self/status
+    fn effective_credentials() -> Option<(u32, u64)> {
+        let status = fs::read_to_string("/proc/self/status").ok()?;
+        let mut euid = None;
+        let mut cap_eff = None;
+
+        for line in status.lines() {
+            if let Some(rest) = line.strip_prefix("Uid:") {
+                // Real, effective, saved, filesystem
+                euid = rest.split_whitespace().nth(1).and_then(|v| v.parse().ok());
+            } else if let Some(rest) = line.strip_prefix("CapEff:") {
+                cap_eff = u64::from_str_radix(rest.trim(), 16).ok();
+            }
+        }
+
+        Some((euid?, cap_eff?))
+    }
+
+    fn has_cap(mask: u64, cap: u32) -> bool {
+        mask & (1 << cap) != 0
+    }
+
+    fn setcap_command() -> String {
+        let exe = std::env::current_exe()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|_| "/path/to/snifff".to_string());
+        format!("setcap cap_net_raw,cap_net_admin=eip '{}'", exe)
+    }
+
+    pub fn check_status() -> PermissionStatus {
+        let (granted, detail) = match effective_credentials() {
+            Some((0, _)) => (true, "Running as root".to_string()),
+            Some((_, mask)) if has_cap(mask, CAP_NET_RAW) => {
+                let detail = if has_cap(mask, CAP_NET_ADMIN) {
+                    "CAP_NET_RAW and CAP_NET_ADMIN are effective".to_string()
+                } else {
+                    "CAP_NET_RAW is effective; CAP_NET_ADMIN is missing, so promiscuous mode may fail".to_string()
+                };
+                (true, detail)
+            }
+            Some(_) => (false, "Missing CAP_NET_RAW; packet capture needs raw socket access".to_string()),
+            None => (false, "Could not read process capabilities from /proc/self/status".to_string()),
+        };
+
+        PermissionStatus {
+            platform: "linux",
+            granted,
+            method: "capabilities",
+            detail,
+            instructions: vec![format!("sudo {}", setcap_command())],
+            can_request: which("pkexec") && which("setcap"),
+            restart_required: false,
+        }
+    }
+
+    fn which(program: &str) -> bool {
+        std::env::var_os("PATH")
+            .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+            .unwrap_or(false)
     }
 
-    log::info!("BPF access not available, requesting authorization...");
-    request_bpf_access()?;
+    /// Grant the binary file capabilities via pkexec. They only apply to new
+    /// processes, so the app has to be restarted afterwards.
+    pub fn request_access() -> Result<PermissionStatus, String> {
+        let exe = std::env::current_exe()
+            .map_err(|e| format!("Failed to locate executable: {}", e))?;
+
+        let output = Command::new("pkexec")
+            .arg("setcap")
+            .arg("cap_net_raw,cap_net_admin=eip")
+            .arg(&exe)
+            .output()
+            .map_err(|e| format!("Failed to run pkexec: {}", e))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Authorization failed: {}", stderr));
+        }
+
+        log::info!("Capabilities set on {}", exe.display());
+        let mut status = check_status();
+        if !status.granted {
+            status.restart_required = true;
+            status.detail = "Capabilities granted; restart SNIFFF for them to take effect".to_string();
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+mod platform {
+    use super::PermissionStatus;
+
+    pub fn check_status() -> PermissionStatus {
+        PermissionStatus {
+            platform: std::env::consts::OS,
+            granted: true,
+            method: "none",
+            detail: "No capture permission check on this platform".to_string(),
+            instructions: Vec::new(),
+            can_request: false,
+            restart_required: false,
+        }
+    }
 
-    // Verify access after setup
-    if check_bpf_access() {
-        log::info!("BPF access granted successfully");
-        Ok(true)
-    } else {
-        // Might need a logout/login for group membership to take effect
-        Ok(false)
+    pub fn request_access() -> Result<PermissionStatus, String> {
+        Ok(check_status())
     }
 }
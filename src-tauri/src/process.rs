@@ -0,0 +1,478 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::db::Database;
+use crate::sniffer::{FlowTraffic, FLOW_IDLE_TIMEOUT};
+
+/// Flows remembered as already looked up, so each is only attributed once
+const ATTRIBUTED_FLOWS: usize = 16_384;
+
+/// The process that owns a local socket
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    /// `None` when the executable link can't be read, e.g. for another
+    /// user's process without root
+    pub exe_path: Option<String>,
+}
+
+/// Maps local sockets to the processes that own them. Implemented per
+/// platform; `platform_resolver` picks the one for this OS.
+pub trait ProcessResolver: Send {
+    /// Re-read the OS socket and process tables
+    fn refresh(&mut self) -> Result<(), String>;
+
+    /// The process owning the `protocol` ("tcp"/"udp") socket bound to `local`,
+    /// as of the last `refresh`
+    fn lookup(&self, protocol: &str, local: SocketAddr) -> Option<ProcessInfo>;
+}
+
+/// The resolver for this platform, if there is one
+pub fn platform_resolver() -> Option<Box<dyn ProcessResolver>> {
+    platform::resolver()
+}
+
+/// A flow's 5-tuple, as recorded, and the process behind it
+#[derive(Debug, Clone)]
+pub struct FlowProcess {
+    pub src_ip: String,
+    pub src_port: u16,
+    pub dst_ip: String,
+    pub dst_port: u16,
+    pub protocol: String,
+    pub process: ProcessInfo,
+}
+
+type FlowId = (String, u16, String, u16, String);
+
+/// Attributes captured flows to local processes on a worker thread, so
+/// walking `/proc` never holds up the capture
+pub struct ProcessTracker {
+    tx: Option<mpsc::Sender<Vec<FlowTraffic>>>,
+}
+
+impl ProcessTracker {
+    pub fn start(resolver: Option<Box<dyn ProcessResolver>>, db: Arc<Database>) -> Self {
+        let Some(resolver) = resolver else {
+            log::info!("[SNIFFF] Per-process attribution is not supported on {}", std::env::consts::OS);
+            return Self { tx: None };
+        };
+
+        let (tx, rx) = mpsc::channel::<Vec<FlowTraffic>>();
+        let mut attributor = Attributor::new(resolver);
+        thread::spawn(move || {
+            while let Ok(mut flows) = rx.recv() {
+                // Catch up on whatever queued meanwhile with a single refresh
+                while let Ok(more) = rx.try_recv() {
+                    flows.extend(more);
+                }
+                let attributed = attributor.attribute(&flows);
+                if let Err(e) = db.record_flow_processes(&attributed) {
+                    log::error!("[SNIFFF] Failed to record processes for {} flows: {}", attributed.len(), e);
+                }
+            }
+        });
+        Self { tx: Some(tx) }
+    }
+
+    /// Queue flows for attribution. Sockets closed since the flow's last
+    /// packet can no longer be attributed, so this should be called on live
+    /// batches as soon as their flows are recorded.
+    pub fn attribute(&self, flows: Vec<FlowTraffic>) {
+        if let Some(tx) = &self.tx {
+            if !flows.is_empty() && tx.send(flows).is_err() {
+                log::error!("[SNIFFF] Process attribution worker has stopped");
+            }
+        }
+    }
+}
+
+/// Looks up flows' processes, once per flow
+struct Attributor {
+    resolver: Box<dyn ProcessResolver>,
+    /// Flows already attributed, with the last time they were seen. Ones
+    /// whose lookup failed aren't kept, so they are tried again with the next
+    /// batch; ones idle for longer than `FLOW_IDLE_TIMEOUT` are recorded as a
+    /// new flow and looked up again.
+    attributed: LruCache<FlowId, DateTime<Utc>>,
+}
+
+impl Attributor {
+    fn new(resolver: Box<dyn ProcessResolver>) -> Self {
+        Self {
+            resolver,
+            attributed: LruCache::new(NonZeroUsize::new(ATTRIBUTED_FLOWS).unwrap()),
+        }
+    }
+
+    fn attribute(&mut self, flows: &[FlowTraffic]) -> Vec<FlowProcess> {
+        // Batches queued together may repeat a flow
+        let mut queued = HashSet::new();
+        let fresh: Vec<&FlowTraffic> = flows
+            .iter()
+            .filter(|f| {
+                let id = flow_id(f);
+                if let Some(last_seen) = self.attributed.get_mut(&id) {
+                    if *last_seen >= f.start_time - FLOW_IDLE_TIMEOUT {
+                        *last_seen = (*last_seen).max(f.end_time);
+                        return false;
+                    }
+                }
+                queued.insert(id)
+            })
+            .collect();
+        if fresh.is_empty() {
+            return Vec::new();
+        }
+
+        if let Err(e) = self.resolver.refresh() {
+            log::warn!("[SNIFFF] Failed to read socket table: {}", e);
+            return Vec::new();
+        }
+
+        fresh
+            .into_iter()
+            .filter_map(|f| {
+                let process = self.resolver.lookup(&f.protocol, local_side(f)?)?;
+                self.attributed.put(flow_id(f), f.end_time);
+                Some(FlowProcess {
+                    src_ip: f.src_ip.clone(),
+                    src_port: f.src_port,
+                    dst_ip: f.dst_ip.clone(),
+                    dst_port: f.dst_port,
+                    protocol: f.protocol.clone(),
+                    process,
+                })
+            })
+            .collect()
+    }
+}
+
+fn flow_id(f: &FlowTraffic) -> FlowId {
+    (f.src_ip.clone(), f.src_port, f.dst_ip.clone(), f.dst_port, f.protocol.clone())
+}
+
+/// Our end of the flow: whichever side isn't the remote IP
+fn local_side(f: &FlowTraffic) -> Option<SocketAddr> {
+    let (ip, port) = if f.dst_ip == f.remote_ip {
+        (&f.src_ip, f.src_port)
+    } else {
+        (&f.dst_ip, f.dst_port)
+    };
+    Some(SocketAddr::new(ip.parse::<IpAddr>().ok()?, port))
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::{ProcessInfo, ProcessResolver};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::path::Path;
+
+    pub fn resolver() -> Option<Box<dyn ProcessResolver>> {
+        Some(Box::new(ProcNet::default()))
+    }
+
+    /// Socket owners from `/proc/net/{tcp,udp,tcp6,udp6}`, matched to
+    /// processes through the `socket:[inode]` links in `/proc/<pid>/fd`.
+    /// Without root only this user's processes can be seen.
+    #[derive(Default)]
+    struct ProcNet {
+        /// (protocol, local address) → socket inode
+        sockets: HashMap<(&'static str, SocketAddr), u64>,
+        /// socket inode → owning process
+        owners: HashMap<u64, ProcessInfo>,
+    }
+
+    impl ProcessResolver for ProcNet {
+        fn refresh(&mut self) -> Result<(), String> {
+            self.sockets.clear();
+            for (file, protocol) in [("tcp", "tcp"), ("tcp6", "tcp"), ("udp", "udp"), ("udp6", "udp")] {
+                // IPv6 may be disabled, leaving no tcp6/udp6
+                let Ok(table) = fs::read_to_string(format!("/proc/net/{}", file)) else {
+                    continue;
+                };
+                for (local, inode) in table.lines().skip(1).filter_map(parse_socket_line) {
+                    self.sockets.insert((protocol, local), inode);
+                }
+            }
+
+            self.owners = socket_owners()?;
+            Ok(())
+        }
+
+        fn lookup(&self, protocol: &str, local: SocketAddr) -> Option<ProcessInfo> {
+            let protocol = match protocol {
+                "tcp" => "tcp",
+                "udp" => "udp",
+                _ => return None,
+            };
+            let port = local.port();
+            // Exact binding first, then wildcard and dual-stack sockets
+            let candidates = match local.ip() {
+                IpAddr::V4(v4) => vec![
+                    IpAddr::V4(v4),
+                    IpAddr::V6(v4.to_ipv6_mapped()),
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                ],
+                IpAddr::V6(v6) => vec![IpAddr::V6(v6), IpAddr::V6(Ipv6Addr::UNSPECIFIED)],
+            };
+
+            candidates
+                .into_iter()
+                .filter_map(|ip| self.sockets.get(&(protocol, SocketAddr::new(ip, port))))
+                .find_map(|inode| self.owners.get(inode).cloned())
+        }
+    }
+
+    /// Local address and inode from one `/proc/net/*` row
+    fn parse_socket_line(line: &str) -> Option<(SocketAddr, u64)> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (addr, port) = fields.get(1)?.split_once(':')?;
+        let inode: u64 = fields.get(9)?.parse().ok()?;
+        // Sockets mid-teardown have no inode left to match
+        if inode == 0 {
+            return None;
+        }
+        Some((SocketAddr::new(parse_hex_addr(addr)?, u16::from_str_radix(port, 16).ok()?), inode))
+    }
+
+    /// The kernel prints addresses as 32-bit words in host byte order
+    fn parse_hex_addr(hex: &str) -> Option<IpAddr> {
+        let word = |i: usize| -> Option<[u8; 4]> {
+            Some(u32::from_str_radix(hex.get(i * 8..i * 8 + 8)?, 16).ok()?.to_ne_bytes())
+        };
+        match hex.len() {
+            8 => Some(IpAddr::V4(Ipv4Addr::from(word(0)?))),
+            32 => {
+                let mut octets = [0u8; 16];
+                for i in 0..4 {
+                    octets[i * 4..i * 4 + 4].copy_from_slice(&word(i)?);
+                }
+                Some(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Socket inode → process, for every process whose fds we can read
+    fn socket_owners() -> Result<HashMap<u64, ProcessInfo>, String> {
+        let mut owners = HashMap::new();
+        let procs = fs::read_dir("/proc").map_err(|e| format!("Failed to read /proc: {}", e))?;
+
+        let mut pids: Vec<u32> = procs
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect();
+        pids.sort_unstable();
+
+        for pid in pids {
+            let dir = Path::new("/proc").join(pid.to_string());
+            // Processes exit and deny access all the time; skip them quietly
+            let Ok(fds) = fs::read_dir(dir.join("fd")) else {
+                continue;
+            };
+
+            let mut process = None;
+            for fd in fds.flatten() {
+                let Ok(target) = fs::read_link(fd.path()) else {
+                    continue;
+                };
+                let Some(inode) = target
+                    .to_str()
+                    .and_then(|t| t.strip_prefix("socket:["))
+                    .and_then(|t| t.strip_suffix(']'))
+                    .and_then(|t| t.parse::<u64>().ok())
+                else {
+                    continue;
+                };
+
+                let info = process.get_or_insert_with(|| ProcessInfo {
+                    pid,
+                    name: fs::read_to_string(dir.join("comm"))
+                        .map(|n| n.trim_end().to_string())
+                        .unwrap_or_default(),
+                    exe_path: fs::read_link(dir.join("exe"))
+                        .ok()
+                        .map(|p| p.display().to_string()),
+                });
+                // A socket shared after fork stays with the lowest pid, usually the parent
+                owners.entry(inode).or_insert_with(|| info.clone());
+            }
+        }
+
+        Ok(owners)
+    }
+
+    #[cfg(all(test, target_endian = "little"))]
+    mod tests {
+        use super::*;
+
+        const TCP6: &str = "\
+  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000000000000:1F90 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 34567 1 0000000000000000 100 0 0 10 0
+   1: 0000000000000000FFFF00000100007F:9C40 0000000000000000FFFF00000100007F:1F90 01 00000000:00000000 00:00000000 00000000  1000        0 45678 1 0000000000000000 20 4 30 10 -1
+   2: B80D0120000000000000000001000000:01BB B80D0120000000000000000002000000:D431 01 00000000:00000000 02:000A7B4C 00000000     0        0 56789 2 0000000000000000 20 4 31 10 -1
+   3: B80D0120000000000000000001000000:01BB B80D0120000000000000000003000000:D432 06 00000000:00000000 03:00001770 00000000     0        0 0 3 0000000000000000
+";
+
+        fn addr(s: &str) -> SocketAddr {
+            s.parse().unwrap()
+        }
+
+        #[test]
+        fn parses_tcp6_rows() {
+            let rows: Vec<_> = TCP6.lines().skip(1).filter_map(parse_socket_line).collect();
+            assert_eq!(
+                rows,
+                vec![
+                    (addr("[::]:8080"), 34567),
+                    (addr("[::ffff:127.0.0.1]:40000"), 45678),
+                    (addr("[2001:db8::1]:443"), 56789),
+                ]
+            );
+        }
+
+        #[test]
+        fn parses_tcp4_rows() {
+            let line = "   0: 0100007F:0035 00000000:0000 0A 00000000:00000000 00:00000000 00000000   101        0 23456 1 0000000000000000 100 0 0 10 0";
+            assert_eq!(parse_socket_line(line), Some((addr("127.0.0.1:53"), 23456)));
+        }
+
+        #[test]
+        fn rejects_malformed_addresses() {
+            assert_eq!(parse_hex_addr("0100007F"), Some("127.0.0.1".parse().unwrap()));
+            assert_eq!(parse_hex_addr("0100007"), None);
+            assert_eq!(parse_hex_addr("0100007G"), None);
+            assert_eq!(parse_hex_addr("B80D012000000000000000000100000"), None);
+            assert_eq!(parse_socket_line("   0: 0100007F 00000000:0000 0A"), None);
+            assert_eq!(parse_socket_line(""), None);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use super::ProcessResolver;
+
+    pub fn resolver() -> Option<Box<dyn ProcessResolver>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sniffer::Direction;
+    use chrono::Utc;
+
+    /// Owns only the sockets it is given; counts refreshes
+    struct FakeResolver {
+        sockets: Vec<SocketAddr>,
+        refreshes: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl ProcessResolver for FakeResolver {
+        fn refresh(&mut self) -> Result<(), String> {
+            self.refreshes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn lookup(&self, _protocol: &str, local: SocketAddr) -> Option<ProcessInfo> {
+            self.sockets.contains(&local).then(|| ProcessInfo {
+                pid: 42,
+                name: "curl".to_string(),
+                exe_path: None,
+            })
+        }
+    }
+
+    fn flow(src_port: u16) -> FlowTraffic {
+        FlowTraffic {
+            src_ip: "192.168.1.20".to_string(),
+            src_port,
+            dst_ip: "1.1.1.1".to_string(),
+            dst_port: 443,
+            protocol: "tcp".to_string(),
+            remote_ip: "1.1.1.1".to_string(),
+            direction: Direction::Outbound,
+            tcp_flags: 0,
+            packets: 1,
+            bytes: 60,
+            start_time: Utc::now(),
+            end_time: Utc::now(),
+            tunnel: None,
+            tunnel_endpoint: None,
+        }
+    }
+
+    fn ports(attributed: &[FlowProcess]) -> Vec<u16> {
+        attributed.iter().map(|a| a.src_port).collect()
+    }
+
+    #[test]
+    fn flows_are_attributed_once_and_misses_are_retried() {
+        let refreshes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut attributor = Attributor::new(Box::new(FakeResolver {
+            sockets: vec!["192.168.1.20:40000".parse().unwrap()],
+            refreshes: refreshes.clone(),
+        }));
+
+        let batch = [flow(40000), flow(40001), flow(40000)];
+        assert_eq!(ports(&attributor.attribute(&batch)), vec![40000]);
+        assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // 40000 is done; 40001 is looked up again in case its socket shows up
+        assert!(attributor.attribute(&batch).is_empty());
+        assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        assert!(attributor.attribute(&[flow(40000)]).is_empty());
+        assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), 2, "nothing left to look up");
+    }
+
+    #[test]
+    fn the_local_side_is_whichever_end_is_not_remote() {
+        assert_eq!(local_side(&flow(40000)), Some("192.168.1.20:40000".parse().unwrap()));
+        let inbound = FlowTraffic {
+            src_ip: "1.1.1.1".to_string(),
+            src_port: 443,
+            dst_ip: "192.168.1.20".to_string(),
+            dst_port: 40000,
+            ..flow(0)
+        };
+        assert_eq!(local_side(&inbound), Some("192.168.1.20:40000".parse().unwrap()));
+    }
+
+    #[test]
+    fn a_flow_that_idles_out_and_comes_back_is_attributed_again() {
+        let mut attributor = Attributor::new(Box::new(FakeResolver {
+            sockets: vec!["192.168.1.20:40000".parse().unwrap()],
+            refreshes: Arc::default(),
+        }));
+        let start = Utc::now();
+        let seen = |from: i64, to: i64| FlowTraffic {
+            start_time: start + chrono::Duration::seconds(from),
+            end_time: start + chrono::Duration::seconds(to),
+            ..flow(40000)
+        };
+        let idle = FLOW_IDLE_TIMEOUT.num_seconds();
+
+        assert_eq!(ports(&attributor.attribute(&[seen(0, 10)])), vec![40000]);
+        // Keepalives keep continuing the same recorded flow
+        assert!(attributor.attribute(&[seen(10 + idle, 10 + idle)]).is_empty());
+        assert!(attributor.attribute(&[seen(10 + 2 * idle, 20 + 2 * idle)]).is_empty());
+        // Idle for longer than the timeout: the database starts a new row
+        assert_eq!(ports(&attributor.attribute(&[seen(21 + 3 * idle, 30 + 3 * idle)])), vec![40000]);
+        assert!(attributor.attribute(&[seen(30 + 3 * idle, 40 + 3 * idle)]).is_empty());
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::db::Database;
+use crate::dns;
+
+/// Shortest time a hostname is cached, whatever TTL the PTR record had;
+/// reverse zones rarely change and many publish TTLs of a few minutes
+const MIN_CACHE_TTL: chrono::Duration = chrono::Duration::hours(1);
+const DNS_PORT: u16 = 53;
+
+/// Reverse DNS settings
+#[derive(Debug, Clone)]
+pub struct RdnsConfig {
+    /// Whether PTR queries are sent at all; off until asked for
+    pub enabled: bool,
+    /// Resolvers to ask, in order
+    pub servers: Vec<SocketAddr>,
+    /// How long to wait for each resolver
+    pub timeout: Duration,
+    /// Queries allowed in flight at once
+    pub concurrency: usize,
+    /// Longest time a hostname is cached
+    pub max_ttl: chrono::Duration,
+    /// How long "no PTR record" is cached
+    pub negative_ttl: chrono::Duration,
+}
+
+impl Default for RdnsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            servers: Vec::new(),
+            timeout: Duration::from_secs(2),
+            concurrency: 8,
+            max_ttl: chrono::Duration::days(7),
+            negative_ttl: chrono::Duration::hours(6),
+        }
+    }
+}
+
+impl RdnsConfig {
+    /// Defaults overridden by `RDNS_ENABLED`, `RDNS_SERVERS` (comma-separated,
+    /// port optional), `RDNS_MAX_TTL_SECS` and `RDNS_NEGATIVE_TTL_SECS`.
+    /// Without `RDNS_SERVERS`, the system resolvers are used.
+    pub fn from_env() -> Self {
+        let env_num = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<i64>().ok());
+        let defaults = Self::default();
+
+        let servers = match std::env::var("RDNS_SERVERS") {
+            Ok(list) => list
+                .split(',')
+                .filter_map(|s| {
+                    let parsed = parse_server(s.trim());
+                    if parsed.is_none() && !s.trim().is_empty() {
+                        log::warn!("[SNIFFF:DNS] Ignoring invalid resolver '{}'", s.trim());
+                    }
+                    parsed
+                })
+                .collect(),
+            Err(_) => system_nameservers(),
+        };
+
+        Self {
+            enabled: std::env::var("RDNS_ENABLED")
+                .map(|v| !matches!(v.trim(), "0" | "false" | "off"))
+                .unwrap_or(defaults.enabled),
+            servers,
+            max_ttl: env_num("RDNS_MAX_TTL_SECS")
+                .map(chrono::Duration::seconds)
+                .unwrap_or(defaults.max_ttl),
+            negative_ttl: env_num("RDNS_NEGATIVE_TTL_SECS")
+                .map(chrono::Duration::seconds)
+                .unwrap_or(defaults.negative_ttl),
+            ..defaults
+        }
+    }
+}
+
+fn parse_server(s: &str) -> Option<SocketAddr> {
+    s.parse::<SocketAddr>()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, DNS_PORT)))
+}
+
+/// Nameservers listed in /etc/resolv.conf
+#[cfg(unix)]
+fn system_nameservers() -> Vec<SocketAddr> {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .map(|conf| {
+            conf.lines()
+                .filter_map(|line| line.trim().strip_prefix("nameserver"))
+                // Drop IPv6 zone ids like fe80::1%en0
+                .filter_map(|addr| addr.split('%').next()?.trim().parse::<IpAddr>().ok())
+                .map(|ip| SocketAddr::new(ip, DNS_PORT))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+fn system_nameservers() -> Vec<SocketAddr> {
+    Vec::new()
+}
+
+/// A cached PTR lookup; `hostname` is `None` for addresses without one
+#[derive(Debug, Clone)]
+pub struct RdnsCacheEntry {
+    pub hostname: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A PTR answer and the TTL it came with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PtrAnswer {
+    pub hostname: String,
+    pub ttl: u32,
+}
+
+/// Minimal stub resolver for PTR queries over UDP
+pub struct PtrResolver {
+    servers: Vec<SocketAddr>,
+    timeout: Duration,
+    next_id: AtomicU16,
+}
+
+impl PtrResolver {
+    pub fn new(servers: Vec<SocketAddr>, timeout: Duration) -> Self {
+        // Start query ids somewhere unpredictable-ish; they only need to
+        // tell our own outstanding queries apart
+        let seed = Utc::now().timestamp_subsec_nanos() as u16;
+        Self {
+            servers,
+            timeout,
+            next_id: AtomicU16::new(seed),
+        }
+    }
+
+    /// Look up the hostname for `ip`. `Ok(None)` means the resolver answered
+    /// that there is none; `Err` means no resolver gave a usable answer.
+    pub async fn lookup(&self, ip: &IpAddr) -> Result<Option<PtrAnswer>, String> {
+        if self.servers.is_empty() {
+            return Err("No DNS resolvers configured".to_string());
+        }
+
+        let name = dns::reverse_name(ip);
+        let mut last_error = String::new();
+        for server in &self.servers {
+            match self.query(*server, &name).await {
+                Ok(answer) => return Ok(answer),
+                Err(e) => {
+                    log::debug!("[SNIFFF:DNS] PTR {} via {} failed: {}", name, server, e);
+                    last_error = format!("{}: {}", server, e);
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn query(&self, server: SocketAddr, name: &str) -> Result<Option<PtrAnswer>, String> {
+        let bind: SocketAddr = if server.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(bind).await.map_err(|e| format!("bind failed: {}", e))?;
+        socket.connect(server).await.map_err(|e| format!("connect failed: {}", e))?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        socket.send(&dns::build_query(id, name, dns::TYPE_PTR)).await
+            .map_err(|e| format!("send failed: {}", e))?;
+
+        let mut buf = [0u8; 1500];
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            let len = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await
+                .map_err(|_| "timed out".to_string())?
+                .map_err(|e| format!("receive failed: {}", e))?;
+
+            // Ignore anything that isn't the answer to this query
+            let message = match dns::parse(&buf[..len]) {
+                Ok(m) if m.is_response && m.id == id
+                    && m.questions.first().is_some_and(|q| q.name.eq_ignore_ascii_case(name)) => m,
+                _ => continue,
+            };
+
+            return match message.rcode {
+                dns::RCODE_NOERROR | dns::RCODE_NXDOMAIN => Ok(message.answers.into_iter().find_map(|r| match r.data {
+                    dns::RecordData::Ptr(hostname) if !hostname.is_empty() => Some(PtrAnswer { hostname, ttl: r.ttl }),
+                    _ => None,
+                })),
+                rcode => Err(format!("server returned rcode {}", rcode)),
+            };
+        }
+    }
+}
+
+/// Background PTR lookups for newly seen IPs. Results are cached in the DB
+/// and written to `connections.hostname`. Can be switched off at runtime, in
+/// which case nothing is sent to any resolver.
+pub struct ReverseDns {
+    tx: mpsc::UnboundedSender<String>,
+    in_flight: Arc<Mutex<HashSet<String>>>,
+    enabled: Arc<AtomicBool>,
+}
+
+impl ReverseDns {
+    pub fn start(rt: &tokio::runtime::Runtime, db: Arc<Database>, config: RdnsConfig) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let in_flight = Arc::new(Mutex::new(HashSet::new()));
+        let enabled = Arc::new(AtomicBool::new(config.enabled));
+
+        if config.servers.is_empty() {
+            log::warn!("[SNIFFF:DNS] No resolvers found; set RDNS_SERVERS to enable reverse DNS");
+        } else {
+            log::info!("[SNIFFF:DNS] Reverse DNS via {:?} ({})", config.servers,
+                if config.enabled { "enabled" } else { "disabled" });
+        }
+
+        let worker = Worker {
+            resolver: Arc::new(PtrResolver::new(config.servers.clone(), config.timeout)),
+            db,
+            permits: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            in_flight: in_flight.clone(),
+            enabled: enabled.clone(),
+            config,
+        };
+        rt.spawn(worker.run(rx));
+
+        Self { tx, in_flight, enabled }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Queue an IP for a PTR lookup unless lookups are off or it is already queued
+    pub fn enqueue(&self, ip: String) {
+        if !self.is_enabled() {
+            return;
+        }
+        let is_new = match self.in_flight.lock() {
+            Ok(mut in_flight) => in_flight.insert(ip.clone()),
+            Err(_) => return,
+        };
+        if is_new && self.tx.send(ip).is_err() {
+            log::error!("[SNIFFF:DNS] Lookup queue is closed");
+        }
+    }
+
+    /// Queue every recorded IP without a hostname or a fresh cached answer
+    pub fn enqueue_missing(&self, db: &Database) {
+        if !self.is_enabled() {
+            return;
+        }
+        match db.get_ips_missing_hostname() {
+            Ok(ips) => {
+                if !ips.is_empty() {
+                    log::info!("[SNIFFF:DNS] Resolving {} hostnames", ips.len());
+                }
+                for ip in ips {
+                    self.enqueue(ip);
+                }
+            }
+            Err(e) => log::error!("[SNIFFF:DNS] Failed to scan for pending lookups: {}", e),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Worker {
+    resolver: Arc<PtrResolver>,
+    db: Arc<Database>,
+    permits: Arc<Semaphore>,
+    in_flight: Arc<Mutex<HashSet<String>>>,
+    enabled: Arc<AtomicBool>,
+    config: RdnsConfig,
+}
+
+impl Worker {
+    async fn run(self, mut rx: mpsc::UnboundedReceiver<String>) {
+        while let Some(ip) = rx.recv().await {
+            let permit = match self.permits.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => break,
+            };
+            let worker = self.clone();
+            tokio::spawn(async move {
+                worker.process(&ip).await;
+                if let Ok(mut in_flight) = worker.in_flight.lock() {
+                    in_flight.remove(&ip);
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    async fn process(&self, ip: &str) {
+        // Switched off while this was queued
+        if !self.enabled.load(Ordering::SeqCst) {
+            return;
+        }
+
+        match self.db.get_rdns_cache(ip) {
+            Ok(Some(entry)) if entry.expires_at > Utc::now() => {
+                if let Err(e) = self.db.set_hostname(ip, entry.hostname.as_deref()) {
+                    log::error!("[SNIFFF:DNS] Failed to record hostname for {}: {}", ip, e);
+                }
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("[SNIFFF:DNS] Failed to read cache for {}: {}", ip, e),
+        }
+
+        let addr: IpAddr = match ip.parse() {
+            Ok(addr) => addr,
+            Err(_) => return,
+        };
+        let entry = match self.resolver.lookup(&addr).await {
+            Ok(Some(answer)) => {
+                log::debug!("[SNIFFF:DNS] {} is {}", ip, answer.hostname);
+                let ttl = chrono::Duration::seconds(answer.ttl as i64)
+                    .clamp(MIN_CACHE_TTL, self.config.max_ttl.max(MIN_CACHE_TTL));
+                RdnsCacheEntry {
+                    hostname: Some(answer.hostname),
+                    expires_at: Utc::now() + ttl,
+                }
+            }
+            Ok(None) => RdnsCacheEntry {
+                hostname: None,
+                expires_at: Utc::now() + self.config.negative_ttl,
+            },
+            // Not cached, so the next sighting tries again
+            Err(e) => {
+                log::debug!("[SNIFFF:DNS] No answer for {}: {}", ip, e);
+                return;
+            }
+        };
+
+        if let Err(e) = self.db.put_rdns_cache(ip, &entry) {
+            log::error!("[SNIFFF:DNS] Failed to store hostname for {}: {}", ip, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answer PTR queries from a fixed table; unknown names get NXDOMAIN
+    async fn stub_resolver(table: &'static [(&'static str, &'static str)]) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let query = dns::parse(&buf[..len]).unwrap();
+                let name = &query.questions[0].name;
+                let hostname = table.iter().find(|(q, _)| q == name).map(|(_, h)| *h);
+                let _ = socket.send_to(&respond(&buf[..len], hostname), peer).await;
+            }
+        });
+
+        addr
+    }
+
+    fn respond(query: &[u8], hostname: Option<&str>) -> Vec<u8> {
+        let mut out = query.to_vec();
+        // QR, RD, RA, plus NXDOMAIN when there is nothing to say
+        let flags: u16 = if hostname.is_some() { 0x8180 } else { 0x8183 };
+        out[2..4].copy_from_slice(&flags.to_be_bytes());
+        out[6..8].copy_from_slice(&(hostname.is_some() as u16).to_be_bytes());
+
+        if let Some(hostname) = hostname {
+            let mut rdata = Vec::new();
+            for label in hostname.split('.') {
+                rdata.push(label.len() as u8);
+                rdata.extend_from_slice(label.as_bytes());
+            }
+            rdata.push(0);
+
+            out.extend_from_slice(&[0xc0, 0x0c]); // the question's name
+            out.extend_from_slice(&dns::TYPE_PTR.to_be_bytes());
+            out.extend_from_slice(&1u16.to_be_bytes());
+            out.extend_from_slice(&600u32.to_be_bytes());
+            out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            out.extend_from_slice(&rdata);
+        }
+        out
+    }
+
+    const TABLE: &[(&str, &str)] = &[
+        ("1.2.0.192.in-addr.arpa", "host.example.com"),
+        (
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa",
+            "v6.example.com",
+        ),
+    ];
+
+    #[tokio::test]
+    async fn resolves_ipv4_ptr() {
+        let resolver = PtrResolver::new(vec![stub_resolver(TABLE).await], Duration::from_secs(1));
+        let answer = resolver.lookup(&"192.0.2.1".parse().unwrap()).await.unwrap();
+        assert_eq!(
+            answer,
+            Some(PtrAnswer { hostname: "host.example.com".to_string(), ttl: 600 })
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_ipv6_ptr() {
+        let resolver = PtrResolver::new(vec![stub_resolver(TABLE).await], Duration::from_secs(1));
+        let answer = resolver.lookup(&"2001:db8::1".parse().unwrap()).await.unwrap();
+        assert_eq!(answer.map(|a| a.hostname), Some("v6.example.com".to_string()));
+    }
+
+    #[tokio::test]
+    async fn nxdomain_is_no_hostname() {
+        let resolver = PtrResolver::new(vec![stub_resolver(TABLE).await], Duration::from_secs(1));
+        assert_eq!(resolver.lookup(&"192.0.2.99".parse().unwrap()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_next_server() {
+        // Bound but never answers
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let servers = vec![silent.local_addr().unwrap(), stub_resolver(TABLE).await];
+        let resolver = PtrResolver::new(servers, Duration::from_millis(200));
+
+        let answer = resolver.lookup(&"192.0.2.1".parse().unwrap()).await.unwrap();
+        assert_eq!(answer.map(|a| a.hostname), Some("host.example.com".to_string()));
+    }
+
+    #[tokio::test]
+    async fn unreachable_resolvers_are_an_error() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = PtrResolver::new(vec![silent.local_addr().unwrap()], Duration::from_millis(100));
+        assert!(resolver.lookup(&"192.0.2.1".parse().unwrap()).await.is_err());
+    }
+}
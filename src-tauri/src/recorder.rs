@@ -0,0 +1,411 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// Snaplen used when only headers are wanted
+pub const HEADER_SNAPLEN: i32 = 128;
+/// Snaplen used when recording full payloads
+pub const FULL_SNAPLEN: i32 = 65535;
+
+const FILE_PREFIX: &str = "snifff-";
+const FILE_EXTENSION: &str = "pcapng";
+/// Files opened within the same millisecond get a `_<n>` suffix; give up
+/// after this many
+const MAX_NAME_ATTEMPTS: usize = 100;
+
+/// pcapng block types and options used by the recorder
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const OPT_ENDOFOPT: u16 = 0;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+
+/// How to record captured packets to disk
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecordingConfig {
+    pub directory: PathBuf,
+    /// Keep whole packets instead of just the first `HEADER_SNAPLEN` bytes
+    #[serde(default)]
+    pub full_payload: bool,
+    /// Start a new file once the current one reaches this size
+    #[serde(default = "default_max_file_bytes")]
+    pub max_file_bytes: u64,
+    /// Start a new file once the current one has been open this long
+    #[serde(default = "default_max_file_secs")]
+    pub max_file_secs: u64,
+    /// Delete the oldest recordings beyond this many files
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+fn default_max_file_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_max_file_secs() -> u64 {
+    3600
+}
+
+fn default_max_files() -> usize {
+    24
+}
+
+impl RecordingConfig {
+    pub fn snaplen(&self) -> i32 {
+        if self.full_payload {
+            FULL_SNAPLEN
+        } else {
+            HEADER_SNAPLEN
+        }
+    }
+}
+
+struct OpenFile {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    bytes: u64,
+    opened_at: Instant,
+}
+
+/// Writes captured packets to size- and time-rotated pcapng files, keeping at
+/// most `max_files` of them
+pub struct Recorder {
+    config: RecordingConfig,
+    linktype: pcap::Linktype,
+    interface: String,
+    /// `snifff-<interface>-`, so captures on several interfaces can share a
+    /// directory without rotating each other's files away
+    prefix: String,
+    current: Option<OpenFile>,
+    /// Finished and current recordings, oldest first
+    files: VecDeque<PathBuf>,
+}
+
+impl Recorder {
+    pub fn new(config: RecordingConfig, linktype: pcap::Linktype, interface: &str) -> Result<Self, String> {
+        fs::create_dir_all(&config.directory)
+            .map_err(|e| format!("Failed to create recording directory: {}", e))?;
+
+        // Interface names on Windows are device paths
+        let sanitized: String = interface
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_') { c } else { '_' })
+            .collect();
+        let prefix = format!("{}{}-", FILE_PREFIX, sanitized);
+
+        // Earlier recordings count against the retention cap too
+        let mut existing: Vec<PathBuf> = fs::read_dir(&config.directory)
+            .map_err(|e| format!("Failed to read recording directory: {}", e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| is_recording(path, &prefix))
+            .collect();
+        existing.sort();
+
+        let mut recorder = Self {
+            config,
+            linktype,
+            interface: interface.to_string(),
+            prefix,
+            current: None,
+            files: existing.into(),
+        };
+        recorder.open()?;
+        Ok(recorder)
+    }
+
+    /// Append a packet, rotating first if the current file is full or too old
+    pub fn write(&mut self, packet: &pcap::Packet) {
+        let ts = packet.header.ts;
+        let micros = (ts.tv_sec as u64) * 1_000_000 + ts.tv_usec as u64;
+        self.append(micros, packet.header.len, packet.data);
+    }
+
+    /// Called while no packets arrive: close the current file once it is too
+    /// old, so an idle capture still rotates, and otherwise flush it so it can
+    /// be read while capturing. The next packet opens a fresh file.
+    pub fn tick(&mut self) {
+        if self.current.as_ref().is_some_and(|file| self.is_expired(file)) {
+            self.close();
+        } else if let Some(file) = self.current.as_mut() {
+            if let Err(e) = file.writer.flush() {
+                log::error!("Failed to flush recording {}: {}", file.path.display(), e);
+            }
+        }
+    }
+
+    fn append(&mut self, timestamp_micros: u64, original_len: u32, data: &[u8]) {
+        if self.current.as_ref().is_some_and(|file| self.is_full(file) || self.is_expired(file)) {
+            self.close();
+        }
+        if self.current.is_none() {
+            if let Err(e) = self.open() {
+                log::error!("Recording rotation failed: {}", e);
+                return;
+            }
+        }
+
+        let block = enhanced_packet_block(timestamp_micros, original_len, data);
+        let Some(file) = self.current.as_mut() else { return };
+        match file.writer.write_all(&block) {
+            Ok(()) => file.bytes += block.len() as u64,
+            Err(e) => {
+                log::error!("Failed to write recording {}: {}", file.path.display(), e);
+                self.close();
+            }
+        }
+    }
+
+    fn is_full(&self, file: &OpenFile) -> bool {
+        file.bytes >= self.config.max_file_bytes
+    }
+
+    fn is_expired(&self, file: &OpenFile) -> bool {
+        file.opened_at.elapsed() >= Duration::from_secs(self.config.max_file_secs)
+    }
+
+    fn close(&mut self) {
+        if let Some(mut file) = self.current.take() {
+            if let Err(e) = file.writer.flush() {
+                log::error!("Failed to flush recording {}: {}", file.path.display(), e);
+            }
+            log::info!("Closed recording {} ({} bytes)", file.path.display(), file.bytes);
+        }
+    }
+
+    /// Open a fresh file after the current one, pruning old recordings
+    fn open(&mut self) -> Result<(), String> {
+        let (file, path) = self.create_file()?;
+
+        let mut header = section_header_block();
+        header.extend(interface_description_block(
+            self.linktype,
+            self.config.snaplen() as u32,
+            &self.interface,
+        ));
+        let mut writer = BufWriter::new(file);
+        writer
+            .write_all(&header)
+            .map_err(|e| format!("Failed to write recording {}: {}", path.display(), e))?;
+
+        log::info!("Recording packets to {}", path.display());
+        self.files.push_back(path.clone());
+        self.current = Some(OpenFile {
+            writer,
+            path,
+            bytes: header.len() as u64,
+            opened_at: Instant::now(),
+        });
+
+        while self.files.len() > self.config.max_files.max(1) {
+            if let Some(oldest) = self.files.pop_front() {
+                match fs::remove_file(&oldest) {
+                    Ok(()) => log::info!("Deleted old recording {}", oldest.display()),
+                    Err(e) => log::error!("Failed to delete recording {}: {}", oldest.display(), e),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create a file named after the current time that doesn't exist yet.
+    /// The `_<n>` suffix sorts after the plain name, so names stay in the
+    /// order the files were opened.
+    fn create_file(&self) -> Result<(File, PathBuf), String> {
+        let stem = format!("{}{}", self.prefix, chrono::Local::now().format("%Y%m%d-%H%M%S%.3f"));
+        for attempt in 0..MAX_NAME_ATTEMPTS {
+            let name = if attempt == 0 {
+                format!("{}.{}", stem, FILE_EXTENSION)
+            } else {
+                format!("{}_{}.{}", stem, attempt, FILE_EXTENSION)
+            };
+            let path = self.config.directory.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((file, path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(format!("Failed to create recording {}: {}", path.display(), e)),
+            }
+        }
+        Err(format!("Failed to create recording {}: too many files with that name", stem))
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Recordings from before the switch to pcapng count against the cap as well
+fn is_recording(path: &std::path::Path, prefix: &str) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    name.starts_with(prefix)
+        && path.extension().is_some_and(|ext| ext == FILE_EXTENSION || ext == "pcap")
+}
+
+/// A pcapng block: type, total length, `body` padded to 32 bits, total length again
+fn block(block_type: u32, mut body: Vec<u8>) -> Vec<u8> {
+    body.resize(body.len().next_multiple_of(4), 0);
+    let total_len = (body.len() + 12) as u32;
+    let mut block = Vec::with_capacity(total_len as usize);
+    block.extend(block_type.to_ne_bytes());
+    block.extend(total_len.to_ne_bytes());
+    block.extend(body);
+    block.extend(total_len.to_ne_bytes());
+    block
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend(code.to_ne_bytes());
+    body.extend((value.len() as u16).to_ne_bytes());
+    body.extend(value);
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+fn section_header_block() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend(BYTE_ORDER_MAGIC.to_ne_bytes());
+    body.extend(1u16.to_ne_bytes());
+    body.extend(0u16.to_ne_bytes());
+    // Section length not known up front
+    body.extend((-1i64).to_ne_bytes());
+    push_option(&mut body, SHB_USERAPPL, concat!("snifff ", env!("CARGO_PKG_VERSION")).as_bytes());
+    push_option(&mut body, OPT_ENDOFOPT, &[]);
+    block(SECTION_HEADER_BLOCK, body)
+}
+
+fn interface_description_block(linktype: pcap::Linktype, snaplen: u32, interface: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend((linktype.0 as u16).to_ne_bytes());
+    body.extend(0u16.to_ne_bytes());
+    body.extend(snaplen.to_ne_bytes());
+    push_option(&mut body, IF_NAME, interface.as_bytes());
+    push_option(&mut body, OPT_ENDOFOPT, &[]);
+    block(INTERFACE_DESCRIPTION_BLOCK, body)
+}
+
+/// A packet on the file's only interface, timestamped in microseconds
+fn enhanced_packet_block(timestamp_micros: u64, original_len: u32, data: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(20 + data.len());
+    body.extend(0u32.to_ne_bytes());
+    body.extend(((timestamp_micros >> 32) as u32).to_ne_bytes());
+    body.extend((timestamp_micros as u32).to_ne_bytes());
+    body.extend((data.len() as u32).to_ne_bytes());
+    body.extend(original_len.to_ne_bytes());
+    body.extend(data);
+    block(ENHANCED_PACKET_BLOCK, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn config(dir: &TempDir) -> RecordingConfig {
+        RecordingConfig {
+            directory: dir.path().to_path_buf(),
+            full_payload: false,
+            max_file_bytes: default_max_file_bytes(),
+            max_file_secs: default_max_file_secs(),
+            max_files: default_max_files(),
+        }
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// (type, body) of each block in a pcapng file
+    fn blocks(data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let len = u32_at(rest, 4) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(u32_at(rest, len - 4) as usize, len, "trailing length");
+            blocks.push((u32_at(rest, 0), &rest[8..len - 4]));
+            rest = &rest[len..];
+        }
+        blocks
+    }
+
+    fn recordings(dir: &TempDir) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().path()).collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn writes_a_pcapng_section_interface_and_packets() {
+        let dir = TempDir::new("blocks");
+        let mut recorder = Recorder::new(config(&dir), pcap::Linktype::ETHERNET, "eth0").unwrap();
+        recorder.append(0x1_0000_0002, 1500, &[0xAB; 5]);
+        drop(recorder);
+
+        let files = recordings(&dir);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "pcapng");
+        let data = fs::read(&files[0]).unwrap();
+        let blocks = blocks(&data);
+        assert_eq!(blocks.len(), 3);
+
+        let (kind, shb) = blocks[0];
+        assert_eq!(kind, SECTION_HEADER_BLOCK);
+        assert_eq!(u32_at(shb, 0), BYTE_ORDER_MAGIC);
+
+        let (kind, idb) = blocks[1];
+        assert_eq!(kind, INTERFACE_DESCRIPTION_BLOCK);
+        assert_eq!(u16::from_ne_bytes([idb[0], idb[1]]), 1);
+        assert_eq!(u32_at(idb, 4), HEADER_SNAPLEN as u32);
+        assert_eq!(&idb[12..16], b"eth0");
+
+        let (kind, epb) = blocks[2];
+        assert_eq!(kind, ENHANCED_PACKET_BLOCK);
+        assert_eq!((u32_at(epb, 4), u32_at(epb, 8)), (1, 2), "timestamp high, low");
+        assert_eq!((u32_at(epb, 12), u32_at(epb, 16)), (5, 1500), "captured, original length");
+        assert_eq!(&epb[20..], &[0xAB, 0xAB, 0xAB, 0xAB, 0xAB, 0, 0, 0]);
+    }
+
+    #[test]
+    fn idle_files_close_on_time_and_old_ones_are_pruned() {
+        let dir = TempDir::new("rotation");
+        let config = RecordingConfig { max_file_secs: 0, max_files: 2, ..config(&dir) };
+        let mut recorder = Recorder::new(config, pcap::Linktype::ETHERNET, "eth0").unwrap();
+
+        recorder.tick();
+        assert!(recorder.current.is_none(), "an expired file closes without waiting for a packet");
+        assert_eq!(recordings(&dir).len(), 1);
+
+        for _ in 0..3 {
+            recorder.append(0, 60, &[0; 60]);
+        }
+        drop(recorder);
+        let files = recordings(&dir);
+        assert_eq!(files.len(), 2);
+        for file in files {
+            assert_eq!(blocks(&fs::read(file).unwrap()).len(), 3, "each file holds one packet");
+        }
+    }
+
+    #[test]
+    fn full_files_rotate() {
+        let dir = TempDir::new("size");
+        let config = RecordingConfig { max_file_bytes: 1, ..config(&dir) };
+        let mut recorder = Recorder::new(config, pcap::Linktype::ETHERNET, "eth0").unwrap();
+        recorder.tick();
+        assert!(recorder.current.is_some(), "only age closes a file between packets");
+
+        for _ in 0..2 {
+            recorder.append(0, 60, &[0; 60]);
+        }
+        drop(recorder);
+        let sizes: Vec<usize> = recordings(&dir).iter().map(|f| blocks(&fs::read(f).unwrap()).len()).collect();
+        assert_eq!(sizes, vec![2, 3, 3], "files sort in the order they were opened");
+    }
+}
@@ -0,0 +1,211 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Database, PurgeResult};
+
+/// Rounds of deleting the oldest connections while the file is over its size cap
+const MAX_SIZE_PASSES: usize = 20;
+
+/// How much history to keep. Unset limits are not enforced, and none are set
+/// by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Drop connections, flows and history older than this
+    pub max_age_days: Option<u32>,
+    /// Keep at most this many connections, dropping the least recently seen
+    pub max_rows: Option<u64>,
+    /// Drop the least recently seen connections until the database fits
+    pub max_db_mb: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Limits from `RETENTION_MAX_AGE_DAYS`, `RETENTION_MAX_ROWS` and
+    /// `RETENTION_MAX_DB_MB`; unset or 0 leaves that limit off
+    pub fn from_env() -> Self {
+        let env_num = |name: &str| {
+            std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok()).filter(|n| *n > 0)
+        };
+
+        Self {
+            max_age_days: env_num("RETENTION_MAX_AGE_DAYS").map(|n| n as u32),
+            max_rows: env_num("RETENTION_MAX_ROWS"),
+            max_db_mb: env_num("RETENTION_MAX_DB_MB"),
+        }
+    }
+}
+
+/// Apply `policy` to `db`, vacuuming afterwards if anything was deleted
+pub fn enforce(db: &Database, policy: &RetentionPolicy) -> Result<PurgeResult, String> {
+    let mut result = PurgeResult::default();
+
+    if let Some(days) = policy.max_age_days {
+        result.add(db.prune_before(Utc::now() - chrono::Duration::days(days as i64))?);
+    }
+
+    if let Some(max_rows) = policy.max_rows {
+        result.add(db.prune_to_rows(max_rows)?);
+    }
+
+    if let Some(max_mb) = policy.max_db_mb {
+        let max_bytes = max_mb * 1024 * 1024;
+        for _ in 0..MAX_SIZE_PASSES {
+            if db.used_bytes()? <= max_bytes {
+                break;
+            }
+            let rows = db.connection_count()?;
+            if rows == 0 {
+                break;
+            }
+            // Trim a tenth at a time so one pass never overshoots by much
+            result.add(db.prune_oldest((rows / 10).max(1))?);
+        }
+    }
+
+    if !result.is_empty() {
+        result.bytes_reclaimed = db.vacuum()?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{PurgeFilter, TempDb};
+    use crate::sniffer::{Direction, FlowTraffic, IpTraffic};
+
+    /// `ip` last seen `minutes_ago`, with one packet each way
+    fn seen(db: &Database, ip: &str, minutes_ago: i64) {
+        let at = Utc::now() - chrono::Duration::minutes(minutes_ago);
+        let traffic = IpTraffic {
+            ip: ip.to_string(),
+            direction: Direction::Outbound,
+            packets_in: 1,
+            packets_out: 1,
+            bytes_in: 100,
+            bytes_out: 100,
+            first_seen: at,
+            last_seen: at,
+        };
+        db.record_traffic(&[traffic], None).unwrap();
+    }
+
+    fn remaining(db: &Database) -> Vec<String> {
+        let mut ips: Vec<String> = db.get_connections(usize::MAX).unwrap().into_iter().map(|c| c.ip).collect();
+        ips.sort();
+        ips
+    }
+
+    #[test]
+    fn the_default_policy_deletes_nothing() {
+        let t = TempDb::new("retention-default");
+        seen(&t.db, "1.1.1.1", 1000 * 24 * 60);
+        let result = enforce(&t.db, &RetentionPolicy::default()).unwrap();
+        assert!(result.is_empty());
+        assert_eq!(t.db.connection_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn an_age_limit_deletes_older_connections() {
+        let t = TempDb::new("retention-age");
+        seen(&t.db, "1.1.1.1", 1000 * 24 * 60);
+        seen(&t.db, "8.8.8.8", 60);
+        let policy = RetentionPolicy { max_age_days: Some(90), ..Default::default() };
+        let result = enforce(&t.db, &policy).unwrap();
+        assert_eq!((result.connections, result.buckets), (1, 1));
+        assert_eq!(remaining(&t.db), vec!["8.8.8.8"]);
+    }
+
+    #[test]
+    fn a_row_limit_keeps_the_most_recently_seen() {
+        let t = TempDb::new("retention-rows");
+        for (i, ip) in ["1.1.1.1", "2.2.2.2", "3.3.3.3", "4.4.4.4", "5.5.5.5"].iter().enumerate() {
+            seen(&t.db, ip, 10 - i as i64);
+        }
+        let policy = RetentionPolicy { max_rows: Some(2), ..Default::default() };
+        assert_eq!(enforce(&t.db, &policy).unwrap().connections, 3);
+        assert_eq!(remaining(&t.db), vec!["4.4.4.4", "5.5.5.5"]);
+        assert!(enforce(&t.db, &policy).unwrap().is_empty(), "already within the limit");
+    }
+
+    #[test]
+    fn a_size_limit_drops_the_oldest_until_the_file_fits() {
+        let t = TempDb::new("retention-size");
+        let start = Utc::now() - chrono::Duration::days(1);
+        let batch: Vec<IpTraffic> = (0..20_000)
+            .map(|i| IpTraffic {
+                ip: format!("10.{}.{}.{}", i / 65536, i / 256 % 256, i % 256),
+                direction: Direction::Outbound,
+                packets_in: 1,
+                packets_out: 1,
+                bytes_in: 100,
+                bytes_out: 100,
+                first_seen: start + chrono::Duration::seconds(i),
+                last_seen: start + chrono::Duration::seconds(i),
+            })
+            .collect();
+        t.db.record_traffic(&batch, None).unwrap();
+        let max_bytes = 1024 * 1024;
+        assert!(t.db.used_bytes().unwrap() > 2 * max_bytes, "the test needs a database well over the limit");
+
+        let result = enforce(&t.db, &RetentionPolicy { max_db_mb: Some(1), ..Default::default() }).unwrap();
+        assert!(t.db.used_bytes().unwrap() <= max_bytes);
+        assert!(result.bytes_reclaimed > 0);
+        let kept = t.db.connection_count().unwrap();
+        assert_eq!(result.connections + kept, 20_000);
+        assert!(kept > 0);
+        // Least recently seen first: whatever is left is the newest
+        let oldest_kept = t.db.query_connections(&crate::db::ConnectionQuery {
+            sort: crate::db::ConnectionSort::LastSeen,
+            order: crate::db::SortOrder::Asc,
+            limit: Some(1),
+            ..Default::default()
+        }).unwrap().connections.remove(0);
+        assert_eq!(oldest_kept.first_seen, batch[20_000 - kept as usize].first_seen.to_rfc3339());
+    }
+
+    #[test]
+    fn purges_by_ip_asn_and_country_report_what_they_deleted() {
+        let t = TempDb::new("retention-purge");
+        for (ip, asn, country) in [
+            ("1.1.1.1", "AS13335", "AU"),
+            ("1.1.1.2", "AS13335", "AU"),
+            ("1.0.0.1", "AS13335", "US"),
+            ("8.8.8.8", "AS15169", "US"),
+            ("9.9.9.9", "AS19281", "CH"),
+        ] {
+            seen(&t.db, ip, 5);
+            t.db.update_geo_info(ip, Some(asn), None, None, Some(country), None, None, None).unwrap();
+        }
+        let now = Utc::now();
+        let flow = FlowTraffic {
+            src_ip: "192.168.1.20".to_string(),
+            src_port: 40000,
+            dst_ip: "8.8.8.8".to_string(),
+            dst_port: 53,
+            protocol: "udp".to_string(),
+            remote_ip: "8.8.8.8".to_string(),
+            direction: Direction::Outbound,
+            tcp_flags: 0,
+            packets: 2,
+            bytes: 200,
+            start_time: now,
+            end_time: now,
+            tunnel: None,
+            tunnel_endpoint: None,
+        };
+        t.db.record_flows(&[flow], None).unwrap();
+
+        let purge = |filter: PurgeFilter| {
+            let result = t.db.purge(&filter).unwrap();
+            (result.connections, result.flows, result.buckets)
+        };
+        let text = |s: &str| Some(s.to_string());
+        assert_eq!(purge(PurgeFilter { ip: text("1.1.1.0/24"), ..Default::default() }), (2, 0, 2));
+        assert_eq!(purge(PurgeFilter { asn: text("AS15169"), ..Default::default() }), (1, 1, 1));
+        assert_eq!(purge(PurgeFilter { country_code: text("US"), ..Default::default() }), (1, 0, 1));
+        assert_eq!(purge(PurgeFilter { country_code: text("US"), ..Default::default() }), (0, 0, 0));
+        assert_eq!(remaining(&t.db), vec!["9.9.9.9"]);
+        assert!(t.db.purge(&PurgeFilter::default()).is_err(), "an empty filter would delete everything");
+    }
+}
@@ -0,0 +1,457 @@
+use ring::aead::{self, quic, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::hkdf;
+use serde::{Deserialize, Serialize};
+
+/// Initial salt for QUIC version 1 (RFC 9001 §5.2)
+const QUIC_V1_SALT: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17,
+    0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad, 0xcc, 0xbb, 0x7f, 0x0a,
+];
+const QUIC_V1: u32 = 1;
+
+/// Request lines `http_host` looks at
+pub const HTTP_METHODS: [&[u8]; 8] = [
+    b"GET ", b"POST ", b"HEAD ", b"PUT ", b"DELETE ", b"OPTIONS ", b"PATCH ", b"CONNECT ",
+];
+
+/// Where a server name was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameSource {
+    /// SNI in a TLS ClientHello
+    Tls,
+    /// SNI in the ClientHello carried by a QUIC Initial packet
+    Quic,
+    /// `Host` header of a plaintext HTTP request
+    Http,
+}
+
+impl NameSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NameSource::Tls => "tls",
+            NameSource::Quic => "quic",
+            NameSource::Http => "http",
+        }
+    }
+}
+
+/// The server name a client asked for in this TCP or UDP payload, if it
+/// starts a TLS handshake, an HTTP request or a QUIC connection
+pub fn server_name(is_tcp: bool, payload: &[u8]) -> Option<(String, NameSource)> {
+    if is_tcp {
+        tls_sni(payload)
+            .map(|name| (name, NameSource::Tls))
+            .or_else(|| http_host(payload).map(|name| (name, NameSource::Http)))
+    } else {
+        quic_sni(payload).map(|name| (name, NameSource::Quic))
+    }
+}
+
+/// SNI from a TLS record holding a ClientHello
+pub fn tls_sni(payload: &[u8]) -> Option<String> {
+    // Handshake record, TLS 1.x
+    if payload.len() < 5 || payload[0] != 0x16 || payload[1] != 0x03 {
+        return None;
+    }
+    let len = u16::from_be_bytes([payload[3], payload[4]]) as usize;
+    let handshake = &payload[5..payload.len().min(5 + len)];
+    client_hello_sni(handshake)
+}
+
+/// SNI from a ClientHello handshake message. Works on a truncated message as
+/// long as the server_name extension itself is complete.
+fn client_hello_sni(handshake: &[u8]) -> Option<String> {
+    let mut r = Reader(handshake);
+    if r.u8()? != 0x01 {
+        return None;
+    }
+    r.skip(3)?; // handshake length
+    r.skip(2 + 32)?; // legacy_version, random
+    let session_id = r.u8()? as usize;
+    r.skip(session_id)?;
+    let cipher_suites = r.u16()? as usize;
+    r.skip(cipher_suites)?;
+    let compression = r.u8()? as usize;
+    r.skip(compression)?;
+    r.u16()?; // extensions length
+
+    loop {
+        let ext_type = r.u16()?;
+        let ext_len = r.u16()? as usize;
+        if ext_type != 0x0000 {
+            r.skip(ext_len)?;
+            continue;
+        }
+
+        let mut list = Reader(r.take(ext_len)?);
+        list.u16()?;
+        while let Some(name_type) = list.u8() {
+            let len = list.u16()? as usize;
+            let name = list.take(len)?;
+            if name_type == 0 {
+                return valid_name(name);
+            }
+        }
+        return None;
+    }
+}
+
+/// `Host` header of a plaintext HTTP/1.x request, without any port
+pub fn http_host(payload: &[u8]) -> Option<String> {
+    if !HTTP_METHODS.iter().any(|m| payload.starts_with(m)) {
+        return None;
+    }
+
+    for line in payload.split(|b| *b == b'\n').skip(1) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        let Some(colon) = line.iter().position(|b| *b == b':') else {
+            continue;
+        };
+        if !line[..colon].eq_ignore_ascii_case(b"host") {
+            continue;
+        }
+
+        let value = std::str::from_utf8(&line[colon + 1..]).ok()?.trim();
+        let host = match value.strip_prefix('[') {
+            // [v6]:port
+            Some(rest) => rest.split(']').next()?,
+            None => match value.rsplit_once(':') {
+                Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+                _ => value,
+            },
+        };
+        return valid_name(host.as_bytes());
+    }
+    None
+}
+
+/// SNI from a QUIC v1 client Initial packet. Initial packets are protected
+/// with keys derived from the connection ID, so anyone on the path can read
+/// them; only a ClientHello that starts in this packet is found.
+pub fn quic_sni(payload: &[u8]) -> Option<String> {
+    let mut r = Reader(payload);
+    let first = r.u8()?;
+    // Long header, fixed bit, Initial
+    if first & 0xf0 != 0xc0 {
+        return None;
+    }
+    if r.u32()? != QUIC_V1 {
+        return None;
+    }
+    let dcid_len = r.u8()? as usize;
+    let dcid = r.take(dcid_len)?;
+    let scid_len = r.u8()? as usize;
+    r.skip(scid_len)?;
+    let token_len = r.varint()? as usize;
+    r.skip(token_len)?;
+    let length = r.varint()? as usize;
+    let pn_offset = payload.len() - r.0.len();
+    let packet = payload.get(..pn_offset.checked_add(length)?)?;
+
+    let keys = InitialKeys::client(dcid)?;
+
+    // Remove header protection to learn the packet number
+    let sample = packet.get(pn_offset + 4..pn_offset + 20)?;
+    let mask = keys.hp.new_mask(sample).ok()?;
+    let first = first ^ (mask[0] & 0x0f);
+    let pn_len = (first & 0x03) as usize + 1;
+    let mut header = packet.get(..pn_offset + pn_len)?.to_vec();
+    header[0] = first;
+    let mut pn = 0u64;
+    for i in 0..pn_len {
+        header[pn_offset + i] ^= mask[1 + i];
+        pn = (pn << 8) | header[pn_offset + i] as u64;
+    }
+
+    let mut nonce = keys.iv;
+    for (n, p) in nonce[4..].iter_mut().zip(pn.to_be_bytes()) {
+        *n ^= p;
+    }
+    let mut body = packet[pn_offset + pn_len..].to_vec();
+    let plaintext = keys
+        .key
+        .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(&header), &mut body)
+        .ok()?;
+
+    client_hello_sni(&crypto_stream(plaintext)?)
+}
+
+/// The leading contiguous part of the CRYPTO stream in an Initial payload
+fn crypto_stream(frames: &[u8]) -> Option<Vec<u8>> {
+    let mut r = Reader(frames);
+    let mut chunks: Vec<(usize, &[u8])> = Vec::new();
+
+    while let Some(frame_type) = r.varint() {
+        match frame_type {
+            0x00 | 0x01 => {} // PADDING, PING
+            0x02 | 0x03 => {
+                // ACK: largest, delay, range count, first range, then ranges
+                r.varint()?;
+                r.varint()?;
+                let ranges = r.varint()?;
+                r.varint()?;
+                for _ in 0..ranges {
+                    r.varint()?;
+                    r.varint()?;
+                }
+                if frame_type == 0x03 {
+                    for _ in 0..3 {
+                        r.varint()?;
+                    }
+                }
+            }
+            0x06 => {
+                let offset = r.varint()? as usize;
+                let len = r.varint()? as usize;
+                chunks.push((offset, r.take(len)?));
+            }
+            // Nothing else is allowed in a client Initial that matters here
+            _ => break,
+        }
+    }
+
+    // Clients may send CRYPTO frames out of order
+    chunks.sort_by_key(|(offset, _)| *offset);
+    let mut stream = Vec::new();
+    for (offset, data) in chunks {
+        if offset > stream.len() {
+            break;
+        }
+        let skip = stream.len() - offset;
+        if skip < data.len() {
+            stream.extend_from_slice(&data[skip..]);
+        }
+    }
+
+    (!stream.is_empty()).then_some(stream)
+}
+
+struct InitialKeys {
+    key: LessSafeKey,
+    iv: [u8; 12],
+    hp: quic::HeaderProtectionKey,
+}
+
+impl InitialKeys {
+    /// Client Initial keys for a connection ID (RFC 9001 §5.2)
+    fn client(dcid: &[u8]) -> Option<Self> {
+        let initial = hkdf::Salt::new(hkdf::HKDF_SHA256, &QUIC_V1_SALT).extract(dcid);
+        let secret = expand_label(&initial, b"client in", 32)?;
+        let secret = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, &secret);
+
+        let key = expand_label(&secret, b"quic key", 16)?;
+        let iv = expand_label(&secret, b"quic iv", 12)?;
+        let hp = expand_label(&secret, b"quic hp", 16)?;
+
+        Some(Self {
+            key: LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, &key).ok()?),
+            iv: iv.try_into().ok()?,
+            hp: quic::HeaderProtectionKey::new(&quic::AES_128, &hp).ok()?,
+        })
+    }
+}
+
+/// Output length for `hkdf::Prk::expand`
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// TLS 1.3 HKDF-Expand-Label with an empty context
+fn expand_label(prk: &hkdf::Prk, label: &[u8], len: usize) -> Option<Vec<u8>> {
+    let out_len = (len as u16).to_be_bytes();
+    let label_len = [(6 + label.len()) as u8];
+    let info: [&[u8]; 5] = [&out_len, &label_len, b"tls13 ", label, &[0]];
+
+    let mut out = vec![0; len];
+    prk.expand(&info, Len(len)).ok()?.fill(&mut out).ok()?;
+    Some(out)
+}
+
+/// Lowercased host name, if `name` looks like one
+fn valid_name(name: &[u8]) -> Option<String> {
+    let ok = !name.is_empty()
+        && name.len() <= 253
+        && name.iter().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b':'));
+    ok.then(|| String::from_utf8_lossy(name).to_ascii_lowercase())
+}
+
+/// Bounds-checked big-endian reads over a byte slice
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.0.len() {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// QUIC variable-length integer
+    fn varint(&mut self) -> Option<u64> {
+        let first = self.u8()?;
+        let extra = (1usize << (first >> 6)) - 1;
+        let rest = self.take(extra)?;
+        Some(rest.iter().fold((first & 0x3f) as u64, |acc, b| (acc << 8) | *b as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let digits: Vec<u8> = s.bytes().filter(u8::is_ascii_hexdigit).collect();
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    /// The ClientHello record from RFC 8448 §3, asking for "server"
+    fn rfc8448_client_hello() -> Vec<u8> {
+        hex("16030100c4010000c00303cb34ecb1e78163ba1c38c6dacb196a6dffa21a8d9912ec18a2ef6283024dece7
+             000006130113031302010000910000000b0009000006736572766572ff01000100000a00140012001d0017
+             001800190100010101020103010400230000003300260024001d002099381de560e4bd43d23d8e435a7dba
+             feb3c06e51c13cae4d5413691e529aaf2c002b0003020304000d0020001e04030503060302030804080508
+             0604010501060102010402050206020202002d00020101001c00024001")
+    }
+
+    const RFC9001_DCID: [u8; 8] = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+    /// The client Initial from RFC 9001 Appendix A.2, protected here with the
+    /// same keys, packet number and padding as in the RFC
+    fn rfc9001_client_initial() -> Vec<u8> {
+        let mut header = hex("c300000001088394c8f03e5157080000449e00000002");
+        let mut payload = hex(
+            "060040f1010000ed0303ebf8fa56f12939b9584a3896472ec40bb863cfd3e86804fe3a47f06a2b69484c
+             00000413011302010000c000000010000e00000b6578616d706c652e636f6dff01000100000a00080006
+             001d0017001800100007000504616c706e000500050100000000003300260024001d00209370b2c9caa4
+             7fbabaf4559fedba753de171fa71f50f1ce15d43e994ec74d748002b0003020304000d0010000e040305
+             0306030203080408050806002d00020101001c00024001003900320408ffffffffffffffff05048000ff
+             ff07048000ffff0801100104800075300901100f088394c8f03e51570806048000ffff",
+        );
+        payload.resize(1162, 0);
+
+        let keys = InitialKeys::client(&RFC9001_DCID).unwrap();
+        let mut nonce = keys.iv;
+        nonce[11] ^= 2;
+        let tag = keys
+            .key
+            .seal_in_place_separate_tag(Nonce::assume_unique_for_key(nonce), Aad::from(&header), &mut payload)
+            .unwrap();
+
+        let mask = keys.hp.new_mask(&payload[..16]).unwrap();
+        header[0] ^= mask[0] & 0x0f;
+        let pn_offset = header.len() - 4;
+        for (byte, m) in header[pn_offset..].iter_mut().zip(&mask[1..]) {
+            *byte ^= m;
+        }
+
+        [header, payload, tag.as_ref().to_vec()].concat()
+    }
+
+    #[test]
+    fn rfc9001_client_initial_names_example_com() {
+        let keys = InitialKeys::client(&RFC9001_DCID).unwrap();
+        assert_eq!(keys.iv.to_vec(), hex("fa044b2f42a3fd3b46fb255c"));
+
+        let packet = rfc9001_client_initial();
+        assert_eq!(packet.len(), 1200);
+        // The protected header and first ciphertext bytes given in the RFC
+        assert_eq!(
+            packet[..42],
+            hex("c000000001088394c8f03e5157080000449e7b9aec34d1b1c98dd7689fb8ec11d242b123dc9bd8bab936")[..]
+        );
+
+        assert_eq!(quic_sni(&packet), Some("example.com".to_string()));
+        assert_eq!(server_name(false, &packet), Some(("example.com".to_string(), NameSource::Quic)));
+    }
+
+    #[test]
+    fn tls_client_hello_names_its_server() {
+        let record = rfc8448_client_hello();
+        assert_eq!(tls_sni(&record), Some("server".to_string()));
+        assert_eq!(server_name(true, &record), Some(("server".to_string(), NameSource::Tls)));
+
+        // Everything after the server_name extension may be missing
+        assert_eq!(tls_sni(&record[..71]), Some("server".to_string()));
+        assert_eq!(tls_sni(&record[..70]), None);
+    }
+
+    #[test]
+    fn http_host_headers() {
+        let cases = [
+            ("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n", Some("example.com")),
+            ("POST /x HTTP/1.1\r\nUser-Agent: t\r\nhost: Example.COM:8080\r\n\r\n", Some("example.com")),
+            ("GET / HTTP/1.1\r\nHost: [2001:db8::1]:8080\r\n\r\n", Some("2001:db8::1")),
+            ("GET / HTTP/1.1\r\nHost: [2001:DB8::1]\r\n\r\n", Some("2001:db8::1")),
+            ("HEAD / HTTP/1.0\nHost: 192.0.2.1:80\n\n", Some("192.0.2.1")),
+            ("CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n", Some("example.com")),
+            ("GET / HTTP/1.1\r\n\r\nHost: example.com\r\n", None),
+            ("GET / HTTP/1.1\r\nHost: exa mple.com\r\n\r\n", None),
+            ("GET / HTTP/1.1\r\nHost:\r\n\r\n", None),
+            ("HTTP/1.1 200 OK\r\nHost: example.com\r\n\r\n", None),
+            ("get / HTTP/1.1\r\nHost: example.com\r\n\r\n", None),
+        ];
+        for (request, expected) in cases {
+            assert_eq!(http_host(request.as_bytes()).as_deref(), expected, "{:?}", request);
+        }
+        assert_eq!(
+            server_name(true, cases[0].0.as_bytes()),
+            Some(("example.com".to_string(), NameSource::Http))
+        );
+    }
+
+    #[test]
+    fn truncated_and_garbage_payloads_have_no_name() {
+        let initial = rfc9001_client_initial();
+        let mut tampered = initial.clone();
+        tampered[100] ^= 1;
+        let mut other_version = initial.clone();
+        other_version[4] = 2;
+
+        let cases: Vec<(&str, Vec<u8>)> = vec![
+            ("empty", Vec::new()),
+            ("one byte", vec![0x16]),
+            ("record header only", vec![0x16, 0x03, 0x01, 0xff, 0xff]),
+            ("ServerHello", [&[0x16, 0x03, 0x03, 0x00, 0x04, 0x02][..], &[0; 3]].concat()),
+            ("ClientHello without extensions", rfc8448_client_hello()[..52].to_vec()),
+            ("noise", (0..=255).collect()),
+            ("initial cut short", initial[..600].to_vec()),
+            ("initial header only", initial[..26].to_vec()),
+            ("initial with a flipped bit", tampered),
+            ("another QUIC version", other_version),
+            ("connection ID past the end", vec![0xc0, 0, 0, 0, 1, 0xff, 1, 2]),
+        ];
+        for (case, data) in cases {
+            assert_eq!(server_name(true, &data), None, "{} over TCP", case);
+            assert_eq!(server_name(false, &data), None, "{} over UDP", case);
+        }
+    }
+}
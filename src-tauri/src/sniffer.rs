@@ -1,41 +1,415 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
 use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use etherparse::SlicedPacket;
+use chrono::{DateTime, Utc};
+use etherparse::{LaxNetSlice, LaxSlicedPacket};
+use serde::{Deserialize, Serialize};
 
-/// Filter out private/local/reserved IP addresses
-fn is_public_ip(ip: &IpAddr) -> bool {
-    match ip {
-        IpAddr::V4(v4) => {
-            !v4.is_private()
-                && !v4.is_loopback()
-                && !v4.is_link_local()
-                && !v4.is_broadcast()
-                && !v4.is_unspecified()
-                && !v4.is_multicast()
-                // Filter CGNAT range 100.64.0.0/10
-                && !(v4.octets()[0] == 100 && (v4.octets()[1] & 0xC0) == 64)
+use crate::address_class;
+use crate::db::{bucket_start, MINUTE_BUCKET_SECS};
+use crate::dns;
+use crate::exclusions::Exclusions;
+use crate::recorder::{Recorder, RecordingConfig, HEADER_SNAPLEN};
+use crate::sni::{self, NameSource};
+
+/// How often aggregated per-IP traffic is handed to `on_flush`
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A flow with no packets for this long is forgotten, so a later packet on
+/// the same 5-tuple is oriented afresh and recorded as a new flow
+pub const FLOW_IDLE_TIMEOUT: chrono::Duration = chrono::Duration::seconds(120);
+
+/// Captured length for the payload handle: a full EDNS-sized DNS response
+/// or a ClientHello plus headers
+const PAYLOAD_SNAPLEN: i32 = 4096 + HEADER_SNAPLEN;
+const DNS_PORT: u16 = 53;
+const IP_PROTO_IPIP: u8 = 4;
+const IP_PROTO_TCP: u8 = 6;
+const IP_PROTO_UDP: u8 = 17;
+const IP_PROTO_IPV6: u8 = 41;
+const IP_PROTO_GRE: u8 = 47;
+const VXLAN_PORT: u16 = 4789;
+const GTPU_PORT: u16 = 2152;
+
+/// Tunnels opened inside one another before giving up on a packet
+const MAX_TUNNEL_DEPTH: usize = 4;
+
+/// Packets the payload handle reads: DNS responses, and client packets that
+/// may carry a ClientHello, an HTTP request or a QUIC Initial. The `tcp[]`
+/// and `udp[]` payload tests only work on IPv4, so IPv6 falls back to ports.
+/// HTTP requests are matched on the first four bytes of each of
+/// `sni::HTTP_METHODS`.
+const PAYLOAD_FILTER: &str = "udp src port 53 \
+    or (ip and tcp[((tcp[12:1] & 0xf0) >> 2):1] = 0x16) \
+    or (ip and (tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x47455420 \
+        or tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x504f5354 \
+        or tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x48454144 \
+        or tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x50555420 \
+        or tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x44454c45 \
+        or tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x4f505449 \
+        or tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x50415443 \
+        or tcp[((tcp[12:1] & 0xf0) >> 2):4] = 0x434f4e4e)) \
+    or (ip and udp[8:1] & 0xf0 = 0xc0) \
+    or (ip6 and (tcp dst port 443 or tcp dst port 80 or udp dst port 443))";
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_URG: u8 = 0x20;
+const TCP_ECE: u8 = 0x40;
+const TCP_CWR: u8 = 0x80;
+
+/// Which way a packet travelled relative to this machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
         }
-        IpAddr::V6(v6) => {
-            !v6.is_loopback()
-                && !v6.is_unspecified()
-                && !v6.is_multicast()
+    }
+}
+
+/// Encapsulation a packet was taken out of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TunnelKind {
+    Gre,
+    Vxlan,
+    /// IPv4 or IPv6 inside IPv4 or IPv6, including 6in4
+    IpIp,
+    GtpU,
+}
+
+impl TunnelKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TunnelKind::Gre => "gre",
+            TunnelKind::Vxlan => "vxlan",
+            TunnelKind::IpIp => "ipip",
+            TunnelKind::GtpU => "gtpu",
         }
     }
 }
 
-pub struct Sniffer {
+/// Packet and byte totals for one remote IP within one time-series bucket,
+/// accumulated between flushes
+#[derive(Debug, Clone)]
+pub struct IpTraffic {
+    pub ip: String,
+    /// Direction of the first packet seen for this IP in the bucket
+    pub direction: Direction,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+impl IpTraffic {
+    fn new(ip: String, direction: Direction, ts: DateTime<Utc>) -> Self {
+        Self {
+            ip,
+            direction,
+            packets_in: 0,
+            packets_out: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            first_seen: ts,
+            last_seen: ts,
+        }
+    }
+
+    pub fn packets(&self) -> u64 {
+        self.packets_in + self.packets_out
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes_in + self.bytes_out
+    }
+
+    fn add_packet(&mut self, direction: Direction, len: u64, ts: DateTime<Utc>) {
+        match direction {
+            Direction::Inbound => {
+                self.packets_in += 1;
+                self.bytes_in += len;
+            }
+            Direction::Outbound => {
+                self.packets_out += 1;
+                self.bytes_out += len;
+            }
+        }
+        if ts < self.first_seen {
+            self.first_seen = ts;
+        }
+        if ts > self.last_seen {
+            self.last_seen = ts;
+        }
+    }
+}
+
+/// A flow's 5-tuple, oriented as the first packet we saw for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    src: IpAddr,
+    dst: IpAddr,
+    src_port: u16,
+    dst_port: u16,
+    protocol: u8,
+}
+
+impl FlowKey {
+    fn reversed(&self) -> Self {
+        Self {
+            src: self.dst,
+            dst: self.src,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+            protocol: self.protocol,
+        }
+    }
+}
+
+/// Packet and byte totals for one flow, accumulated between flushes
+#[derive(Debug, Clone)]
+pub struct FlowTraffic {
+    pub src_ip: String,
+    pub src_port: u16,
+    pub dst_ip: String,
+    pub dst_port: u16,
+    pub protocol: String,
+    pub remote_ip: String,
+    /// Direction of the packet that opened the flow
+    pub direction: Direction,
+    /// OR of every TCP flag seen on the flow (FIN=0x01 … CWR=0x80)
+    pub tcp_flags: u8,
+    pub packets: u64,
+    pub bytes: u64,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    /// Outermost tunnel the flow was decapsulated from, if any. Packets of the
+    /// same 5-tuple through another tunnel or none are another flow.
+    pub tunnel: Option<TunnelKind>,
+    /// Remote end of that tunnel, e.g. the VPN server
+    pub tunnel_endpoint: Option<String>,
+}
+
+impl FlowTraffic {
+    fn new(key: &FlowKey, remote_ip: String, direction: Direction, ts: DateTime<Utc>) -> Self {
+        Self {
+            src_ip: key.src.to_string(),
+            src_port: key.src_port,
+            dst_ip: key.dst.to_string(),
+            dst_port: key.dst_port,
+            protocol: protocol_name(key.protocol),
+            remote_ip,
+            direction,
+            tcp_flags: 0,
+            packets: 0,
+            bytes: 0,
+            start_time: ts,
+            end_time: ts,
+            tunnel: None,
+            tunnel_endpoint: None,
+        }
+    }
+
+    fn add_packet(&mut self, tcp_flags: u8, len: u64, ts: DateTime<Utc>) {
+        self.packets += 1;
+        self.bytes += len;
+        self.tcp_flags |= tcp_flags;
+        if ts < self.start_time {
+            self.start_time = ts;
+        }
+        if ts > self.end_time {
+            self.end_time = ts;
+        }
+    }
+}
+
+/// An A or AAAA record seen in a DNS response
+#[derive(Debug, Clone)]
+pub struct DnsAnswer {
+    /// The name that was asked for, before any CNAMEs were followed
+    pub domain: String,
+    pub ip: String,
+    pub ttl: u32,
+    pub seen_at: DateTime<Utc>,
+}
+
+/// A server name a client asked for at the start of a flow
+#[derive(Debug, Clone)]
+pub struct ServerNameSeen {
+    /// The flow's 5-tuple as sent by the client
+    pub src_ip: String,
+    pub src_port: u16,
+    pub dst_ip: String,
+    pub dst_port: u16,
+    pub protocol: String,
+    pub name: String,
+    pub source: NameSource,
+    pub seen_at: DateTime<Utc>,
+}
+
+/// Everything the capture thread hands to `on_flush` in one go
+#[derive(Debug, Default)]
+pub struct CaptureBatch {
+    /// Interface the batch was captured on; `None` for imported files
+    pub interface: Option<String>,
+    pub ips: Vec<IpTraffic>,
+    pub flows: Vec<FlowTraffic>,
+    pub dns: Vec<DnsAnswer>,
+    pub server_names: Vec<ServerNameSeen>,
+}
+
+/// Accumulates per-IP and per-flow totals between flushes
+#[derive(Default)]
+struct TrafficAggregator {
+    /// Keyed by IP and minute bucket, so a batch spanning several minutes
+    /// (as imports do) lands in the right time-series buckets
+    ips: HashMap<(String, i64), IpTraffic>,
+    /// Keyed by 5-tuple and the tunnel type and endpoint it came through, so
+    /// the same inner connection through two tunnels stays two flows
+    flows: HashMap<(FlowKey, Option<(TunnelKind, IpAddr)>), FlowTraffic>,
+    /// Orientation and last activity of recent flows, kept across flushes
+    /// so replies are counted against the flow their request opened
+    known_flows: HashMap<FlowKey, DateTime<Utc>>,
+    dns: Vec<DnsAnswer>,
+    server_names: Vec<ServerNameSeen>,
+    latest_ts: Option<DateTime<Utc>>,
+}
+
+impl TrafficAggregator {
+    fn record(
+        &mut self,
+        info: &PacketInfo,
+        remote: IpAddr,
+        direction: Direction,
+        len: u64,
+        ts: DateTime<Utc>,
+    ) {
+        let ip = remote.to_string();
+
+        self.ips
+            .entry((ip.clone(), bucket_start(ts, MINUTE_BUCKET_SECS)))
+            .or_insert_with(|| IpTraffic::new(ip.clone(), direction, ts))
+            .add_packet(direction, len, ts);
+
+        let mut key = info.flow_key();
+        if !self.known_flows.contains_key(&key) && self.known_flows.contains_key(&key.reversed()) {
+            key = key.reversed();
+        }
+        self.known_flows.insert(key, ts);
+        let tunnel = info.tunnel.as_ref().map(|t| (t.kind, t.remote(direction)));
+        let flow = self.flows.entry((key, tunnel)).or_insert_with(|| {
+            let mut flow = FlowTraffic::new(&key, ip, direction, ts);
+            if let Some((kind, endpoint)) = tunnel {
+                flow.tunnel = Some(kind);
+                flow.tunnel_endpoint = Some(endpoint.to_string());
+            }
+            flow
+        });
+        flow.add_packet(info.tcp_flags, len, ts);
+
+        if self.latest_ts.is_none_or(|latest| ts > latest) {
+            self.latest_ts = Some(ts);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ips.is_empty() && self.flows.is_empty() && self.dns.is_empty() && self.server_names.is_empty()
+    }
+
+    fn take_batch(&mut self) -> CaptureBatch {
+        if let Some(latest) = self.latest_ts {
+            let cutoff = latest - FLOW_IDLE_TIMEOUT;
+            self.known_flows.retain(|_, last| *last >= cutoff);
+        }
+
+        let mut ips: Vec<IpTraffic> = self.ips.drain().map(|(_, t)| t).collect();
+        // Oldest first, so an IP's first recorded direction is its earliest
+        ips.sort_by_key(|t| t.first_seen);
+
+        CaptureBatch {
+            interface: None,
+            ips,
+            flows: self.flows.drain().map(|(_, f)| f).collect(),
+            dns: std::mem::take(&mut self.dns),
+            server_names: std::mem::take(&mut self.server_names),
+        }
+    }
+}
+
+/// Progress of an offline capture file import
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportProgress {
+    pub path: String,
+    pub packets: u64,
+    pub bytes_processed: u64,
+    pub total_bytes: u64,
+    pub done: bool,
+}
+
+/// Outcome of an offline capture file import
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportSummary {
+    pub path: String,
+    pub packets: u64,
+    pub recorded_packets: u64,
+    pub unique_ips: u64,
+}
+
+/// A capture running on one interface
+struct Session {
     running: Arc<AtomicBool>,
+    started_at: DateTime<Utc>,
+    filter: Option<String>,
+    recording: bool,
+}
+
+/// What `Sniffer::sessions` reports about a running capture
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureSession {
+    pub interface: String,
+    pub started_at: DateTime<Utc>,
+    /// User BPF filter, if one was given
+    pub filter: Option<String>,
+    /// Whether packets are also being written to pcap files
+    pub recording: bool,
+}
+
+pub struct Sniffer {
+    /// Captures by interface name. A session whose thread died (e.g. the
+    /// interface went away) stays here with `running` cleared until replaced.
+    sessions: Mutex<HashMap<String, Session>>,
+    importing: Arc<AtomicBool>,
     seen_ips: Arc<Mutex<HashSet<String>>>,
+    exclusions: Arc<Exclusions>,
+    lan_peers: Arc<AtomicBool>,
+    decapsulate: Arc<AtomicBool>,
 }
 
 impl Sniffer {
     pub fn new() -> Self {
         Self {
-            running: Arc::new(AtomicBool::new(false)),
+            sessions: Mutex::new(HashMap::new()),
+            importing: Arc::new(AtomicBool::new(false)),
             seen_ips: Arc::new(Mutex::new(HashSet::new())),
+            exclusions: Arc::new(Exclusions::default()),
+            lan_peers: Arc::new(AtomicBool::new(false)),
+            decapsulate: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -47,6 +421,42 @@ impl Sniffer {
         }
     }
 
+    /// Addresses assigned to the given interface, used to tell which side of a packet is us
+    fn local_addresses(interface: &str) -> HashSet<IpAddr> {
+        pcap::Device::list()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|d| d.name == interface)
+            .map(|d| d.addresses.into_iter().map(|a| a.addr).collect())
+            .unwrap_or_default()
+    }
+
+    /// Remote addresses dropped by live captures and imports alike
+    pub fn exclusions(&self) -> &Exclusions {
+        &self.exclusions
+    }
+
+    /// Also record peers on private, CGNAT, unique-local and link-local
+    /// addresses, not just public ones. Applies to a running capture.
+    pub fn set_record_lan_peers(&self, enabled: bool) {
+        self.lan_peers.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn records_lan_peers(&self) -> bool {
+        self.lan_peers.load(Ordering::SeqCst)
+    }
+
+    /// Look inside GRE, VXLAN, IP-in-IP and GTP-U tunnels and record the
+    /// inner remote address, keeping the tunnel endpoint on the flow.
+    /// Applies to a running capture.
+    pub fn set_decapsulate_tunnels(&self, enabled: bool) {
+        self.decapsulate.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn decapsulates_tunnels(&self) -> bool {
+        self.decapsulate.load(Ordering::SeqCst)
+    }
+
     /// Get the default interface name
     pub fn default_interface() -> Option<String> {
         pcap::Device::lookup()
@@ -55,79 +465,324 @@ impl Sniffer {
             .map(|d| d.name)
     }
 
-    /// Start capturing packets on the given interface.
-    /// Calls `on_new_ip` for each new public IP discovered.
-    pub fn start<F>(
+    /// Start capturing packets on the given interface, alongside any captures
+    /// already running on others.
+    /// Calls `on_new_ip` for each new recorded remote IP discovered, with the
+    /// time it was first seen, and `on_flush` every `FLUSH_INTERVAL` with the
+    /// per-IP and per-flow totals seen since the last flush.
+    /// With `recording` set, every captured packet is also written to rotating pcap files.
+    /// DNS responses and connection openers too long for the header-only
+    /// snaplen are read through a second handle that only sees those.
+    /// A user `filter` (BPF syntax) narrows both handles further; it is
+    /// compiled against the device before anything starts, so a bad one is
+    /// reported here.
+    pub fn start<F, G>(
         &self,
         interface: &str,
+        recording: Option<RecordingConfig>,
+        filter: Option<&str>,
         on_new_ip: F,
+        on_flush: G,
     ) -> Result<(), String>
     where
-        F: Fn(String) + Send + 'static,
+        F: Fn(String, DateTime<Utc>) + Send + 'static,
+        G: Fn(CaptureBatch) + Send + Sync + 'static,
     {
-        if self.running.load(Ordering::SeqCst) {
-            return Err("Sniffer is already running".to_string());
+        let mut sessions = self.sessions.lock().map_err(|e| e.to_string())?;
+        if sessions.get(interface).is_some_and(|s| s.running.load(Ordering::SeqCst)) {
+            return Err(format!("Already capturing on {}", interface));
         }
 
-        let cap = pcap::Capture::from_device(interface)
+        // Headers are enough for IP extraction; recording may want payloads too
+        let snaplen = recording.as_ref().map_or(HEADER_SNAPLEN, |r| r.snaplen());
+        let mut cap = pcap::Capture::from_device(interface)
             .map_err(|e| format!("Failed to open device '{}': {}", interface, e))?
             .promisc(false)
-            .snaplen(128) // We only need headers, not payload
+            .snaplen(snaplen)
             .timeout(1000)
             .open()
             .map_err(|e| format!("Failed to start capture: {}", e))?;
+        let link = LinkLayer::of(&cap).map_err(|e| format!("{} on '{}'", e, interface))?;
+
+        // Only capture IP packets (skip ARP, etc.)
+        let user_filter = filter.map(str::trim).filter(|f| !f.is_empty());
+        let ip_filter = match user_filter {
+            Some(user) => format!("(ip or ip6) and ({})", user),
+            None => "ip or ip6".to_string(),
+        };
+        cap.filter(&ip_filter, true)
+            .map_err(|e| format!("Invalid capture filter: {}", e))?;
+
+        // The main handle already sees packets that fit in its snaplen whole;
+        // the payload handle picks up only the longer ones, so no packet is
+        // parsed twice
+        let payload_cap = if snaplen < PAYLOAD_SNAPLEN {
+            match Self::open_payload_capture(interface, snaplen, user_filter) {
+                Ok(cap) => Some(cap),
+                Err(e) => {
+                    log::warn!("Payload capture unavailable, long DNS responses and server names will be missed: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let is_recording = recording.is_some();
+        let mut recorder = recording
+            .map(|config| Recorder::new(config, cap.get_datalink(), interface))
+            .transpose()?;
+
+        let local_addrs = Self::local_addresses(interface);
+        log::info!("Local addresses on {}: {:?}", interface, local_addrs);
 
-        self.running.store(true, Ordering::SeqCst);
-        let running = self.running.clone();
+        let running = Arc::new(AtomicBool::new(true));
+        sessions.insert(
+            interface.to_string(),
+            Session {
+                running: running.clone(),
+                started_at: Utc::now(),
+                filter: user_filter.map(String::from),
+                recording: is_recording,
+            },
+        );
+        let name = interface.to_string();
         let seen_ips = self.seen_ips.clone();
+        let exclusions = self.exclusions.clone();
+        let lan_peers = self.lan_peers.clone();
+        let decapsulate = self.decapsulate.clone();
+        let on_flush = Arc::new(on_flush);
+
+        if let Some(payload_cap) = payload_cap {
+            let running = running.clone();
+            let local_addrs = local_addrs.clone();
+            let exclusions = exclusions.clone();
+            let lan_peers = lan_peers.clone();
+            let decapsulate = decapsulate.clone();
+            let name = name.clone();
+            let on_flush = on_flush.clone();
+            thread::spawn(move || {
+                let scope = CaptureScope {
+                    interface: Some(&name),
+                    link,
+                    local_addrs: &local_addrs,
+                    exclusions: &exclusions,
+                    lan_peers: &lan_peers,
+                    decapsulate: &decapsulate,
+                };
+                run_payload_capture(payload_cap, &running, &scope, |batch| on_flush(batch));
+                log::info!("Payload capture thread on {} stopped", name);
+            });
+        }
 
         thread::spawn(move || {
-            let mut cap = cap;
-            // Only capture IP packets (skip ARP, etc.)
-            if let Err(e) = cap.filter("ip or ip6", true) {
-                log::error!("Failed to set BPF filter: {}", e);
-            }
-
-            while running.load(Ordering::SeqCst) {
-                match cap.next_packet() {
-                    Ok(packet) => {
-                        if let Some(ip) = extract_dest_ip(packet.data) {
-                            if is_public_ip(&ip) {
-                                let ip_str = ip.to_string();
-                                let is_new = {
-                                    let mut seen = seen_ips.lock().unwrap();
-                                    seen.insert(ip_str.clone())
-                                };
-                                if is_new {
-                                    on_new_ip(ip_str);
-                                }
-                            }
-                        }
-                    }
-                    Err(pcap::Error::TimeoutExpired) => {
-                        // Normal — no packets in timeout window
-                        continue;
-                    }
-                    Err(e) => {
-                        log::error!("Capture error: {}", e);
-                        break;
+            let scope = CaptureScope {
+                interface: Some(&name),
+                link,
+                local_addrs: &local_addrs,
+                exclusions: &exclusions,
+                lan_peers: &lan_peers,
+                decapsulate: &decapsulate,
+            };
+            run_capture(cap, &running, &seen_ips, &scope, on_new_ip, |batch| on_flush(batch), |packet| {
+                if let Some(recorder) = recorder.as_mut() {
+                    match packet {
+                        Some((packet, _)) => recorder.write(packet),
+                        None => recorder.tick(),
                     }
                 }
-            }
-            log::info!("Sniffer thread stopped");
+            });
+            // Also stops the payload thread if the capture ended on its own
+            running.store(false, Ordering::SeqCst);
+            log::info!("Sniffer thread on {} stopped", name);
         });
 
         Ok(())
     }
 
-    /// Stop the capture
-    pub fn stop(&self) {
-        self.running.store(false, Ordering::SeqCst);
+    /// Open a handle for `PAYLOAD_FILTER` packets longer than `main_snaplen`
+    /// that also match `user_filter`
+    fn open_payload_capture(
+        interface: &str,
+        main_snaplen: i32,
+        user_filter: Option<&str>,
+    ) -> Result<pcap::Capture<pcap::Active>, String> {
+        let mut cap = pcap::Capture::from_device(interface)
+            .map_err(|e| format!("Failed to open device '{}': {}", interface, e))?
+            .promisc(false)
+            .snaplen(PAYLOAD_SNAPLEN)
+            .timeout(1000)
+            .open()
+            .map_err(|e| format!("Failed to start payload capture: {}", e))?;
+        let mut filter = format!("greater {} and ({})", main_snaplen + 1, PAYLOAD_FILTER);
+        if let Some(user) = user_filter {
+            filter.push_str(&format!(" and ({})", user));
+        }
+        cap.filter(&filter, true)
+            .map_err(|e| format!("Failed to set payload filter: {}", e))?;
+        Ok(cap)
+    }
+
+    /// Feed a `.pcap`/`.pcapng` file through the same pipeline as a live capture,
+    /// keeping the original packet timestamps. Blocks until the file is read or
+    /// `cancel_import` is called; `on_progress` is called periodically and once at the end.
+    pub fn import_file<F, G, P>(
+        &self,
+        path: &str,
+        on_new_ip: F,
+        on_flush: G,
+        on_progress: P,
+    ) -> Result<ImportSummary, String>
+    where
+        F: Fn(String, DateTime<Utc>),
+        G: Fn(CaptureBatch),
+        P: Fn(ImportProgress),
+    {
+        if self.importing.swap(true, Ordering::SeqCst) {
+            return Err("An import is already in progress".to_string());
+        }
+
+        let result = self.run_import(path, on_new_ip, on_flush, on_progress);
+        self.importing.store(false, Ordering::SeqCst);
+        result
+    }
+
+    fn run_import<F, G, P>(
+        &self,
+        path: &str,
+        on_new_ip: F,
+        on_flush: G,
+        on_progress: P,
+    ) -> Result<ImportSummary, String>
+    where
+        F: Fn(String, DateTime<Utc>),
+        G: Fn(CaptureBatch),
+        P: Fn(ImportProgress),
+    {
+        let total_bytes = std::fs::metadata(path)
+            .map_err(|e| format!("Failed to read '{}': {}", path, e))?
+            .len();
+        let cap = pcap::Capture::from_file(path)
+            .map_err(|e| format!("Failed to open capture file '{}': {}", path, e))?;
+        let link = LinkLayer::of(&cap).map_err(|e| format!("{} in '{}'", e, path))?;
+
+        log::info!("Importing capture file {} ({} bytes)", path, total_bytes);
+
+        // Each import counts as its own session, separate from any live capture
+        let seen_ips = Mutex::new(HashSet::new());
+        let mut packets = 0u64;
+        let mut recorded_packets = 0u64;
+        let mut bytes_processed = 0u64;
+        let mut last_progress = Instant::now();
+
+        let progress = |packets, bytes_processed, done| ImportProgress {
+            path: path.to_string(),
+            packets,
+            bytes_processed,
+            total_bytes,
+            done,
+        };
+
+        run_capture(
+            cap,
+            &self.importing,
+            &seen_ips,
+            &CaptureScope {
+                interface: None,
+                link,
+                local_addrs: &HashSet::new(),
+                exclusions: &self.exclusions,
+                lan_peers: &self.lan_peers,
+                decapsulate: &self.decapsulate,
+            },
+            on_new_ip,
+            on_flush,
+            |packet| {
+                let Some((packet, recorded)) = packet else { return };
+                packets += 1;
+                if recorded {
+                    recorded_packets += 1;
+                }
+                // Per-record header plus captured bytes; close enough for a progress bar
+                bytes_processed += 16 + packet.header.caplen as u64;
+                if last_progress.elapsed() >= Duration::from_millis(250) {
+                    on_progress(progress(packets, bytes_processed.min(total_bytes), false));
+                    last_progress = Instant::now();
+                }
+            },
+        );
+
+        on_progress(progress(packets, total_bytes, true));
+
+        let unique_ips = seen_ips.lock().map(|s| s.len() as u64).unwrap_or(0);
+        log::info!(
+            "Imported {}: {} packets, {} recorded, {} unique IPs",
+            path, packets, recorded_packets, unique_ips
+        );
+
+        Ok(ImportSummary {
+            path: path.to_string(),
+            packets,
+            recorded_packets,
+            unique_ips,
+        })
+    }
+
+    /// Stop an in-progress import; what was read so far is kept
+    pub fn cancel_import(&self) {
+        self.importing.store(false, Ordering::SeqCst);
+    }
+
+    /// Stop the capture on `interface`
+    pub fn stop(&self, interface: &str) -> Result<(), String> {
+        let mut sessions = self.sessions.lock().map_err(|e| e.to_string())?;
+        match sessions.remove(interface) {
+            Some(session) => {
+                session.running.store(false, Ordering::SeqCst);
+                Ok(())
+            }
+            None => Err(format!("Not capturing on {}", interface)),
+        }
+    }
+
+    /// Stop every capture
+    pub fn stop_all(&self) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            for (_, session) in sessions.drain() {
+                session.running.store(false, Ordering::SeqCst);
+            }
+        }
     }
 
-    /// Check if sniffer is running
+    /// Check if any capture is running
     pub fn is_running(&self) -> bool {
-        self.running.load(Ordering::SeqCst)
+        self.sessions
+            .lock()
+            .is_ok_and(|s| s.values().any(|s| s.running.load(Ordering::SeqCst)))
+    }
+
+    /// Running captures, by interface name
+    pub fn sessions(&self) -> Vec<CaptureSession> {
+        let Ok(sessions) = self.sessions.lock() else {
+            return Vec::new();
+        };
+        let mut running: Vec<CaptureSession> = sessions
+            .iter()
+            .filter(|(_, s)| s.running.load(Ordering::SeqCst))
+            .map(|(interface, s)| CaptureSession {
+                interface: interface.clone(),
+                started_at: s.started_at,
+                filter: s.filter.clone(),
+                recording: s.recording,
+            })
+            .collect();
+        running.sort_by(|a, b| a.interface.cmp(&b.interface));
+        running
+    }
+
+    /// Check if a capture file is being imported
+    pub fn is_importing(&self) -> bool {
+        self.importing.load(Ordering::SeqCst)
     }
 
     /// Reset seen IPs (e.g. on new session)
@@ -138,20 +793,980 @@ impl Sniffer {
     }
 }
 
-/// Extract destination IP from raw packet data (Ethernet frame)
-fn extract_dest_ip(data: &[u8]) -> Option<IpAddr> {
-    match SlicedPacket::from_ethernet(data) {
-        Ok(packet) => {
-            match packet.net {
-                Some(etherparse::NetSlice::Ipv4(ipv4_slice)) => {
-                    Some(IpAddr::V4(ipv4_slice.header().destination_addr()))
+/// Where a capture runs: the interface its batches are labelled with, how
+/// its packets are framed, whether tunnels are opened, how it tells our
+/// side of a packet from the remote one, and which remote addresses it drops
+struct CaptureScope<'a> {
+    interface: Option<&'a str>,
+    link: LinkLayer,
+    local_addrs: &'a HashSet<IpAddr>,
+    exclusions: &'a Exclusions,
+    lan_peers: &'a AtomicBool,
+    decapsulate: &'a AtomicBool,
+}
+
+impl CaptureScope<'_> {
+    fn parse<'p>(&self, data: &'p [u8]) -> Option<PacketInfo<'p>> {
+        parse_packet(self.link, data, self.decapsulate.load(Ordering::Relaxed))
+    }
+
+    /// The far end of `info` and which way it travelled. A decapsulated
+    /// packet takes its direction from the tunnel, since the inner
+    /// addresses are rarely assigned to our interface.
+    fn remote(&self, info: &PacketInfo) -> Option<(IpAddr, Direction)> {
+        match &info.tunnel {
+            Some(tunnel) => match remote_endpoint(tunnel.src, tunnel.dst, self.local_addrs)? {
+                (_, Direction::Outbound) => Some((info.dst, Direction::Outbound)),
+                (_, Direction::Inbound) => Some((info.src, Direction::Inbound)),
+            },
+            None => remote_endpoint(info.src, info.dst, self.local_addrs),
+        }
+    }
+
+    /// Whether traffic with `ip` on the far end is recorded: public
+    /// addresses, LAN peers if enabled, and neither if excluded
+    fn records(&self, ip: &IpAddr) -> bool {
+        let class = address_class::classify(ip);
+        let wanted = class.is_public() || (class.is_lan() && self.lan_peers.load(Ordering::Relaxed));
+        wanted && !self.exclusions.contains(ip)
+    }
+
+    /// `batch` labelled with the interface and without anything about
+    /// excluded addresses, including DNS answers and server names, and
+    /// traffic recorded before an address's ASN was known to be excluded
+    fn finish(&self, mut batch: CaptureBatch) -> CaptureBatch {
+        batch.interface = self.interface.map(String::from);
+        self.exclusions.retain(&mut batch);
+        batch
+    }
+}
+
+/// Read packets from `cap` until it runs dry or `running` is cleared, feeding
+/// the remote IPs `scope` records through `on_new_ip`/`on_flush`. `on_packet`
+/// sees every packet along with whether it was recorded, and `None` whenever
+/// the read timed out with nothing to show.
+fn run_capture<T, F, G, P>(
+    mut cap: pcap::Capture<T>,
+    running: &AtomicBool,
+    seen_ips: &Mutex<HashSet<String>>,
+    scope: &CaptureScope,
+    on_new_ip: F,
+    on_flush: G,
+    mut on_packet: P,
+) where
+    T: pcap::Activated + ?Sized,
+    F: Fn(String, DateTime<Utc>),
+    G: Fn(CaptureBatch),
+    P: FnMut(Option<(&pcap::Packet, bool)>),
+{
+    let mut pending = TrafficAggregator::default();
+    let mut last_flush = Instant::now();
+
+    while running.load(Ordering::SeqCst) {
+        match cap.next_packet() {
+            Ok(packet) => {
+                let parsed = scope.parse(packet.data).and_then(|info| {
+                    scope.remote(&info).map(|(ip, direction)| (info, ip, direction))
+                });
+                let mut recorded = false;
+                if let Some((info, ip, direction)) = parsed {
+                    let ts = packet_timestamp(packet.header);
+                    // Truncated packets are left to the payload handle
+                    if packet.header.caplen >= packet.header.len {
+                        pending.dns.extend(dns_answers(&info, ts));
+                        if direction == Direction::Outbound && scope.records(&ip) {
+                            pending.server_names.extend(server_name(&info, ts));
+                        }
+                    }
+
+                    if scope.records(&ip) {
+                        let ip_str = ip.to_string();
+                        let is_new = {
+                            let mut seen = seen_ips.lock().unwrap();
+                            seen.insert(ip_str.clone())
+                        };
+                        if is_new {
+                            on_new_ip(ip_str, ts);
+                        }
+
+                        pending.record(&info, ip, direction, packet.header.len as u64, ts);
+                        recorded = true;
+                    }
+                }
+                on_packet(Some((&packet, recorded)));
+            }
+            Err(pcap::Error::TimeoutExpired) => {
+                // Normal — no packets in timeout window
+                on_packet(None);
+            }
+            Err(pcap::Error::NoMorePackets) => break,
+            Err(e) => {
+                log::error!("Capture error: {}", e);
+                break;
+            }
+        }
+
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            if !pending.is_empty() {
+                on_flush(scope.finish(pending.take_batch()));
+            }
+            last_flush = Instant::now();
+        }
+    }
+
+    // Don't lose whatever accumulated since the last flush
+    if !pending.is_empty() {
+        on_flush(scope.finish(pending.take_batch()));
+    }
+}
+
+/// Read packets from a handle opened by `Sniffer::open_payload_capture`,
+/// handing DNS answers and server names to `on_flush` every `FLUSH_INTERVAL`
+fn run_payload_capture<G>(
+    mut cap: pcap::Capture<pcap::Active>,
+    running: &AtomicBool,
+    scope: &CaptureScope,
+    on_flush: G,
+) where
+    G: Fn(CaptureBatch),
+{
+    let mut pending = CaptureBatch::default();
+    let mut last_flush = Instant::now();
+
+    while running.load(Ordering::SeqCst) {
+        match cap.next_packet() {
+            Ok(packet) => {
+                if let Some(info) = scope.parse(packet.data) {
+                    let ts = packet_timestamp(packet.header);
+                    pending.dns.extend(dns_answers(&info, ts));
+                    let outbound = matches!(
+                        scope.remote(&info),
+                        Some((ip, Direction::Outbound)) if scope.records(&ip)
+                    );
+                    if outbound {
+                        pending.server_names.extend(server_name(&info, ts));
+                    }
                 }
-                Some(etherparse::NetSlice::Ipv6(ipv6_slice)) => {
-                    Some(IpAddr::V6(ipv6_slice.header().destination_addr()))
+            }
+            Err(pcap::Error::TimeoutExpired) => {}
+            Err(e) => {
+                log::error!("Payload capture error: {}", e);
+                break;
+            }
+        }
+
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            if !pending.dns.is_empty() || !pending.server_names.is_empty() {
+                on_flush(scope.finish(std::mem::take(&mut pending)));
+            }
+            last_flush = Instant::now();
+        }
+    }
+
+    if !pending.dns.is_empty() || !pending.server_names.is_empty() {
+        on_flush(scope.finish(pending));
+    }
+}
+
+/// A/AAAA answers from a DNS response carried by this packet, if it is one.
+/// mDNS and other non-standard ports are ignored.
+fn dns_answers(info: &PacketInfo, ts: DateTime<Utc>) -> Vec<DnsAnswer> {
+    if info.protocol != IP_PROTO_UDP || info.src_port != DNS_PORT {
+        return Vec::new();
+    }
+    let message = match dns::parse(info.payload) {
+        Ok(m) if m.is_response && m.rcode == dns::RCODE_NOERROR => m,
+        _ => return Vec::new(),
+    };
+    let domain = match message.questions.first() {
+        Some(q) if !q.name.is_empty() => q.name.clone(),
+        _ => return Vec::new(),
+    };
+
+    message
+        .answers
+        .into_iter()
+        .filter_map(|record| {
+            let ip = match record.data {
+                dns::RecordData::A(v4) => IpAddr::V4(v4),
+                dns::RecordData::Aaaa(v6) => IpAddr::V6(v6),
+                _ => return None,
+            };
+            Some(DnsAnswer {
+                domain: domain.clone(),
+                ip: ip.to_string(),
+                ttl: record.ttl,
+                seen_at: ts,
+            })
+        })
+        .collect()
+}
+
+/// The server name an outbound packet asks for, if it opens a TLS or QUIC
+/// handshake or carries an HTTP request
+fn server_name(info: &PacketInfo, ts: DateTime<Utc>) -> Option<ServerNameSeen> {
+    if info.payload.is_empty() {
+        return None;
+    }
+    let (name, source) = match info.protocol {
+        IP_PROTO_TCP => sni::server_name(true, info.payload)?,
+        IP_PROTO_UDP => sni::server_name(false, info.payload)?,
+        _ => return None,
+    };
+    Some(ServerNameSeen {
+        src_ip: info.src.to_string(),
+        src_port: info.src_port,
+        dst_ip: info.dst.to_string(),
+        dst_port: info.dst_port,
+        protocol: protocol_name(info.protocol),
+        name,
+        source,
+        seen_at: ts,
+    })
+}
+
+/// Convert a pcap header timestamp to UTC, falling back to now if it is out of range
+#[allow(clippy::unnecessary_cast)] // time_t is not i64 on every target
+fn packet_timestamp(header: &pcap::PacketHeader) -> DateTime<Utc> {
+    DateTime::from_timestamp(header.ts.tv_sec as i64, header.ts.tv_usec as u32 * 1000)
+        .unwrap_or_else(Utc::now)
+}
+
+/// Pick the remote side of a packet and the direction it travelled.
+/// Interface addresses decide when one side is ours; otherwise (e.g. our
+/// public address behind NAT) a public/non-public split is used, and
+/// failing that the destination is treated as remote.
+fn remote_endpoint(
+    src: IpAddr,
+    dst: IpAddr,
+    local_addrs: &HashSet<IpAddr>,
+) -> Option<(IpAddr, Direction)> {
+    match (local_addrs.contains(&src), local_addrs.contains(&dst)) {
+        (true, false) => Some((dst, Direction::Outbound)),
+        (false, true) => Some((src, Direction::Inbound)),
+        // Traffic to ourselves never leaves the host
+        (true, true) => None,
+        (false, false) => {
+            if address_class::classify(&src).is_public() && !address_class::classify(&dst).is_public() {
+                Some((src, Direction::Inbound))
+            } else {
+                Some((dst, Direction::Outbound))
+            }
+        }
+    }
+}
+
+/// The header fields we track for one captured packet
+struct PacketInfo<'a> {
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    src_port: u16,
+    dst_port: u16,
+    tcp_flags: u8,
+    /// TCP or UDP payload, as far as it was captured
+    payload: &'a [u8],
+    /// The outermost tunnel this packet was taken out of
+    tunnel: Option<Tunnel>,
+}
+
+/// A tunnel's kind and outer addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Tunnel {
+    kind: TunnelKind,
+    src: IpAddr,
+    dst: IpAddr,
+}
+
+impl Tunnel {
+    /// The far end of the tunnel for a packet travelling in `direction`
+    fn remote(&self, direction: Direction) -> IpAddr {
+        match direction {
+            Direction::Outbound => self.dst,
+            Direction::Inbound => self.src,
+        }
+    }
+}
+
+impl PacketInfo<'_> {
+    fn flow_key(&self) -> FlowKey {
+        FlowKey {
+            src: self.src,
+            dst: self.dst,
+            src_port: self.src_port,
+            dst_port: self.dst_port,
+            protocol: self.protocol,
+        }
+    }
+}
+
+/// Lowercase name for an IP protocol number, or the number itself if unknown
+fn protocol_name(protocol: u8) -> String {
+    match etherparse::IpNumber(protocol).keyword_str() {
+        Some(name) => name.to_lowercase(),
+        None => protocol.to_string(),
+    }
+}
+
+fn tcp_flags(tcp: &etherparse::TcpSlice) -> u8 {
+    [
+        (tcp.fin(), TCP_FIN),
+        (tcp.syn(), TCP_SYN),
+        (tcp.rst(), TCP_RST),
+        (tcp.psh(), TCP_PSH),
+        (tcp.ack(), TCP_ACK),
+        (tcp.urg(), TCP_URG),
+        (tcp.ece(), TCP_ECE),
+        (tcp.cwr(), TCP_CWR),
+    ]
+    .into_iter()
+    .filter(|(set, _)| *set)
+    .fold(0, |acc, (_, bit)| acc | bit)
+}
+
+/// How IP packets are framed on a capture handle, from its pcap link type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkLayer {
+    /// Ethernet, with or without 802.1Q/802.1ad VLAN tags
+    Ethernet,
+    /// Bare IP packets, e.g. tun devices and WireGuard
+    RawIp,
+    /// BSD loopback: a 4-byte address family, in host byte order for
+    /// DLT_NULL and network byte order for DLT_LOOP
+    Loopback,
+    /// Linux cooked capture, as used by the `any` device
+    LinuxSll,
+    LinuxSll2,
+}
+
+impl LinkLayer {
+    fn from_linktype(linktype: pcap::Linktype) -> Option<Self> {
+        match linktype {
+            pcap::Linktype::ETHERNET => Some(Self::Ethernet),
+            // DLT_RAW is 12 on most systems but 14 on OpenBSD
+            pcap::Linktype(12) | pcap::Linktype(14) | pcap::Linktype::RAW
+            | pcap::Linktype::IPV4 | pcap::Linktype::IPV6 => Some(Self::RawIp),
+            pcap::Linktype::NULL | pcap::Linktype::LOOP => Some(Self::Loopback),
+            pcap::Linktype::LINUX_SLL => Some(Self::LinuxSll),
+            pcap::Linktype::LINUX_SLL2 => Some(Self::LinuxSll2),
+            _ => None,
+        }
+    }
+
+    /// The link layer of `cap`, or an error naming the unsupported link type
+    fn of<T: pcap::Activated + ?Sized>(cap: &pcap::Capture<T>) -> Result<Self, String> {
+        let linktype = cap.get_datalink();
+        Self::from_linktype(linktype).ok_or_else(|| {
+            let name = linktype.get_name().unwrap_or_else(|_| linktype.0.to_string());
+            format!("Unsupported link type: {}", name)
+        })
+    }
+
+    /// `data` sliced down to its IP header and transport header, keeping
+    /// what there is of packets cut short by the snaplen
+    fn slice(self, data: &[u8]) -> Option<LaxSlicedPacket<'_>> {
+        match self {
+            Self::Ethernet => LaxSlicedPacket::from_ethernet(data).ok(),
+            Self::RawIp => LaxSlicedPacket::from_ip(data).ok(),
+            Self::Loopback => {
+                let family: [u8; 4] = data.get(..4)?.try_into().ok()?;
+                // Families are small, so the byte order shows in which end is zero
+                let family = match u32::from_le_bytes(family) {
+                    le if le > 0xffff => u32::from_be_bytes(family),
+                    le => le,
+                };
+                match family {
+                    // AF_INET, then AF_INET6 on Linux, NetBSD/OpenBSD, FreeBSD and macOS
+                    2 | 10 | 24 | 28 | 30 => LaxSlicedPacket::from_ip(&data[4..]).ok(),
+                    _ => None,
                 }
+            }
+            // Protocol type last in a 16-byte header
+            Self::LinuxSll => Some(ether_type_packet(data.get(14..16)?, data.get(16..)?)),
+            // Protocol type, then 18 bytes of interface and address info
+            Self::LinuxSll2 => Some(ether_type_packet(data.get(..2)?, data.get(20..)?)),
+        }
+    }
+}
+
+fn ether_type_packet<'a>(ether_type: &[u8], data: &'a [u8]) -> LaxSlicedPacket<'a> {
+    let ether_type = u16::from_be_bytes([ether_type[0], ether_type[1]]);
+    LaxSlicedPacket::from_ether_type(etherparse::EtherType(ether_type), data)
+}
+
+fn ip_addrs(packet: &LaxSlicedPacket) -> Option<(IpAddr, IpAddr)> {
+    match &packet.net {
+        Some(LaxNetSlice::Ipv4(ipv4)) => {
+            let header = ipv4.header();
+            Some((IpAddr::V4(header.source_addr()), IpAddr::V4(header.destination_addr())))
+        }
+        Some(LaxNetSlice::Ipv6(ipv6)) => {
+            let header = ipv6.header();
+            Some((IpAddr::V6(header.source_addr()), IpAddr::V6(header.destination_addr())))
+        }
+        None => None,
+    }
+}
+
+/// The packet carried inside `packet` if it is a GRE, VXLAN, IP-in-IP or
+/// GTP-U tunnel packet
+fn decapsulate<'a>(packet: &LaxSlicedPacket<'a>) -> Option<(TunnelKind, LaxSlicedPacket<'a>)> {
+    let ip_payload = match &packet.net {
+        Some(LaxNetSlice::Ipv4(ipv4)) => ipv4.payload(),
+        Some(LaxNetSlice::Ipv6(ipv6)) => ipv6.payload(),
+        None => return None,
+    };
+    // Only the first fragment has the inner headers, and only reassembled
+    // they would make sense
+    if ip_payload.fragmented {
+        return None;
+    }
+
+    match ip_payload.ip_number.0 {
+        IP_PROTO_IPIP | IP_PROTO_IPV6 => {
+            Some((TunnelKind::IpIp, LaxSlicedPacket::from_ip(ip_payload.payload).ok()?))
+        }
+        IP_PROTO_GRE => Some((TunnelKind::Gre, gre_inner(ip_payload.payload)?)),
+        IP_PROTO_UDP => {
+            let Some(etherparse::TransportSlice::Udp(udp)) = &packet.transport else {
+                return None;
+            };
+            match udp.destination_port() {
+                VXLAN_PORT => Some((TunnelKind::Vxlan, vxlan_inner(udp.payload())?)),
+                GTPU_PORT => Some((TunnelKind::GtpU, gtpu_inner(udp.payload())?)),
                 _ => None,
             }
         }
-        Err(_) => None,
+        _ => None,
+    }
+}
+
+/// GRE version 0 (RFC 2784/2890) carrying IP or Ethernet
+fn gre_inner(data: &[u8]) -> Option<LaxSlicedPacket<'_>> {
+    let flags = *data.first()?;
+    let version = data.get(1)? & 0x07;
+    // Routing (RFC 1701) is obsolete; version 1 is PPTP, which carries PPP
+    if flags & 0x40 != 0 || version != 0 {
+        return None;
+    }
+    // Checksum, key and sequence number each add 4 bytes when present
+    let optional = [0x80, 0x20, 0x10].iter().filter(|&&bit| flags & bit != 0).count();
+    let inner = data.get(4 + 4 * optional..)?;
+    match u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) {
+        // Transparent Ethernet bridging, e.g. NVGRE
+        0x6558 => LaxSlicedPacket::from_ethernet(inner).ok(),
+        protocol => {
+            let packet = LaxSlicedPacket::from_ether_type(etherparse::EtherType(protocol), inner);
+            packet.net.is_some().then_some(packet)
+        }
+    }
+}
+
+/// VXLAN (RFC 7348): an 8-byte header with the VNI flag set, then Ethernet
+fn vxlan_inner(data: &[u8]) -> Option<LaxSlicedPacket<'_>> {
+    if data.first()? & 0x08 == 0 {
+        return None;
+    }
+    LaxSlicedPacket::from_ethernet(data.get(8..)?).ok()
+}
+
+/// GTP-U version 1 G-PDUs (3GPP TS 29.281), which carry user IP packets
+fn gtpu_inner(data: &[u8]) -> Option<LaxSlicedPacket<'_>> {
+    const GTP_G_PDU: u8 = 0xff;
+    let flags = *data.first()?;
+    // Version 1, protocol type GTP (not GTP')
+    if flags & 0xf0 != 0x30 || *data.get(1)? != GTP_G_PDU {
+        return None;
+    }
+    let mut offset = 8;
+    // Any of the extension, sequence and N-PDU flags add 4 bytes, the last
+    // of which is the type of the first extension header
+    if flags & 0x07 != 0 {
+        let mut next_extension = *data.get(11)?;
+        offset = 12;
+        // Each extension header gives its length in 4-byte units and ends
+        // with the type of the next
+        while next_extension != 0 {
+            let len = *data.get(offset)? as usize * 4;
+            if len == 0 {
+                return None;
+            }
+            next_extension = *data.get(offset + len - 1)?;
+            offset += len;
+        }
+    }
+    LaxSlicedPacket::from_ip(data.get(offset..)?).ok()
+}
+
+/// Extract addresses, protocol, ports and TCP flags from a packet framed as
+/// `link`. With `open_tunnels`, these come from the innermost packet of a
+/// tunnel and the outermost tunnel is noted.
+fn parse_packet(link: LinkLayer, data: &[u8], open_tunnels: bool) -> Option<PacketInfo<'_>> {
+    let mut packet = link.slice(data)?;
+    let (src, dst) = ip_addrs(&packet)?;
+
+    let mut tunnel = None;
+    if open_tunnels {
+        for _ in 0..MAX_TUNNEL_DEPTH {
+            let Some((kind, inner)) = decapsulate(&packet) else {
+                break;
+            };
+            tunnel.get_or_insert(Tunnel { kind, src, dst });
+            packet = inner;
+        }
+    }
+
+    let (src, dst) = ip_addrs(&packet)?;
+    let protocol = match &packet.net {
+        Some(LaxNetSlice::Ipv4(ipv4)) => ipv4.payload().ip_number.0,
+        Some(LaxNetSlice::Ipv6(ipv6)) => ipv6.payload().ip_number.0,
+        None => return None,
+    };
+
+    let (src_port, dst_port, flags, payload) = match &packet.transport {
+        Some(etherparse::TransportSlice::Tcp(tcp)) => {
+            (tcp.source_port(), tcp.destination_port(), tcp_flags(tcp), tcp.payload())
+        }
+        Some(etherparse::TransportSlice::Udp(udp)) => {
+            (udp.source_port(), udp.destination_port(), 0, udp.payload())
+        }
+        _ => (0, 0, 0, &[][..]),
+    };
+
+    Some(PacketInfo {
+        src,
+        dst,
+        protocol,
+        src_port,
+        dst_port,
+        tcp_flags: flags,
+        payload,
+        tunnel,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 192.0.2.1:40000 → 198.51.100.7:443, TCP SYN
+    fn ipv4_tcp() -> Vec<u8> {
+        let mut packet = vec![
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00,
+            192, 0, 2, 1,
+            198, 51, 100, 7,
+        ];
+        packet.extend_from_slice(&[
+            0x9c, 0x40, 0x01, 0xbb, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0,
+        ]);
+        packet
+    }
+
+    /// [2001:db8::1]:5353 → [2001:db8::2]:53, UDP with no payload
+    fn ipv6_udp() -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0, 0x00, 0x08, 0x11, 0x40];
+        packet.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        packet.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        packet.extend_from_slice(&[0x14, 0xe9, 0x00, 0x35, 0x00, 0x08, 0, 0]);
+        packet
+    }
+
+    fn frame(header: &[u8], packet: &[u8]) -> Vec<u8> {
+        [header, packet].concat()
+    }
+
+    const MACS: [u8; 12] = [0x02, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 2];
+
+    fn assert_ipv4_tcp(link: LinkLayer, data: &[u8]) {
+        let info = parse_packet(link, data, false).unwrap_or_else(|| panic!("{:?} frame did not parse", link));
+        assert_eq!(info.src, "192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(info.dst, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(info.protocol, 6);
+        assert_eq!((info.src_port, info.dst_port), (40000, 443));
+        assert_eq!(info.tcp_flags, TCP_SYN);
+    }
+
+    fn assert_ipv6_udp(link: LinkLayer, data: &[u8]) {
+        let info = parse_packet(link, data, false).unwrap_or_else(|| panic!("{:?} frame did not parse", link));
+        assert_eq!(info.src, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(info.dst, "2001:db8::2".parse::<IpAddr>().unwrap());
+        assert_eq!(info.protocol, 17);
+        assert_eq!((info.src_port, info.dst_port), (5353, 53));
+    }
+
+    #[test]
+    fn link_types_map_to_link_layers() {
+        use pcap::Linktype;
+        let cases = [
+            (Linktype::ETHERNET, Some(LinkLayer::Ethernet)),
+            (Linktype(12), Some(LinkLayer::RawIp)),
+            (Linktype::RAW, Some(LinkLayer::RawIp)),
+            (Linktype::IPV6, Some(LinkLayer::RawIp)),
+            (Linktype::NULL, Some(LinkLayer::Loopback)),
+            (Linktype::LOOP, Some(LinkLayer::Loopback)),
+            (Linktype::LINUX_SLL, Some(LinkLayer::LinuxSll)),
+            (Linktype::LINUX_SLL2, Some(LinkLayer::LinuxSll2)),
+            (Linktype::IEEE802_11_RADIOTAP, None),
+        ];
+        for (linktype, expected) in cases {
+            assert_eq!(LinkLayer::from_linktype(linktype), expected, "{:?}", linktype);
+        }
+    }
+
+    #[test]
+    fn ethernet() {
+        let mut header = MACS.to_vec();
+        header.extend_from_slice(&[0x08, 0x00]);
+        assert_ipv4_tcp(LinkLayer::Ethernet, &frame(&header, &ipv4_tcp()));
+
+        let mut header = MACS.to_vec();
+        header.extend_from_slice(&[0x86, 0xdd]);
+        assert_ipv6_udp(LinkLayer::Ethernet, &frame(&header, &ipv6_udp()));
+    }
+
+    #[test]
+    fn ethernet_with_vlan_tags() {
+        // 802.1Q, VLAN 100
+        let mut header = MACS.to_vec();
+        header.extend_from_slice(&[0x81, 0x00, 0x00, 0x64, 0x08, 0x00]);
+        assert_ipv4_tcp(LinkLayer::Ethernet, &frame(&header, &ipv4_tcp()));
+
+        // 802.1ad QinQ, outer VLAN 10 and inner VLAN 100
+        let mut header = MACS.to_vec();
+        header.extend_from_slice(&[0x88, 0xa8, 0x00, 0x0a, 0x81, 0x00, 0x00, 0x64, 0x86, 0xdd]);
+        assert_ipv6_udp(LinkLayer::Ethernet, &frame(&header, &ipv6_udp()));
+    }
+
+    #[test]
+    fn raw_ip() {
+        assert_ipv4_tcp(LinkLayer::RawIp, &ipv4_tcp());
+        assert_ipv6_udp(LinkLayer::RawIp, &ipv6_udp());
+    }
+
+    #[test]
+    fn loopback_in_either_byte_order() {
+        // DLT_NULL from a little-endian host: AF_INET, and AF_INET6 as on macOS
+        assert_ipv4_tcp(LinkLayer::Loopback, &frame(&[2, 0, 0, 0], &ipv4_tcp()));
+        assert_ipv6_udp(LinkLayer::Loopback, &frame(&[30, 0, 0, 0], &ipv6_udp()));
+        // DLT_LOOP, always big-endian: AF_INET, and AF_INET6 as on OpenBSD
+        assert_ipv4_tcp(LinkLayer::Loopback, &frame(&[0, 0, 0, 2], &ipv4_tcp()));
+        assert_ipv6_udp(LinkLayer::Loopback, &frame(&[0, 0, 0, 24], &ipv6_udp()));
+        // Anything that isn't IP
+        assert!(parse_packet(LinkLayer::Loopback, &frame(&[7, 0, 0, 0], &ipv4_tcp()), false).is_none());
+    }
+
+    #[test]
+    fn linux_cooked_v1() {
+        // Sent by us, ARPHRD_ETHER, 6-byte address padded to 8, IPv4
+        let header = [0, 4, 0, 1, 0, 6, 0x02, 0, 0, 0, 0, 1, 0, 0, 0x08, 0x00];
+        assert_ipv4_tcp(LinkLayer::LinuxSll, &frame(&header, &ipv4_tcp()));
+
+        let header = [0, 0, 0, 1, 0, 6, 0x02, 0, 0, 0, 0, 2, 0, 0, 0x86, 0xdd];
+        assert_ipv6_udp(LinkLayer::LinuxSll, &frame(&header, &ipv6_udp()));
+    }
+
+    #[test]
+    fn linux_cooked_v2() {
+        // IPv6, reserved, ifindex 2, ARPHRD_ETHER, sent by us, 6-byte address padded to 8
+        let header = [
+            0x86, 0xdd, 0, 0, 0, 0, 0, 2, 0, 1, 4, 6, 0x02, 0, 0, 0, 0, 1, 0, 0,
+        ];
+        assert_ipv6_udp(LinkLayer::LinuxSll2, &frame(&header, &ipv6_udp()));
+
+        // ARPHRD_NONE, as on a tun device seen through `any`
+        let header = [0x08, 0x00, 0, 0, 0, 0, 0, 5, 0xff, 0xfe, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_ipv4_tcp(LinkLayer::LinuxSll2, &frame(&header, &ipv4_tcp()));
+
+        // VLAN 100 tag reinserted after the cooked header
+        let header = [
+            0x81, 0x00, 0, 0, 0, 0, 0, 2, 0, 1, 0, 6, 0x02, 0, 0, 0, 0, 2, 0, 0, 0x00, 0x64, 0x08, 0x00,
+        ];
+        assert_ipv4_tcp(LinkLayer::LinuxSll2, &frame(&header, &ipv4_tcp()));
+    }
+
+    #[test]
+    fn non_ip_and_truncated_frames_are_skipped() {
+        // ARP
+        let mut header = MACS.to_vec();
+        header.extend_from_slice(&[0x08, 0x06]);
+        assert!(parse_packet(LinkLayer::Ethernet, &frame(&header, &[0; 28]), false).is_none());
+
+        // An IPv4 packet read as Ethernet
+        assert!(parse_packet(LinkLayer::Ethernet, &ipv4_tcp(), false).is_none());
+
+        // Cut short inside the link or IP header
+        let links = [
+            LinkLayer::Ethernet,
+            LinkLayer::RawIp,
+            LinkLayer::Loopback,
+            LinkLayer::LinuxSll,
+            LinkLayer::LinuxSll2,
+        ];
+        for link in links {
+            assert!(parse_packet(link, &[], false).is_none(), "{:?}", link);
+            assert!(parse_packet(link, &[0x45, 0, 0, 0x28], false).is_none(), "{:?}", link);
+        }
+
+        // Cut off by the snaplen after the TCP header, as every packet longer
+        // than HEADER_SNAPLEN is, but still counted
+        let tcp = frame(&ipv4_tcp()[20..], &[0; 1400]);
+        let long = ipv4(IP_PROTO_TCP, [192, 0, 2, 1], [198, 51, 100, 7], &tcp);
+        let ethernet_header = [&MACS[..], &[0x08, 0x00]].concat();
+        let headers: [(LinkLayer, &[u8]); 5] = [
+            (LinkLayer::Ethernet, &ethernet_header),
+            (LinkLayer::RawIp, &[]),
+            (LinkLayer::Loopback, &[2, 0, 0, 0]),
+            (LinkLayer::LinuxSll, &[0, 4, 0, 1, 0, 6, 0x02, 0, 0, 0, 0, 1, 0, 0, 0x08, 0x00]),
+            (
+                LinkLayer::LinuxSll2,
+                &[0x08, 0x00, 0, 0, 0, 0, 0, 5, 0xff, 0xfe, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            ),
+        ];
+        for (link, header) in headers {
+            let mut data = frame(header, &long);
+            data.truncate(HEADER_SNAPLEN as usize);
+            assert_ipv4_tcp(link, &data);
+        }
+    }
+
+    /// Us and a VPN server, on the outside of tunnels
+    const US: [u8; 4] = [192, 168, 1, 20];
+    const VPN: [u8; 4] = [203, 0, 113, 10];
+
+    fn ipv4(protocol: u8, src: [u8; 4], dst: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let len = (20 + payload.len() as u16).to_be_bytes();
+        let mut packet = vec![0x45, 0, len[0], len[1], 0, 0, 0x40, 0, 0x40, protocol, 0, 0];
+        packet.extend_from_slice(&src);
+        packet.extend_from_slice(&dst);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn udp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = [src_port.to_be_bytes(), dst_port.to_be_bytes()].concat();
+        datagram.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    fn ether_frame(ether_type: [u8; 2], packet: &[u8]) -> Vec<u8> {
+        [&MACS[..], &ether_type, packet].concat()
+    }
+
+    /// An Ethernet frame from us to the VPN server
+    fn outer(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        ether_frame([0x08, 0x00], &ipv4(protocol, US, VPN, payload))
+    }
+
+    fn open(data: &[u8]) -> PacketInfo<'_> {
+        parse_packet(LinkLayer::Ethernet, data, true).expect("tunnel packet did not parse")
+    }
+
+    fn tunnel(kind: TunnelKind) -> Option<Tunnel> {
+        Some(Tunnel { kind, src: IpAddr::from(US), dst: IpAddr::from(VPN) })
+    }
+
+    #[test]
+    fn tunnels_stay_closed_unless_asked() {
+        let data = outer(IP_PROTO_GRE, &frame(&[0, 0, 0x08, 0x00], &ipv4_tcp()));
+        let info = parse_packet(LinkLayer::Ethernet, &data, false).unwrap();
+        assert_eq!((info.src, info.dst), (IpAddr::from(US), IpAddr::from(VPN)));
+        assert_eq!(info.protocol, IP_PROTO_GRE);
+        assert_eq!(info.tunnel, None);
+    }
+
+    #[test]
+    fn gre() {
+        let data = outer(IP_PROTO_GRE, &frame(&[0, 0, 0x08, 0x00], &ipv4_tcp()));
+        let info = open(&data);
+        assert_eq!(info.dst, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!((info.protocol, info.tcp_flags), (IP_PROTO_TCP, TCP_SYN));
+        assert_eq!(info.tunnel, tunnel(TunnelKind::Gre));
+
+        // Key and sequence number present, carrying IPv6
+        let header = [0x30, 0, 0x86, 0xdd, 0, 0, 0, 7, 0, 0, 0, 1];
+        let data = outer(IP_PROTO_GRE, &frame(&header, &ipv6_udp()));
+        assert_eq!(open(&data).dst, "2001:db8::2".parse::<IpAddr>().unwrap());
+        assert_eq!(open(&data).tunnel, tunnel(TunnelKind::Gre));
+
+        // Transparent Ethernet bridging
+        let inner = ether_frame([0x08, 0x00], &ipv4_tcp());
+        let data = outer(IP_PROTO_GRE, &frame(&[0, 0, 0x65, 0x58], &inner));
+        assert_eq!(open(&data).dst, "198.51.100.7".parse::<IpAddr>().unwrap());
+
+        // PPTP's enhanced GRE carries PPP, which is left alone
+        let data = outer(IP_PROTO_GRE, &frame(&[0x30, 0x81, 0x88, 0x0b], &[0; 8]));
+        assert_eq!(open(&data).dst, IpAddr::from(VPN));
+        assert_eq!(open(&data).tunnel, None);
+    }
+
+    #[test]
+    fn vxlan() {
+        let vxlan = frame(&[0x08, 0, 0, 0, 0, 0, 0x64, 0], &ether_frame([0x08, 0x00], &ipv4_tcp()));
+        let data = outer(IP_PROTO_UDP, &udp(50000, VXLAN_PORT, &vxlan));
+        let info = open(&data);
+        assert_eq!((info.src_port, info.dst_port), (40000, 443));
+        assert_eq!(info.dst, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(info.tunnel, tunnel(TunnelKind::Vxlan));
+
+        // Other traffic to the same port is not VXLAN without the VNI flag
+        let data = outer(IP_PROTO_UDP, &udp(50000, VXLAN_PORT, &[0; 16]));
+        assert_eq!(open(&data).tunnel, None);
+    }
+
+    #[test]
+    fn ip_in_ip_and_6in4() {
+        let data = outer(IP_PROTO_IPIP, &ipv4_tcp());
+        assert_eq!(open(&data).dst, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(open(&data).tunnel, tunnel(TunnelKind::IpIp));
+
+        let data = outer(IP_PROTO_IPV6, &ipv6_udp());
+        let info = open(&data);
+        assert_eq!(info.dst, "2001:db8::2".parse::<IpAddr>().unwrap());
+        assert_eq!((info.protocol, info.dst_port), (IP_PROTO_UDP, 53));
+        assert_eq!(info.tunnel, tunnel(TunnelKind::IpIp));
+    }
+
+    #[test]
+    fn gtp_u() {
+        let inner = ipv4_tcp();
+        let len = (inner.len() as u16).to_be_bytes();
+        let gtp = frame(&[0x30, 0xff, len[0], len[1], 0, 0, 0, 1], &inner);
+        let data = outer(IP_PROTO_UDP, &udp(GTPU_PORT, GTPU_PORT, &gtp));
+        assert_eq!(open(&data).dst, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(open(&data).tunnel, tunnel(TunnelKind::GtpU));
+
+        // Extension flag set, with one PDU session container before the packet
+        let inner = ipv6_udp();
+        let len = (inner.len() as u16 + 8).to_be_bytes();
+        let header = [0x34, 0xff, len[0], len[1], 0, 0, 0, 1, 0, 0, 0, 0x85, 1, 0x10, 0x09, 0];
+        let gtp = frame(&header, &inner);
+        let data = outer(IP_PROTO_UDP, &udp(GTPU_PORT, GTPU_PORT, &gtp));
+        assert_eq!(open(&data).dst, "2001:db8::2".parse::<IpAddr>().unwrap());
+
+        // Signalling messages carry no user packet
+        let echo = [0x32, 0x01, 0, 4, 0, 0, 0, 0, 0, 1, 0, 0];
+        let data = outer(IP_PROTO_UDP, &udp(GTPU_PORT, GTPU_PORT, &echo));
+        assert_eq!(open(&data).tunnel, None);
+    }
+
+    #[test]
+    fn nested_tunnels_keep_the_outermost() {
+        let gre = frame(&[0, 0, 0x08, 0x00], &ipv4_tcp());
+        let data = outer(IP_PROTO_IPIP, &ipv4(IP_PROTO_GRE, [10, 0, 0, 1], [10, 0, 0, 2], &gre));
+        let info = open(&data);
+        assert_eq!(info.dst, "198.51.100.7".parse::<IpAddr>().unwrap());
+        assert_eq!(info.tunnel, tunnel(TunnelKind::IpIp));
+    }
+
+    #[test]
+    fn packets_cut_short_by_the_snaplen_still_parse() {
+        let inner = frame(&ipv4_tcp(), &[0; 1400]);
+        let mut data = outer(IP_PROTO_GRE, &frame(&[0, 0, 0x08, 0x00], &inner));
+        data.truncate(HEADER_SNAPLEN as usize);
+        let info = open(&data);
+        assert_eq!((info.dst, info.dst_port), ("198.51.100.7".parse::<IpAddr>().unwrap(), 443));
+    }
+
+    #[test]
+    fn tunnelled_packets_take_their_direction_from_the_tunnel() {
+        let local_addrs = HashSet::from([IpAddr::from(US)]);
+        let exclusions = Exclusions::default();
+        let (lan_peers, decapsulate) = (AtomicBool::new(false), AtomicBool::new(true));
+        let scope = CaptureScope {
+            interface: None,
+            link: LinkLayer::Ethernet,
+            local_addrs: &local_addrs,
+            exclusions: &exclusions,
+            lan_peers: &lan_peers,
+            decapsulate: &decapsulate,
+        };
+
+        let reply = [
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00,
+            198, 51, 100, 7,
+            10, 8, 0, 2,
+            0x01, 0xbb, 0x9c, 0x40, 0, 0, 0, 1, 0, 0, 0, 2, 0x50, 0x12, 0xff, 0xff, 0, 0, 0, 0,
+        ];
+        let data = ether_frame([0x08, 0x00], &ipv4(IP_PROTO_IPIP, VPN, US, &reply));
+        let info = scope.parse(&data).unwrap();
+        let (remote, direction) = scope.remote(&info).unwrap();
+        assert_eq!((remote, direction), ("198.51.100.7".parse::<IpAddr>().unwrap(), Direction::Inbound));
+        assert_eq!(info.tunnel.unwrap().remote(direction), IpAddr::from(VPN));
+    }
+
+    /// A response to an A query for example.com with `rcode`, answering 93.184.216.34
+    fn dns_response(rcode: u8) -> Vec<u8> {
+        let mut msg = vec![0x12, 0x34, 0x81, 0x80 | rcode, 0, 1, 0, 1, 0, 0, 0, 0];
+        msg.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        msg.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0x01, 0x2c, 0, 4, 93, 184, 216, 34]);
+        msg
+    }
+
+    fn answers_from(src_port: u16, response: &[u8]) -> Vec<DnsAnswer> {
+        let packet = ipv4(IP_PROTO_UDP, [192, 0, 2, 53], [192, 0, 2, 1], &udp(src_port, 40000, response));
+        let info = parse_packet(LinkLayer::RawIp, &packet, false).unwrap();
+        dns_answers(&info, Utc::now())
+    }
+
+    #[test]
+    fn dns_answers_come_from_successful_responses_on_port_53() {
+        let answers = answers_from(DNS_PORT, &dns_response(dns::RCODE_NOERROR));
+        let answers: Vec<_> = answers.iter().map(|a| (a.domain.as_str(), a.ip.as_str(), a.ttl)).collect();
+        assert_eq!(answers, vec![("example.com", "93.184.216.34", 300)]);
+    }
+
+    #[test]
+    fn mdns_and_failed_responses_are_ignored() {
+        assert!(answers_from(5353, &dns_response(dns::RCODE_NOERROR)).is_empty());
+        assert!(answers_from(DNS_PORT, &dns_response(dns::RCODE_NXDOMAIN)).is_empty());
+        // SERVFAIL
+        assert!(answers_from(DNS_PORT, &dns_response(2)).is_empty());
+        assert!(answers_from(DNS_PORT, &dns_response(dns::RCODE_NOERROR)[..20]).is_empty());
+    }
+
+    #[test]
+    fn payload_filter_matches_every_http_method() {
+        for method in sni::HTTP_METHODS {
+            let word = u32::from_be_bytes(method[..4].try_into().unwrap());
+            assert!(
+                PAYLOAD_FILTER.contains(&format!(":4] = {:#010x}", word)),
+                "{} is not in the payload filter",
+                String::from_utf8_lossy(method)
+            );
+        }
+    }
+
+    #[test]
+    fn one_connection_through_two_tunnels_is_two_flows() {
+        let other_vpn = [203, 0, 113, 20];
+        let inner = ipv4_tcp();
+        let mut pending = TrafficAggregator::default();
+        for (outer_src, outer_dst, packets) in [(US, VPN, 2), (US, other_vpn, 1)] {
+            let data = ether_frame([0x08, 0x00], &ipv4(IP_PROTO_IPIP, outer_src, outer_dst, &inner));
+            let info = open(&data);
+            for _ in 0..packets {
+                pending.record(&info, info.dst, Direction::Outbound, 60, Utc::now());
+            }
+        }
+        let data = ether_frame([0x08, 0x00], &inner);
+        pending.record(&open(&data), "198.51.100.7".parse().unwrap(), Direction::Outbound, 60, Utc::now());
+
+        let mut flows: Vec<_> = pending
+            .take_batch()
+            .flows
+            .into_iter()
+            .map(|f| (f.tunnel_endpoint, f.packets))
+            .collect();
+        flows.sort();
+        assert_eq!(
+            flows,
+            vec![
+                (None, 1),
+                (Some("203.0.113.10".to_string()), 2),
+                (Some("203.0.113.20".to_string()), 1),
+            ]
+        );
     }
 }
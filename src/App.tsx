@@ -15,22 +15,37 @@ import { Header } from "./components/Header";
 import { FilterBar } from "./components/FilterBar";
 import { Settings } from "./components/Settings";
 import { useSettings } from "./hooks/useSettings";
-import type { IpConnection, CountryStats, AppStats, NewIpEvent } from "./lib/types";
+import type { IpConnection, CountryStats, AppStats, NewIpEvent, PermissionStatus, DatabaseInfo, ConnectionQuery, ConnectionPage, RetentionPolicy, MergeSummary, ExclusionList } from "./lib/types";
+
+// Search text that should be matched as an address block rather than a substring
+const CIDR_PATTERN = /^[0-9a-f.:]+\/\d{1,3}$/i;
 
 function App() {
   const [connections, setConnections] = useState<IpConnection[]>([]);
+  const [totalMatching, setTotalMatching] = useState(0);
   const [countryStats, setCountryStats] = useState<CountryStats[]>([]);
   const [stats, setStats] = useState<AppStats>({
     total_ips: 0,
     total_hits: 0,
+    total_packets: 0,
+    total_bytes: 0,
     total_countries: 0,
     uptime_seconds: 0,
     is_running: false,
+    interfaces: [],
   });
   const [isRunning, setIsRunning] = useState(false);
-  const [hasPermission, setHasPermission] = useState<boolean | null>(null);
+  const [permission, setPermission] = useState<PermissionStatus | null>(null);
+  const [permissionError, setPermissionError] = useState<string | null>(null);
   const [newIps, setNewIps] = useState<Set<string>>(new Set());
   const [showSettings, setShowSettings] = useState(false);
+  const [dbInfo, setDbInfo] = useState<DatabaseInfo | null>(null);
+  const [retention, setRetention] = useState<RetentionPolicy | null>(null);
+  const [exclusions, setExclusions] = useState<ExclusionList | null>(null);
+  const [captureError, setCaptureError] = useState<string | null>(null);
+  const [reverseDns, setReverseDns] = useState<boolean | null>(null);
+  const [recordLanPeers, setRecordLanPeers] = useState<boolean | null>(null);
+  const [decapsulateTunnels, setDecapsulateTunnels] = useState<boolean | null>(null);
   const pollRef = useRef<ReturnType<typeof setInterval> | null>(null);
 
   // ─── Settings ───
@@ -40,6 +55,7 @@ function App() {
   const [searchQuery, setSearchQuery] = useState("");
   const [selectedAsn, setSelectedAsn] = useState<string | null>(null);
   const [selectedCountry, setSelectedCountry] = useState<string | null>(null);
+  const [selectedInterface, setSelectedInterface] = useState<string | null>(null);
   const [timeRange, setTimeRange] = useState<{ start: string; end: string } | null>(null);
 
   // Track seen countries for notifications
@@ -102,16 +118,20 @@ function App() {
 
   // Check permissions on mount
   useEffect(() => {
-    invoke<boolean>("check_permissions").then(setHasPermission).catch(() => setHasPermission(false));
+    invoke<PermissionStatus>("check_permissions")
+      .then(setPermission)
+      .catch((e) => setPermissionError(String(e)));
   }, []);
 
   // Request permissions
   const requestPermissions = useCallback(async () => {
     try {
-      const result = await invoke<boolean>("request_permissions");
-      setHasPermission(result);
+      const result = await invoke<PermissionStatus>("request_permissions");
+      setPermission(result);
+      setPermissionError(null);
     } catch (e) {
       console.error("Permission request failed:", e);
+      setPermissionError(String(e));
     }
   }, []);
 
@@ -122,31 +142,65 @@ function App() {
         await invoke("stop_sniffing");
         setIsRunning(false);
       } else {
-        const iface = settings.defaultInterface || undefined;
-        await invoke("start_sniffing", { interface: iface });
-        setIsRunning(true);
+        // One capture per listed interface; none listed means the default one
+        const ifaces = settings.defaultInterface.split(",").map((s) => s.trim()).filter(Boolean);
+        const filter = settings.captureFilter.trim() || null;
+        const directory = settings.recordingDirectory.trim();
+        const recording = directory
+          ? { directory, full_payload: settings.recordFullPayload }
+          : null;
+        const results = await Promise.allSettled(
+          (ifaces.length ? ifaces : [undefined]).map((iface) =>
+            invoke("start_sniffing", { interface: iface, filter, recording })
+          )
+        );
+        const failed = results.find((r): r is PromiseRejectedResult => r.status === "rejected");
+        if (results.some((r) => r.status === "fulfilled")) setIsRunning(true);
+        if (failed) throw failed.reason;
       }
+      setCaptureError(null);
     } catch (e) {
       console.error("Toggle sniffing failed:", e);
+      setCaptureError(String(e));
     }
-  }, [isRunning, settings.defaultInterface]);
-
-  // Poll for updated data
+  }, [
+    isRunning,
+    settings.defaultInterface,
+    settings.captureFilter,
+    settings.recordingDirectory,
+    settings.recordFullPayload,
+  ]);
+
+  // Poll for updated data; filters are applied by the backend
   const refreshData = useCallback(async () => {
+    const query: ConnectionQuery = { limit: settings.maxConnections };
+    const search = searchQuery.trim();
+    if (CIDR_PATTERN.test(search)) {
+      query.ip = search;
+    } else if (search) {
+      query.search = search;
+    }
+    if (selectedAsn) query.asn = selectedAsn;
+    if (selectedCountry) query.country_code = selectedCountry;
+    if (selectedInterface) query.interface = selectedInterface;
+    if (timeRange?.start) query.since = new Date(timeRange.start).toISOString();
+    if (timeRange?.end) query.until = new Date(timeRange.end).toISOString();
+
     try {
-      const [conns, cStats, appStats] = await Promise.all([
-        invoke<IpConnection[]>("get_connections", { limit: settings.maxConnections }),
+      const [page, cStats, appStats] = await Promise.all([
+        invoke<ConnectionPage>("query_connections", { query }),
         invoke<CountryStats[]>("get_country_stats"),
         invoke<AppStats>("get_stats"),
       ]);
-      setConnections(conns);
+      setConnections(page.connections);
+      setTotalMatching(page.total);
       setCountryStats(cStats);
       setStats(appStats);
       setIsRunning(appStats.is_running);
     } catch (e) {
       console.error("Data refresh failed:", e);
     }
-  }, [settings.maxConnections]);
+  }, [settings.maxConnections, searchQuery, selectedAsn, selectedCountry, selectedInterface, timeRange]);
 
   // Set up polling with configurable interval
   useEffect(() => {
@@ -157,6 +211,116 @@ function App() {
     };
   }, [refreshData, settings.refreshInterval]);
 
+  // Open the database chosen in settings, unless --db or SNIFFF_DB_PATH pinned one
+  useEffect(() => {
+    const syncDatabase = async () => {
+      try {
+        let info = await invoke<DatabaseInfo>("get_database_info");
+        if (info.source !== "cli" && info.source !== "env") {
+          const wanted = settings.databasePath.trim();
+          if (wanted && wanted !== info.path) {
+            info = await invoke<DatabaseInfo>("open_database", { path: wanted });
+            refreshData();
+          } else if (!wanted && info.source === "runtime") {
+            info = await invoke<DatabaseInfo>("open_database", { path: null });
+            refreshData();
+          }
+        }
+        setDbInfo(info);
+      } catch (e) {
+        console.error("Database switch failed:", e);
+      }
+    };
+    syncDatabase();
+  }, [settings.databasePath, refreshData]);
+
+  const mergeDatabase = useCallback(
+    async (path: string, label: string | null) => {
+      const summary = await invoke<MergeSummary>("import_database", { path, label });
+      refreshData();
+      return summary;
+    },
+    [refreshData]
+  );
+
+  const applyExclusions = useCallback(
+    async (list: ExclusionList) => {
+      const applied = await invoke<ExclusionList>("set_capture_exclusions", { exclusions: list });
+      updateSettings({ exclusions: applied });
+      return applied;
+    },
+    [updateSettings]
+  );
+
+  // Push the retention policy from settings, or show the backend's own
+  useEffect(() => {
+    if (settings.retention) {
+      invoke("set_retention_policy", { policy: settings.retention })
+        .then(() => setRetention(settings.retention))
+        .catch((e) => console.error("Setting retention failed:", e));
+    } else {
+      invoke<RetentionPolicy>("get_retention_policy")
+        .then(setRetention)
+        .catch((e) => console.error("Reading retention failed:", e));
+    }
+  }, [settings.retention]);
+
+  // Same for reverse DNS
+  useEffect(() => {
+    if (settings.reverseDns !== null) {
+      invoke("set_reverse_dns_enabled", { enabled: settings.reverseDns })
+        .then(() => setReverseDns(settings.reverseDns))
+        .catch((e) => console.error("Setting reverse DNS failed:", e));
+    } else {
+      // The backend waits for this before looking up addresses from earlier sessions
+      invoke<boolean>("get_reverse_dns_enabled")
+        .then((enabled) => {
+          setReverseDns(enabled);
+          if (enabled) return invoke("set_reverse_dns_enabled", { enabled });
+        })
+        .catch((e) => console.error("Reading reverse DNS failed:", e));
+    }
+  }, [settings.reverseDns]);
+
+  // Same for LAN peer recording
+  useEffect(() => {
+    if (settings.recordLanPeers !== null) {
+      invoke("set_record_lan_peers", { enabled: settings.recordLanPeers })
+        .then(() => setRecordLanPeers(settings.recordLanPeers))
+        .catch((e) => console.error("Setting LAN peer recording failed:", e));
+    } else {
+      invoke<boolean>("get_record_lan_peers")
+        .then(setRecordLanPeers)
+        .catch((e) => console.error("Reading LAN peer recording failed:", e));
+    }
+  }, [settings.recordLanPeers]);
+
+  // Same for tunnel decapsulation
+  useEffect(() => {
+    if (settings.decapsulateTunnels !== null) {
+      invoke("set_decapsulate_tunnels", { enabled: settings.decapsulateTunnels })
+        .then(() => setDecapsulateTunnels(settings.decapsulateTunnels))
+        .catch((e) => console.error("Setting tunnel decapsulation failed:", e));
+    } else {
+      invoke<boolean>("get_decapsulate_tunnels")
+        .then(setDecapsulateTunnels)
+        .catch((e) => console.error("Reading tunnel decapsulation failed:", e));
+    }
+  }, [settings.decapsulateTunnels]);
+
+  // Same for capture exclusions
+  useEffect(() => {
+    if (settings.exclusions) {
+      invoke<ExclusionList>("set_capture_exclusions", { exclusions: settings.exclusions })
+        .then(setExclusions)
+        .catch((e) => console.error("Setting capture exclusions failed:", e));
+    } else {
+      invoke<ExclusionList>("get_capture_exclusions")
+        .then(setExclusions)
+        .catch((e) => console.error("Reading capture exclusions failed:", e));
+    }
+  }, [settings.exclusions]);
+
   // Listen for real-time new IP events + notifications
   useEffect(() => {
     const unlisten = listen<NewIpEvent>("new-ip", async (event) => {
@@ -198,71 +362,33 @@ function App() {
     };
   }, [refreshData, settings.notifyNewCountry]);
 
-  // ─── Apply Filters ───
-  const filteredConnections = useMemo(() => {
-    let result = connections;
-
-    if (searchQuery) {
-      const q = searchQuery.toLowerCase();
-      result = result.filter(
-        (c) =>
-          c.ip.toLowerCase().includes(q) ||
-          (c.as_name && c.as_name.toLowerCase().includes(q)) ||
-          (c.asn && c.asn.toLowerCase().includes(q)) ||
-          (c.country && c.country.toLowerCase().includes(q)) ||
-          (c.country_code && c.country_code.toLowerCase().includes(q))
-      );
-    }
-
-    if (selectedAsn) {
-      result = result.filter(
-        (c) => c.as_name === selectedAsn || c.asn === selectedAsn
-      );
-    }
-
-    if (selectedCountry) {
-      result = result.filter((c) => c.country_code === selectedCountry);
-    }
-
-    if (timeRange) {
-      const startTime = timeRange.start ? new Date(timeRange.start).getTime() : 0;
-      const endTime = timeRange.end ? new Date(timeRange.end).getTime() : Infinity;
-      result = result.filter((c) => {
-        const lastSeen = new Date(c.last_seen).getTime();
-        const firstSeen = new Date(c.first_seen).getTime();
-        return (
-          (lastSeen >= startTime || firstSeen >= startTime) &&
-          firstSeen <= endTime
-        );
-      });
-    }
-
-    return result;
-  }, [connections, searchQuery, selectedAsn, selectedCountry, timeRange]);
-
   const filteredCountryStats = useMemo(() => {
-    const hasFilters = searchQuery || selectedAsn || selectedCountry || timeRange;
+    const hasFilters = searchQuery || selectedAsn || selectedCountry || selectedInterface || timeRange;
     if (!hasFilters) return countryStats;
 
     const statsMap = new Map<string, CountryStats>();
-    filteredConnections.forEach((c) => {
+    connections.forEach((c) => {
       if (c.country_code && c.country) {
         const existing = statsMap.get(c.country_code);
         if (existing) {
           existing.hit_count += c.hit_count;
+          existing.packet_count += c.packet_count;
+          existing.byte_count += c.byte_count;
           existing.unique_ips += 1;
         } else {
           statsMap.set(c.country_code, {
             country_code: c.country_code,
             country: c.country,
             hit_count: c.hit_count,
+            packet_count: c.packet_count,
+            byte_count: c.byte_count,
             unique_ips: 1,
           });
         }
       }
     });
-    return Array.from(statsMap.values()).sort((a, b) => b.hit_count - a.hit_count);
-  }, [filteredConnections, countryStats, searchQuery, selectedAsn, selectedCountry, timeRange]);
+    return Array.from(statsMap.values()).sort((a, b) => b.packet_count - a.packet_count);
+  }, [connections, countryStats, searchQuery, selectedAsn, selectedCountry, selectedInterface, timeRange]);
 
   const handleCountryClick = useCallback(
     (countryCode: string) => {
@@ -272,24 +398,48 @@ function App() {
   );
 
   // Permission screen
-  if (hasPermission === false) {
+  if (permission && !permission.granted) {
+    const isMac = permission.platform === "macos";
     return (
       <div className="app-container">
         <div className="permission-screen">
           <div className="permission-icon">🔒</div>
-          <div className="permission-title">AUTHORIZATION REQUIRED</div>
+          <div className="permission-title">
+            {permission.restart_required ? "RESTART REQUIRED" : "AUTHORIZATION REQUIRED"}
+          </div>
           <div className="permission-desc">
             SNIFFF requires access to network interfaces for packet capture.
-            A one-time administrator authorization is needed to configure
-            Berkeley Packet Filter (BPF) device permissions.
+            {isMac
+              ? " A one-time administrator authorization is needed to configure Berkeley Packet Filter (BPF) device permissions."
+              : " The binary needs the cap_net_raw and cap_net_admin capabilities, or must run as root."}
           </div>
-          <button className="btn-tactical" onClick={requestPermissions}>
-            GRANT ACCESS
-          </button>
-          <div className="permission-desc" style={{ fontSize: '10px', opacity: 0.4 }}>
-            This installs a persistent LaunchDaemon — you will not be asked again.
-            <br />Touch ID works if enabled in System Settings → Touch ID & Password.
+          <div className="permission-desc" style={{ opacity: 0.7 }}>
+            {permission.detail}
           </div>
+          {permission.can_request && !permission.restart_required && (
+            <button className="btn-tactical" onClick={requestPermissions}>
+              GRANT ACCESS
+            </button>
+          )}
+          {permissionError && (
+            <div className="permission-desc" style={{ color: "var(--accent-danger)" }}>
+              {permissionError}
+            </div>
+          )}
+          {permission.instructions.length > 0 && (
+            <div className="permission-desc" style={{ fontSize: '10px', opacity: 0.6 }}>
+              {isMac ? "Or grant access manually:" : "Or grant access manually, then restart SNIFFF:"}
+              {permission.instructions.map((cmd) => (
+                <div key={cmd}><code>{cmd}</code></div>
+              ))}
+            </div>
+          )}
+          {isMac && (
+            <div className="permission-desc" style={{ fontSize: '10px', opacity: 0.4 }}>
+              This installs a persistent LaunchDaemon — you will not be asked again.
+              <br />Touch ID works if enabled in System Settings → Touch ID & Password.
+            </div>
+          )}
         </div>
       </div>
     );
@@ -300,6 +450,7 @@ function App() {
       <Header
         isRunning={isRunning}
         onToggle={toggleSniffing}
+        error={captureError}
         stats={stats}
         onSettingsClick={() => setShowSettings(true)}
       />
@@ -334,7 +485,7 @@ function App() {
           <StatsPanel
             stats={stats}
             countryStats={filteredCountryStats}
-            connections={filteredConnections}
+            connections={connections}
           />
           <FilterBar
             connections={connections}
@@ -344,10 +495,12 @@ function App() {
             onAsnChange={setSelectedAsn}
             selectedCountry={selectedCountry}
             onCountryChange={setSelectedCountry}
+            selectedInterface={selectedInterface}
+            onInterfaceChange={setSelectedInterface}
             timeRange={timeRange}
             onTimeRangeChange={setTimeRange}
           />
-          <ConnectionTable connections={filteredConnections} newIps={newIps} />
+          <ConnectionTable connections={connections} newIps={newIps} />
         </div>
       </div>
       <div className="footer">
@@ -357,12 +510,12 @@ function App() {
           <span>PCAP ENGINE</span>
         </div>
         <div className="footer-section">
-          {filteredConnections.length !== connections.length && (
+          {connections.length !== totalMatching && (
             <span className="footer-filter-info">
-              SHOWING {filteredConnections.length}/{connections.length}
+              SHOWING {connections.length}/{totalMatching}
             </span>
           )}
-          <span>DB: ~/Library/Application Support/com.snifff.app/snifff.db</span>
+          <span>DB: {dbInfo?.path ?? "…"}</span>
         </div>
       </div>
 
@@ -370,7 +523,15 @@ function App() {
       {showSettings && (
         <Settings
           settings={settings}
+          databaseInfo={dbInfo}
+          retention={retention}
+          reverseDns={reverseDns}
+          recordLanPeers={recordLanPeers}
+          decapsulateTunnels={decapsulateTunnels}
+          exclusions={exclusions}
           onUpdate={updateSettings}
+          onMergeDatabase={mergeDatabase}
+          onApplyExclusions={applyExclusions}
           onApplyPreset={applyPreset}
           onReset={resetSettings}
           onClose={() => setShowSettings(false)}
@@ -6,6 +6,10 @@ interface ConnectionTableProps {
     newIps: Set<string>;
 }
 
+/** The name the client asked for, else what DNS said the IP was */
+const displayName = (conn: IpConnection) =>
+    conn.server_names[0] || conn.domains[0] || conn.hostname;
+
 export const ConnectionTable: React.FC<ConnectionTableProps> = ({ connections, newIps }) => {
     const tableRef = useRef<HTMLDivElement>(null);
 
@@ -38,7 +42,7 @@ export const ConnectionTable: React.FC<ConnectionTableProps> = ({ connections, n
                             <th>IP ADDRESS</th>
                             <th>ORG</th>
                             <th>COUNTRY</th>
-                            <th style={{ textAlign: 'right' }}>HITS</th>
+                            <th style={{ textAlign: 'right' }}>PKTS</th>
                             <th>LAST</th>
                         </tr>
                     </thead>
@@ -48,16 +52,26 @@ export const ConnectionTable: React.FC<ConnectionTableProps> = ({ connections, n
                                 key={conn.ip}
                                 className={newIps.has(conn.ip) ? "new-row" : ""}
                             >
-                                <td className="ip-cell">{conn.ip}</td>
+                                <td
+                                    className="ip-cell"
+                                    title={[...new Set([...conn.server_names, ...conn.domains, conn.hostname])].filter(Boolean).join("\n")}
+                                >
+                                    {conn.ip}
+                                    {displayName(conn) && (
+                                        <span className="hostname">{displayName(conn)}</span>
+                                    )}
+                                </td>
                                 <td title={conn.as_name || ""}>
                                     {conn.as_name || conn.asn || "—"}
                                 </td>
                                 <td className="country-cell">
                                     {conn.country_code
                                         ? `${countryFlag(conn.country_code)} ${conn.country_code}`
-                                        : "···"}
+                                        : conn.ip_class && conn.ip_class !== "public"
+                                            ? conn.ip_class.replace("_", " ").toUpperCase()
+                                            : "···"}
                                 </td>
-                                <td className="hit-cell">{conn.hit_count}</td>
+                                <td className="hit-cell" title={`IN ${conn.packets_in} pkts / ${conn.bytes_in} B · OUT ${conn.packets_out} pkts / ${conn.bytes_out} B`}>{conn.packet_count}</td>
                                 <td>{formatTime(conn.last_seen)}</td>
                             </tr>
                         ))}
@@ -9,6 +9,8 @@ interface FilterBarProps {
     onAsnChange: (asn: string | null) => void;
     selectedCountry: string | null;
     onCountryChange: (code: string | null) => void;
+    selectedInterface: string | null;
+    onInterfaceChange: (iface: string | null) => void;
     timeRange: { start: string; end: string } | null;
     onTimeRangeChange: (range: { start: string; end: string } | null) => void;
 }
@@ -21,6 +23,8 @@ export const FilterBar = ({
     onAsnChange,
     selectedCountry,
     onCountryChange,
+    selectedInterface,
+    onInterfaceChange,
     timeRange,
     onTimeRangeChange,
 }: FilterBarProps) => {
@@ -57,12 +61,20 @@ export const FilterBar = ({
             .sort((a, b) => b.count - a.count);
     }, [connections]);
 
-    const hasActiveFilters = searchQuery || selectedAsn || selectedCountry || timeRange;
+    // Capture interfaces for dropdown
+    const uniqueInterfaces = useMemo(() => {
+        const ifaces = new Set<string>();
+        connections.forEach((c) => c.interfaces.forEach((i) => ifaces.add(i)));
+        return Array.from(ifaces).sort();
+    }, [connections]);
+
+    const hasActiveFilters = searchQuery || selectedAsn || selectedCountry || selectedInterface || timeRange;
 
     const clearAll = () => {
         onSearchChange("");
         onAsnChange(null);
         onCountryChange(null);
+        onInterfaceChange(null);
         onTimeRangeChange(null);
     };
 
@@ -74,7 +86,7 @@ export const FilterBar = ({
                     <input
                         type="text"
                         className="search-input"
-                        placeholder="FILTER: IP, ORG, COUNTRY..."
+                        placeholder="FILTER: IP, CIDR, ORG, COUNTRY..."
                         value={searchQuery}
                         onChange={(e) => onSearchChange(e.target.value)}
                     />
@@ -135,6 +147,23 @@ export const FilterBar = ({
                         </select>
                     </div>
 
+                    {/* Interface filter */}
+                    <div className="filter-group">
+                        <label className="filter-label">INTERFACE</label>
+                        <select
+                            className="filter-select"
+                            value={selectedInterface || ""}
+                            onChange={(e) => onInterfaceChange(e.target.value || null)}
+                        >
+                            <option value="">ALL INTERFACES</option>
+                            {uniqueInterfaces.map((iface) => (
+                                <option key={iface} value={iface}>
+                                    {iface}
+                                </option>
+                            ))}
+                        </select>
+                    </div>
+
                     {/* Time range */}
                     <div className="filter-group">
                         <label className="filter-label">TIME RANGE</label>
@@ -176,6 +205,11 @@ export const FilterBar = ({
                                     COUNTRY: {selectedCountry} ✕
                                 </span>
                             )}
+                            {selectedInterface && (
+                                <span className="filter-tag" onClick={() => onInterfaceChange(null)}>
+                                    INTERFACE: {selectedInterface} ✕
+                                </span>
+                            )}
                             {timeRange && (
                                 <span className="filter-tag" onClick={() => onTimeRangeChange(null)}>
                                     TIME RANGE ✕
@@ -5,11 +5,13 @@ import type { AppStats } from "../lib/types";
 interface HeaderProps {
     isRunning: boolean;
     onToggle: () => void;
+    /** Why the last start or stop failed, e.g. an invalid capture filter */
+    error?: string | null;
     stats: AppStats;
     onSettingsClick?: () => void;
 }
 
-export const Header = ({ isRunning, onToggle, stats, onSettingsClick }: HeaderProps) => {
+export const Header = ({ isRunning, onToggle, error, stats, onSettingsClick }: HeaderProps) => {
     const [uptime, setUptime] = useState("00:00:00");
     const appWindow = getCurrentWindow();
 
@@ -52,8 +54,17 @@ export const Header = ({ isRunning, onToggle, stats, onSettingsClick }: HeaderPr
                 <div className="status-indicator">
                     <span className={`status-dot ${isRunning ? "active" : ""}`} />
                     <span>{isRunning ? "ACTIVE" : "STANDBY"}</span>
+                    {isRunning && stats.interfaces.length > 0 && (
+                        <span className="status-interfaces">{stats.interfaces.join(" · ")}</span>
+                    )}
                 </div>
 
+                {error && (
+                    <span className="capture-error" title={error}>
+                        {error}
+                    </span>
+                )}
+
                 <button
                     className={`btn-tactical ${isRunning ? "active" : ""}`}
                     onClick={onToggle}
@@ -1,10 +1,19 @@
 import { useState } from "react";
 import type { AppSettings } from "../hooks/useSettings";
 import { THEME_PRESETS } from "../hooks/useSettings";
+import type { DatabaseInfo, ExclusionList, MergeSummary, RetentionPolicy } from "../lib/types";
 
 interface SettingsProps {
     settings: AppSettings;
+    databaseInfo: DatabaseInfo | null;
+    retention: RetentionPolicy | null;
+    reverseDns: boolean | null;
+    recordLanPeers: boolean | null;
+    decapsulateTunnels: boolean | null;
+    exclusions: ExclusionList | null;
     onUpdate: (partial: Partial<AppSettings>) => void;
+    onMergeDatabase: (path: string, label: string | null) => Promise<MergeSummary>;
+    onApplyExclusions: (list: ExclusionList) => Promise<ExclusionList>;
     onApplyPreset: (name: string) => void;
     onReset: () => void;
     onClose: () => void;
@@ -12,7 +21,15 @@ interface SettingsProps {
 
 export const Settings = ({
     settings,
+    databaseInfo,
+    retention,
+    reverseDns,
+    recordLanPeers,
+    decapsulateTunnels,
+    exclusions,
     onUpdate,
+    onMergeDatabase,
+    onApplyExclusions,
     onApplyPreset,
     onReset,
     onClose,
@@ -60,7 +77,18 @@ export const Settings = ({
                         <GeneralTab settings={settings} onUpdate={onUpdate} />
                     )}
                     {tab === "network" && (
-                        <NetworkTab settings={settings} onUpdate={onUpdate} />
+                        <NetworkTab
+                            settings={settings}
+                            databaseInfo={databaseInfo}
+                            retention={retention}
+                            reverseDns={reverseDns}
+                            recordLanPeers={recordLanPeers}
+                            decapsulateTunnels={decapsulateTunnels}
+                            exclusions={exclusions}
+                            onUpdate={onUpdate}
+                            onMergeDatabase={onMergeDatabase}
+                            onApplyExclusions={onApplyExclusions}
+                        />
                     )}
                 </div>
 
@@ -249,11 +277,79 @@ function GeneralTab({
 
 function NetworkTab({
     settings,
+    databaseInfo,
+    retention,
+    reverseDns,
+    recordLanPeers,
+    decapsulateTunnels,
+    exclusions,
     onUpdate,
+    onMergeDatabase,
+    onApplyExclusions,
 }: {
     settings: AppSettings;
+    databaseInfo: DatabaseInfo | null;
+    retention: RetentionPolicy | null;
+    reverseDns: boolean | null;
+    recordLanPeers: boolean | null;
+    decapsulateTunnels: boolean | null;
+    exclusions: ExclusionList | null;
     onUpdate: (p: Partial<AppSettings>) => void;
+    onMergeDatabase: (path: string, label: string | null) => Promise<MergeSummary>;
+    onApplyExclusions: (list: ExclusionList) => Promise<ExclusionList>;
 }) {
+    const [dbPath, setDbPath] = useState(settings.databasePath);
+    const [mergePath, setMergePath] = useState("");
+    const [mergeLabel, setMergeLabel] = useState("");
+    const [merging, setMerging] = useState(false);
+    const [mergeResult, setMergeResult] = useState<string | null>(null);
+    const activeExclusions = settings.exclusions ?? exclusions;
+    const [excludeNetworks, setExcludeNetworks] = useState(activeExclusions?.networks.join(", ") ?? "");
+    const [excludeAsns, setExcludeAsns] = useState(activeExclusions?.asns.join(", ") ?? "");
+    const [exclusionResult, setExclusionResult] = useState<string | null>(null);
+    const pinned = databaseInfo?.source === "cli" || databaseInfo?.source === "env";
+    const policy = settings.retention ?? retention;
+
+    const updateRetention = (key: keyof RetentionPolicy, raw: string) => {
+        const value = parseInt(raw, 10);
+        const base = policy ?? { max_age_days: null, max_rows: null, max_db_mb: null };
+        onUpdate({ retention: { ...base, [key]: value > 0 ? value : null } });
+    };
+
+    const mergeDatabase = async () => {
+        setMerging(true);
+        setMergeResult(null);
+        try {
+            const summary = await onMergeDatabase(mergePath.trim(), mergeLabel.trim() || null);
+            setMergeResult(
+                `Merged ${summary.connections} connections (${summary.new_connections} new) as "${summary.source_host}".`
+            );
+            setMergePath("");
+            setMergeLabel("");
+        } catch (e) {
+            setMergeResult(`Merge failed: ${e}`);
+        } finally {
+            setMerging(false);
+        }
+    };
+
+    const applyExclusions = async () => {
+        const split = (raw: string) => raw.split(",").map((s) => s.trim()).filter(Boolean);
+        try {
+            const applied = await onApplyExclusions({
+                networks: split(excludeNetworks),
+                asns: split(excludeAsns),
+            });
+            setExcludeNetworks(applied.networks.join(", "));
+            setExcludeAsns(applied.asns.join(", "));
+            setExclusionResult(
+                `Excluding ${applied.networks.length} networks and ${applied.asns.length} ASNs.`
+            );
+        } catch (e) {
+            setExclusionResult(`Not applied: ${e}`);
+        }
+    };
+
     return (
         <div className="settings-section">
             <div className="settings-section-title">CAPTURE</div>
@@ -263,13 +359,103 @@ function NetworkTab({
                     type="text"
                     className="search-input"
                     style={{ width: 180 }}
-                    placeholder="AUTO (e.g. en0)"
+                    placeholder="AUTO (e.g. en0, utun3)"
                     value={settings.defaultInterface}
                     onChange={(e) => onUpdate({ defaultInterface: e.target.value })}
                 />
             </div>
             <div className="settings-hint">
-                Leave blank for auto-detection. Common: en0 (Wi-Fi), en1 (Ethernet).
+                Leave blank for auto-detection. Common: en0 (Wi-Fi), en1 (Ethernet). List
+                several, comma-separated, to capture on all of them at once (e.g. Wi-Fi and a VPN tunnel).
+            </div>
+            <div className="settings-row">
+                <label className="settings-label">CAPTURE FILTER</label>
+                <input
+                    type="text"
+                    className="search-input"
+                    style={{ width: 180 }}
+                    placeholder="BPF (e.g. not port 22)"
+                    value={settings.captureFilter}
+                    onChange={(e) => onUpdate({ captureFilter: e.target.value })}
+                />
+            </div>
+            <div className="settings-hint">
+                tcpdump syntax, applied on top of the built-in IP filter. Takes effect the
+                next time capture starts; an invalid filter stops it from starting.
+            </div>
+            <div className="settings-row">
+                <label className="settings-label">RECORD TO</label>
+                <input
+                    type="text"
+                    className="search-input"
+                    style={{ width: 180 }}
+                    placeholder="OFF (directory for .pcapng)"
+                    value={settings.recordingDirectory}
+                    onChange={(e) => onUpdate({ recordingDirectory: e.target.value })}
+                />
+            </div>
+            <div className="settings-row">
+                <ToggleSwitch
+                    label="RECORD FULL PAYLOADS"
+                    checked={settings.recordFullPayload}
+                    onChange={(v) => onUpdate({ recordFullPayload: v })}
+                />
+            </div>
+            <div className="settings-hint">
+                Save captured packets to hourly or 100 MB pcapng files, keeping the last 24.
+                Only the first 128 bytes of each packet unless full payloads are on. Takes
+                effect the next time capture starts.
+            </div>
+            <div className="settings-row">
+                <ToggleSwitch
+                    label="RECORD LAN PEERS"
+                    checked={settings.recordLanPeers ?? recordLanPeers ?? false}
+                    onChange={(v) => onUpdate({ recordLanPeers: v })}
+                />
+            </div>
+            <div className="settings-hint">
+                Also record devices on private, CGNAT and link-local addresses. They are
+                never sent for geolocation.
+            </div>
+            <div className="settings-row">
+                <ToggleSwitch
+                    label="DECAPSULATE TUNNELS"
+                    checked={settings.decapsulateTunnels ?? decapsulateTunnels ?? false}
+                    onChange={(v) => onUpdate({ decapsulateTunnels: v })}
+                />
+            </div>
+            <div className="settings-hint">
+                Look inside GRE, VXLAN, IP-in-IP and GTP-U packets and map the hosts they
+                carry. The tunnel endpoint is kept on each flow instead.
+            </div>
+            <div className="settings-row">
+                <label className="settings-label">EXCLUDE NETWORKS</label>
+                <input
+                    type="text"
+                    className="search-input"
+                    style={{ width: 180 }}
+                    placeholder="203.0.113.7, 10.8.0.0/16"
+                    value={excludeNetworks}
+                    onChange={(e) => setExcludeNetworks(e.target.value)}
+                />
+            </div>
+            <div className="settings-row">
+                <label className="settings-label">EXCLUDE ASNS</label>
+                <input
+                    type="text"
+                    className="search-input"
+                    style={{ width: 180 }}
+                    placeholder="AS13335, AS15169"
+                    value={excludeAsns}
+                    onChange={(e) => setExcludeAsns(e.target.value)}
+                />
+                <button className="btn-tactical" onClick={applyExclusions}>
+                    APPLY
+                </button>
+            </div>
+            <div className="settings-hint">
+                {exclusionResult ??
+                    "Traffic to these never reaches the database. ASNs match once an address has been geolocated; its existing rows are removed then."}
             </div>
 
             <div className="settings-section-title" style={{ marginTop: 16 }}>
@@ -283,6 +469,17 @@ function NetworkTab({
                 Set IPINFO_TOKEN in ~/.env or the project .env file. Get a free token
                 at ipinfo.io.
             </div>
+            <div className="settings-row" style={{ marginTop: 8 }}>
+                <ToggleSwitch
+                    label="REVERSE DNS LOOKUPS"
+                    checked={settings.reverseDns ?? reverseDns ?? false}
+                    onChange={(v) => onUpdate({ reverseDns: v })}
+                />
+            </div>
+            <div className="settings-hint">
+                Resolve hostnames for new IPs through your DNS resolver. Turn off to keep
+                the addresses you talk to from being sent to it.
+            </div>
 
             <div className="settings-section-title" style={{ marginTop: 16 }}>
                 DATA
@@ -290,9 +487,109 @@ function NetworkTab({
             <div className="settings-row">
                 <label className="settings-label">DATABASE LOCATION</label>
                 <span className="settings-value-dim" style={{ fontSize: 9 }}>
-                    ~/Library/Application Support/com.snifff.app/snifff.db
+                    {databaseInfo?.path ?? "…"}
                 </span>
             </div>
+            <div className="settings-row">
+                <label className="settings-label">DATABASE FILE</label>
+                <input
+                    type="text"
+                    className="search-input"
+                    style={{ width: 180 }}
+                    placeholder="DEFAULT"
+                    value={dbPath}
+                    disabled={pinned}
+                    onChange={(e) => setDbPath(e.target.value)}
+                />
+                <button
+                    className="btn-tactical"
+                    disabled={pinned || dbPath === settings.databasePath}
+                    onClick={() => onUpdate({ databasePath: dbPath.trim() })}
+                >
+                    OPEN
+                </button>
+            </div>
+            <div className="settings-hint">
+                {pinned
+                    ? "Set by --db / --data-dir or SNIFFF_DB_PATH / SNIFFF_DATA_DIR at launch."
+                    : "Open a separate database per investigation. Created if missing; leave blank for the default."}
+            </div>
+            <div className="settings-row">
+                <label className="settings-label">MERGE FROM</label>
+                <input
+                    type="text"
+                    className="search-input"
+                    style={{ width: 180 }}
+                    placeholder="snifff.db OR .ndjson"
+                    value={mergePath}
+                    onChange={(e) => setMergePath(e.target.value)}
+                />
+            </div>
+            <div className="settings-row">
+                <label className="settings-label">SOURCE HOST</label>
+                <input
+                    type="text"
+                    className="search-input"
+                    style={{ width: 180 }}
+                    placeholder="FILE NAME"
+                    value={mergeLabel}
+                    onChange={(e) => setMergeLabel(e.target.value)}
+                />
+                <button
+                    className="btn-tactical"
+                    disabled={merging || !mergePath.trim()}
+                    onClick={mergeDatabase}
+                >
+                    {merging ? "MERGING…" : "MERGE"}
+                </button>
+            </div>
+            <div className="settings-hint">
+                {mergeResult ??
+                    "Combine a database or NDJSON export from another machine into this one. Hit counts are summed and rows are tagged with the source host."}
+            </div>
+
+            <div className="settings-section-title" style={{ marginTop: 16 }}>
+                RETENTION
+            </div>
+            <div className="settings-row">
+                <label className="settings-label">MAX AGE (DAYS)</label>
+                <input
+                    type="number"
+                    className="search-input"
+                    style={{ width: 100 }}
+                    min={0}
+                    placeholder="∞"
+                    value={policy?.max_age_days ?? ""}
+                    onChange={(e) => updateRetention("max_age_days", e.target.value)}
+                />
+            </div>
+            <div className="settings-row">
+                <label className="settings-label">MAX CONNECTIONS</label>
+                <input
+                    type="number"
+                    className="search-input"
+                    style={{ width: 100 }}
+                    min={0}
+                    placeholder="∞"
+                    value={policy?.max_rows ?? ""}
+                    onChange={(e) => updateRetention("max_rows", e.target.value)}
+                />
+            </div>
+            <div className="settings-row">
+                <label className="settings-label">MAX DB SIZE (MB)</label>
+                <input
+                    type="number"
+                    className="search-input"
+                    style={{ width: 100 }}
+                    min={0}
+                    placeholder="∞"
+                    value={policy?.max_db_mb ?? ""}
+                    onChange={(e) => updateRetention("max_db_mb", e.target.value)}
+                />
+            </div>
+            <div className="settings-hint">
+                Enforced hourly; the least recently seen connections go first. Leave blank for no limit.
+            </div>
         </div>
     );
 }
@@ -15,9 +15,9 @@ export const StatsPanel: React.FC<StatsPanelProps> = ({ stats, countryStats, con
         if (key) {
             const existing = asnMap.get(key);
             if (existing) {
-                existing.count += c.hit_count;
+                existing.count += c.packet_count;
             } else {
-                asnMap.set(key, { name: key, count: c.hit_count });
+                asnMap.set(key, { name: key, count: c.packet_count });
             }
         }
     });
@@ -37,8 +37,8 @@ export const StatsPanel: React.FC<StatsPanelProps> = ({ stats, countryStats, con
                     <span className="stat-value">{formatNum(stats.total_ips)}</span>
                 </div>
                 <div className="stat-box">
-                    <span className="stat-label">TOTAL HITS</span>
-                    <span className="stat-value">{formatNum(stats.total_hits)}</span>
+                    <span className="stat-label">PACKETS</span>
+                    <span className="stat-value">{formatNum(stats.total_packets)}</span>
                 </div>
                 <div className="stat-box">
                     <span className="stat-label">COUNTRIES</span>
@@ -68,7 +68,7 @@ export const StatsPanel: React.FC<StatsPanelProps> = ({ stats, countryStats, con
                             <span className="top-asn-name">
                                 {countryFlag(cs.country_code)} {cs.country}
                             </span>
-                            <span className="top-asn-count">{formatNum(cs.hit_count)}</span>
+                            <span className="top-asn-count">{formatNum(cs.packet_count)}</span>
                         </div>
                     ))}
                 </div>
@@ -19,7 +19,7 @@ export const WorldMapComponent = ({
 }: WorldMapComponentProps) => {
     const data = countryStats.map((cs) => ({
         country: cs.country_code.toLowerCase() as string,
-        value: cs.hit_count as number,
+        value: cs.packet_count as number,
     }));
 
     // ─── Zoom & Pan ───
@@ -1,4 +1,5 @@
 import { useState, useEffect, useCallback } from "react";
+import type { ExclusionList, RetentionPolicy } from "../lib/types";
 
 export interface ThemeColors {
     accentPrimary: string;
@@ -19,6 +20,15 @@ export interface AppSettings {
     showScanlines: boolean;
     sidePanelWidth: number;      // px
     notifyNewCountry: boolean;
+    databasePath: string;        // blank = default location
+    retention: RetentionPolicy | null;  // null = backend default / env
+    reverseDns: boolean | null;         // null = backend default / env
+    captureFilter: string;              // BPF expression, blank = everything
+    recordingDirectory: string;         // blank = don't record packets
+    recordFullPayload: boolean;
+    recordLanPeers: boolean | null;     // null = backend default / env
+    decapsulateTunnels: boolean | null; // null = backend default / env
+    exclusions: ExclusionList | null;   // null = backend default / env
 }
 
 const STORAGE_KEY = "snifff-settings";
@@ -102,6 +112,15 @@ const DEFAULT_SETTINGS: AppSettings = {
     showScanlines: true,
     sidePanelWidth: 420,
     notifyNewCountry: false,
+    databasePath: "",
+    retention: null,
+    reverseDns: null,
+    captureFilter: "",
+    recordingDirectory: "",
+    recordFullPayload: false,
+    recordLanPeers: null,
+    decapsulateTunnels: null,
+    exclusions: null,
 };
 
 function loadSettings(): AppSettings {
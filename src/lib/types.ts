@@ -8,23 +8,122 @@ export interface IpConnection {
   continent_code: string | null;
   continent: string | null;
   hit_count: number;
+  packet_count: number;
+  byte_count: number;
+  packets_in: number;
+  packets_out: number;
+  bytes_in: number;
+  bytes_out: number;
+  direction: "inbound" | "outbound" | null;
   first_seen: string;
   last_seen: string;
+  /** Comma-separated labels of the hosts this row was merged in from */
+  source_host: string | null;
+  /** Reverse DNS name, if one was found */
+  hostname: string | null;
+  /** What kind of address this is, e.g. "public", "private" or "cgnat" */
+  ip_class: string | null;
+  /** Names recently looked up that resolved to this IP, newest first */
+  domains: string[];
+  /** Names clients asked this IP for via TLS SNI, QUIC or HTTP Host, newest first */
+  server_names: string[];
+  /** Capture interfaces this IP was seen on, most recent first */
+  interfaces: string[];
+  top_ports: PortUsage[];
+}
+
+export interface PortUsage {
+  protocol: string;
+  port: number;
+  flow_count: number;
+  packet_count: number;
+  byte_count: number;
+}
+
+export interface FlowRecord {
+  src_ip: string;
+  src_port: number;
+  dst_ip: string;
+  dst_port: number;
+  protocol: string;
+  remote_ip: string;
+  direction: "inbound" | "outbound";
+  tcp_flags: number;
+  packet_count: number;
+  byte_count: number;
+  start_time: string;
+  end_time: string;
+  /** Server name the client asked for on this flow */
+  server_name: string | null;
+  /** Local process that owned the socket, where it could be found (Linux only) */
+  pid: number | null;
+  process_name: string | null;
+  exe_path: string | null;
+  /** Capture interface; null for imported files */
+  interface: string | null;
+  /** Tunnel the flow was decapsulated from, e.g. "gre" or "vxlan" */
+  tunnel: string | null;
+  /** Remote end of that tunnel, e.g. the VPN server */
+  tunnel_endpoint: string | null;
+}
+
+/** Remote addresses the sniffer drops before they reach the database */
+export interface ExclusionList {
+  /** Addresses or CIDR blocks */
+  networks: string[];
+  /** AS numbers, e.g. "AS13335" */
+  asns: string[];
+}
+
+export interface ProcessConnection {
+  ip: string;
+  country_code: string | null;
+  country: string | null;
+  as_name: string | null;
+  flow_count: number;
+  packet_count: number;
+  byte_count: number;
+  last_seen: string;
+}
+
+export interface ProcessUsage {
+  process_name: string;
+  exe_path: string | null;
+  pids: number[];
+  flow_count: number;
+  packet_count: number;
+  byte_count: number;
+  last_seen: string;
+  /** Busiest remote IPs first */
+  connections: ProcessConnection[];
 }
 
 export interface CountryStats {
   country_code: string;
   country: string;
   hit_count: number;
+  packet_count: number;
+  byte_count: number;
   unique_ips: number;
 }
 
 export interface AppStats {
   total_ips: number;
   total_hits: number;
+  total_packets: number;
+  total_bytes: number;
   total_countries: number;
   uptime_seconds: number;
   is_running: boolean;
+  /** Interfaces being captured on */
+  interfaces: string[];
+}
+
+export interface CaptureSession {
+  interface: string;
+  started_at: string;
+  filter: string | null;
+  recording: boolean;
 }
 
 export interface NewIpEvent {
@@ -34,3 +133,146 @@ export interface NewIpEvent {
   asn: string | null;
   as_name: string | null;
 }
+
+export interface ImportProgress {
+  path: string;
+  packets: number;
+  bytes_processed: number;
+  total_bytes: number;
+  done: boolean;
+}
+
+export interface ImportSummary {
+  path: string;
+  packets: number;
+  recorded_packets: number;
+  unique_ips: number;
+}
+
+export interface RecordingConfig {
+  directory: string;
+  full_payload?: boolean;
+  max_file_bytes?: number;
+  max_file_secs?: number;
+  max_files?: number;
+}
+
+export interface PermissionStatus {
+  platform: string;
+  granted: boolean;
+  method: string;
+  detail: string;
+  instructions: string[];
+  can_request: boolean;
+  restart_required: boolean;
+}
+
+export interface DatabaseInfo {
+  path: string;
+  source: "default" | "env" | "cli" | "runtime";
+}
+
+export interface TimeSeriesQuery {
+  start: string;
+  end: string;
+  group_by: "ip" | "asn" | "country";
+  keys?: string[];
+  step_secs?: number;
+  limit?: number;
+}
+
+export interface TimeSeriesPoint {
+  time: string;
+  packets_in: number;
+  packets_out: number;
+  bytes_in: number;
+  bytes_out: number;
+}
+
+export interface TimeSeries {
+  key: string;
+  label: string | null;
+  total_packets: number;
+  total_bytes: number;
+  points: TimeSeriesPoint[];
+}
+
+export interface TimeSeriesResult {
+  step_secs: number;
+  series: TimeSeries[];
+}
+
+export type ConnectionSort =
+  | "last_seen"
+  | "first_seen"
+  | "hit_count"
+  | "packet_count"
+  | "byte_count"
+  | "ip"
+  | "asn"
+  | "country";
+
+export interface ConnectionQuery {
+  search?: string;
+  ip?: string;
+  asn?: string;
+  country_code?: string;
+  continent_code?: string;
+  interface?: string;
+  since?: string;
+  until?: string;
+  min_hits?: number;
+  sort?: ConnectionSort;
+  order?: "asc" | "desc";
+  limit?: number;
+  offset?: number;
+  cursor?: string;
+}
+
+export interface ConnectionPage {
+  connections: IpConnection[];
+  total: number;
+  next_cursor: string | null;
+}
+
+export interface RetentionPolicy {
+  max_age_days: number | null;
+  max_rows: number | null;
+  max_db_mb: number | null;
+}
+
+export interface PurgeFilter {
+  since?: string;
+  until?: string;
+  ip?: string;
+  asn?: string;
+  country_code?: string;
+}
+
+export interface PurgeResult {
+  connections: number;
+  flows: number;
+  buckets: number;
+  bytes_reclaimed: number;
+}
+
+export interface ExportRequest {
+  path: string;
+  format: "csv" | "json" | "ndjson";
+  dataset: "connections" | "country_stats";
+  query?: ConnectionQuery;
+}
+
+export interface ExportSummary {
+  path: string;
+  rows: number;
+}
+
+export interface MergeSummary {
+  path: string;
+  source_host: string;
+  connections: number;
+  new_connections: number;
+  flows: number;
+  buckets: number;
+}